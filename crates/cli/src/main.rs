@@ -1,6 +1,168 @@
+use std::env;
+use std::fs;
 use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+const USAGE: &str = "usage: moye [ast [--json] [--optimized] FILE | check [--types] FILE... | fmt [--check] FILE... | disasm FILE \
+                     | compile FILE [-o OUT] | run FILE.moyec]";
+
+// Without arguments, starts the REPL.
 fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => return repl(),
+        ["ast", options @ .., path] if options.iter().all(|option| matches!(*option, "--json" | "--optimized")) => {
+            ast(path, options.contains(&"--json"), options.contains(&"--optimized"))
+        },
+        ["check", "--types", paths @ ..] if !paths.is_empty() => check(paths, true),
+        ["check", paths @ ..] if !paths.is_empty() => check(paths, false),
+        ["fmt", "--check", paths @ ..] if !paths.is_empty() => fmt(paths, true),
+        ["fmt", paths @ ..] if !paths.is_empty() => fmt(paths, false),
+        ["disasm", path] => disasm(path),
+        ["compile", path] => compile(path, &Path::new(path).with_extension("moyec")),
+        ["compile", path, "-o", out] => compile(path, Path::new(out)),
+        ["run", path] => run_compiled(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    };
+
+    if let Err(msg) = result {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Prints the syntax tree of a file, as JSON or in Rust's debug format, and
+// with every optimization applied if `optimized`.
+fn ast(path: &str, json: bool, optimized: bool) -> Result<(), String> {
+    let mut parse = parse_file(path)?;
+    if optimized {
+        parse.optimize(moye::Passes::default());
+    }
+    let output = if json {
+        serde_json::to_string_pretty(&parse).map_err(|error| error.to_string())?
+    } else {
+        format!("{:#?}", parse.statements())
+    };
+    writeln!(io::stdout(), "{}", output).map_err(|error| error.to_string())
+}
+
+// Reports mistakes in names, and in types if `types`, without running the
+// files. Only errors make the check fail; warnings are just printed.
+fn check(paths: &[&str], types: bool) -> Result<(), String> {
+    let mut failed = false;
+    for path in paths {
+        let diagnostics = read_file(path).and_then(|source| {
+            let env = moye::Env::default();
+            let mut diagnostics = moye::check(&source, &env).map_err(|error| file_parse_error(path, &error))?;
+            if types {
+                diagnostics.extend(moye::check_types(&source, &env).map_err(|error| file_parse_error(path, &error))?);
+                diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+            }
+            Ok(diagnostics)
+        });
+        match diagnostics {
+            Ok(diagnostics) => {
+                for diagnostic in diagnostics {
+                    failed |= diagnostic.severity == moye::Severity::Error;
+                    eprintln!("{}:{}", path, diagnostic);
+                }
+            },
+            Err(error) => {
+                failed = true;
+                eprintln!("{}", error);
+            },
+        }
+    }
+
+    if failed { Err("check failed".to_string()) } else { Ok(()) }
+}
+
+// Formats files in place. With `check`, reports the files that aren't
+// formatted instead.
+fn fmt(paths: &[&str], check: bool) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for path in paths {
+        let result = read_file(path).and_then(|source| {
+            let formatted = moye::format(&source).map_err(|error| file_parse_error(path, &error))?;
+            if formatted == source {
+                Ok(())
+            } else if check {
+                Err(format!("{}: not formatted", path))
+            } else {
+                fs::write(path, formatted).map_err(|error| format!("cannot write {}: {}", path, error))
+            }
+        });
+        errors.extend(result.err());
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors.join("\n")) }
+}
+
+// Prints the bytecode of a file, under the lines it was compiled from.
+fn disasm(path: &str) -> Result<(), String> {
+    let source = read_file(path)?;
+    let program = moye::compile(&source, &moye::Env::default()).map_err(|error| file_parse_error(path, &error))?;
+    write!(io::stdout(), "{}", program.disassemble(Some(&source))).map_err(|error| error.to_string())
+}
+
+// Compiles a file to the `.moyec` format, for `run`.
+fn compile(path: &str, out: &Path) -> Result<(), String> {
+    let source = read_file(path)?;
+    let program = moye::compile(&source, &moye::Env::default()).map_err(|error| file_parse_error(path, &error))?;
+    fs::write(out, program.to_bytes()).map_err(|error| format!("cannot write {}: {}", out.display(), error))
+}
+
+// Runs a compiled file and prints its value, like the REPL.
+fn run_compiled(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|error| format!("cannot read {}: {}", path, error))?;
+    let program = moye::Program::from_bytes(&bytes).map_err(|msg| format!("{}: {}", path, msg))?;
+    let val = program.run(&mut moye::Env::default()).map_err(|error| format!("Evaluation error: {}", error))?;
+    if val != moye::Val::Unit {
+        writeln!(io::stdout(), "{}", val).map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path, error))
+}
+
+fn parse_file(path: &str) -> Result<moye::Parse, String> {
+    moye::parse(&read_file(path)?).map_err(|error| file_parse_error(path, &error))
+}
+
+fn file_parse_error(path: &str, error: &moye::ParseError) -> String {
+    error.diagnostics
+        .iter()
+        .map(|diagnostic| format!("{}:{}", path, diagnostic))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_parse_error(error: &moye::ParseError) -> String {
+    error.diagnostics
+        .iter()
+        .map(|diagnostic| format!("Parse error at {}", diagnostic))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// At the line and column of the failing statement, when it is known.
+fn format_eval_error(input: &str, error: &moye::Error) -> String {
+    match error.diagnostic(input) {
+        Some(diagnostic) => format!("Evaluation error at {}", diagnostic),
+        None => format!("Evaluation error: {}", error),
+    }
+}
+
+fn repl() -> io::Result<()> {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
     let mut stderr = io::stderr();
@@ -8,12 +170,23 @@ fn main() -> io::Result<()> {
     let mut input = String::new();
     let mut env = moye::Env::default();
 
+    let cancel_token = moye::CancelToken::new();
+    let evaluating = Arc::new(AtomicBool::new(false));
+    install_interrupt_handler(cancel_token.clone(), Arc::clone(&evaluating));
+
     loop {
         write!(stdout, "> ")?;
         stdout.flush()?;
-        stdin.read_line(&mut input)?;
+        if stdin.read_line(&mut input)? == 0 {
+            return Ok(());
+        }
+
+        cancel_token.reset();
+        evaluating.store(true, Ordering::SeqCst);
+        let result = run(input.trim(), &mut env, &cancel_token);
+        evaluating.store(false, Ordering::SeqCst);
 
-        match run(input.trim(), &mut env) {
+        match result {
             Ok(Some(val)) => writeln!(stdout, "{}", val)?,
             Ok(None) => {},
             Err(msg) => writeln!(stderr, "{}", msg)?,
@@ -23,10 +196,25 @@ fn main() -> io::Result<()> {
     }
 }
 
-fn run(input: &str, env: &mut moye::Env) -> Result<Option<moye::Val>, String> {
-    let parse = moye::parse(input).map_err(|msg| format!("Parse error: {}", msg))?;
+// Ctrl-C stops the evaluation in progress; at the prompt it exits as usual.
+fn install_interrupt_handler(cancel_token: moye::CancelToken, evaluating: Arc<AtomicBool>) {
+    let result = ctrlc::set_handler(move || {
+        if evaluating.load(Ordering::SeqCst) {
+            cancel_token.cancel();
+        } else {
+            process::exit(130);
+        }
+    });
+
+    if let Err(err) = result {
+        eprintln!("could not install Ctrl-C handler: {}", err);
+    }
+}
+
+fn run(input: &str, env: &mut moye::Env, cancel_token: &moye::CancelToken) -> Result<Option<moye::Val>, String> {
+    let parse = moye::parse(input).map_err(|error| format_parse_error(&error))?;
 
-    let evaluated = parse.eval(env).map_err(|msg| format!("Evaluation error: {}", msg))?;
+    let evaluated = parse.eval_cancellable(env, cancel_token).map_err(|error| format_eval_error(input, &error))?;
 
     if evaluated == moye::Val::Unit {
         Ok(None)
@@ -0,0 +1,134 @@
+/*
+ * Type annotations, which say what type a binding, a parameter or the value
+ * of a function has
+ */
+
+use crate::diagnostic::SyntaxError;
+use crate::error::{Error, ErrorKind};
+use crate::lexer::{Span, Token, TokenKind};
+use crate::utils;
+use crate::val::Val;
+use std::fmt;
+
+/// A type that definitions can be annotated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    Unit,
+    List,
+    Map,
+}
+
+pub(crate) const TYPES: [Type; 7] = [Type::Int, Type::Float, Type::Bool, Type::String, Type::Unit, Type::List, Type::Map];
+
+impl Type {
+    /// The name annotations use.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Int => "Int",
+            Self::Float => "Float",
+            Self::Bool => "Bool",
+            Self::String => "String",
+            Self::Unit => "Unit",
+            Self::List => "List",
+            Self::Map => "Map",
+        }
+    }
+
+    /// Whether `val` has this type. Big ints are ints.
+    pub(crate) fn admits(self, val: &Val) -> bool {
+        matches!(
+            (self, val),
+            (Self::Int, Val::Number(_) | Val::BigInt(_))
+                | (Self::Float, Val::Float(_))
+                | (Self::Bool, Val::Bool(_))
+                | (Self::String, Val::Str(_))
+                | (Self::Unit, Val::Unit)
+                | (Self::List, Val::List(_))
+                | (Self::Map, Val::Map(_))
+        )
+    }
+
+    /// Fails unless `val` has this type, saying that `what` must have it.
+    pub(crate) fn enforce(self, val: &Val, what: impl FnOnce() -> String) -> Result<(), Error> {
+        if self.admits(val) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::Type, format!("{} must be {}, found {}", what(), self, val.type_name())))
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The type in `let name: Type = value`, `(param: Type)` or `-> Type`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeAnnotation {
+    pub ty: Type,
+    pub span: Span,
+}
+
+impl TypeAnnotation {
+    // Just the name of the type; the `:` or `->` before it belongs to the
+    // definition.
+    pub(crate) fn new(tokens: &[Token]) -> Result<(&[Token], Self), SyntaxError> {
+        let TokenKind::Ident(name) = &tokens[0].kind else {
+            return Err(SyntaxError::expected(["type"], &tokens[0]));
+        };
+        match TYPES.into_iter().find(|ty| ty.name() == name) {
+            Some(ty) => Ok((&tokens[1..], Self { ty, span: tokens[0].span })),
+            None => Err(SyntaxError::new(tokens[0].span, format!("unknown type ‘{}’", name))),
+        }
+    }
+
+    /// `marker Type`, if the tokens start with `marker`.
+    pub(crate) fn optional<'t>(
+        marker: &TokenKind,
+        tokens: &'t [Token],
+    ) -> Result<(&'t [Token], Option<Self>), SyntaxError> {
+        match utils::tag(marker, tokens) {
+            Ok(tokens) => Self::new(tokens).map(|(tokens, ty)| (tokens, Some(ty))),
+            Err(_) => Ok((tokens, None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::parse_str;
+
+    #[test]
+    fn parse_type_annotation() {
+        assert_eq!(
+            parse_str(TypeAnnotation::new, "Float x"),
+            Ok(("x", TypeAnnotation { ty: Type::Float, span: Span::default() })),
+        );
+    }
+
+    #[test]
+    fn cannot_parse_unknown_type() {
+        assert_eq!(parse_str(TypeAnnotation::new, "int"), Err("unknown type ‘int’".to_string()));
+        assert_eq!(parse_str(TypeAnnotation::new, "1"), Err("expected type, found ‘1’".to_string()));
+    }
+
+    #[test]
+    fn enforce_types_of_values() {
+        assert_eq!(Type::Int.enforce(&Val::BigInt(1.into()), || unreachable!()), Ok(()));
+        assert_eq!(Type::Unit.enforce(&Val::Unit, || unreachable!()), Ok(()));
+        assert_eq!(
+            Type::Float.enforce(&Val::Number(1), || "binding ‘x’".to_string()),
+            Err(Error::new(ErrorKind::Type, "binding ‘x’ must be Float, found int")),
+        );
+    }
+}
@@ -0,0 +1,669 @@
+/*
+ * Arithmetic on numbers. Integers are `i64` and are promoted to arbitrary
+ * precision integers whenever a result does not fit, so operations never
+ * overflow; results that fit into an `i64` again are demoted back.
+ *
+ * If either operand is a float, the other one is converted to a float and the
+ * result is a float too. Only integer division truncates. Floats are always
+ * finite: results that would be infinite or NaN are errors, like division by
+ * zero.
+ */
+
+use crate::error::{Error, ErrorKind};
+use crate::expr::{Operations, UnaryOperations};
+use crate::val::Val;
+use num_bigint::{BigInt, Sign};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::cmp::Ordering;
+
+/// Integers that would need more bits than this are rejected instead of
+/// computed, so that a single `**` or `<<` cannot exhaust memory.
+const MAX_BITS: u64 = 1 << 24;
+
+pub(crate) fn normalize(n: BigInt) -> Val {
+    match i64::try_from(&n) {
+        Ok(small) => Val::Number(small),
+        Err(_) => Val::BigInt(n),
+    }
+}
+
+pub(crate) fn binary(op: &Operations, lhs: Val, rhs: Val) -> Result<Val, Error> {
+    if op.is_bitwise() {
+        return bitwise(op, lhs, rhs);
+    }
+    if op.is_comparison() {
+        return compare(op, lhs, rhs);
+    }
+
+    match (lhs, rhs) {
+        (Val::Number(lhs), Val::Number(rhs)) => match small(op, lhs, rhs)? {
+            Some(res) => Ok(Val::Number(res)),
+            None => big(op, BigInt::from(lhs), BigInt::from(rhs)),
+        },
+        (lhs @ Val::Float(_), rhs) | (lhs, rhs @ Val::Float(_)) => match (to_float(&lhs), to_float(&rhs)) {
+            (Some(lhs), Some(rhs)) => float(op, lhs, rhs),
+            _ => Err(not_numbers()),
+        },
+        (lhs, rhs) => match (into_big(lhs), into_big(rhs)) {
+            (Some(lhs), Some(rhs)) => big(op, lhs, rhs),
+            _ => Err(not_numbers()),
+        },
+    }
+}
+
+pub(crate) fn unary(op: &UnaryOperations, val: Val) -> Result<Val, Error> {
+    match (op, val) {
+        (UnaryOperations::Neg, Val::Number(n)) => Ok(n
+            .checked_neg()
+            .map(Val::Number)
+            .unwrap_or_else(|| Val::BigInt(-BigInt::from(n)))),
+        (UnaryOperations::Neg, Val::BigInt(n)) => Ok(normalize(-n)),
+        (UnaryOperations::Neg, Val::Float(f)) => Ok(Val::Float(-f)),
+        (UnaryOperations::Plus, val @ (Val::Number(_) | Val::BigInt(_) | Val::Float(_))) => Ok(val),
+        (UnaryOperations::Not, Val::Number(n)) => Ok(Val::Number(!n)),
+        (UnaryOperations::Not, Val::BigInt(n)) => Ok(normalize(!n)),
+        (UnaryOperations::Not, Val::Bool(b)) => Ok(Val::Bool(!b)),
+        (op, val) => Err(Error::new(
+            ErrorKind::Type,
+            format!("cannot apply unary ‘{}’ to {}", op.symbol(), val.type_name()),
+        )),
+    }
+}
+
+pub(crate) fn to_float(val: &Val) -> Option<f64> {
+    match val {
+        Val::Number(n) => Some(*n as f64),
+        Val::BigInt(n) => n.to_f64(),
+        Val::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+// Truncates towards zero, like `as` casts.
+pub(crate) fn float_to_int(f: f64) -> Result<Val, Error> {
+    if !f.is_finite() {
+        return Err(Error::new(ErrorKind::InvalidOperand, format!("cannot convert {:?} to an int", f)));
+    }
+    let truncated = f.trunc();
+    if truncated >= i64::MIN as f64 && truncated < i64::MAX as f64 {
+        Ok(Val::Number(truncated as i64))
+    } else {
+        // Finite floats always convert.
+        Ok(normalize(BigInt::from_f64(truncated).unwrap()))
+    }
+}
+
+fn not_numbers() -> Error {
+    Error::new(
+        ErrorKind::Type,
+        "cannot evaluate operation whose left-hand side and right-hand side are not both numbers",
+    )
+}
+
+fn division_by_zero() -> Error {
+    Error::new(ErrorKind::DivisionByZero, "division by zero")
+}
+
+fn into_big(val: Val) -> Option<BigInt> {
+    match val {
+        Val::Number(n) => Some(BigInt::from(n)),
+        Val::BigInt(n) => Some(n),
+        _ => None,
+    }
+}
+
+// `Ok(None)` means the result does not fit into an `i64`.
+fn small(op: &Operations, lhs: i64, rhs: i64) -> Result<Option<i64>, Error> {
+    Ok(match op {
+        Operations::Add => lhs.checked_add(rhs),
+        Operations::Sub => lhs.checked_sub(rhs),
+        Operations::Mul => lhs.checked_mul(rhs),
+        Operations::Div => checked_div(lhs, rhs)?,
+        Operations::Rem => checked_rem(lhs, rhs)?,
+        Operations::Pow => {
+            if rhs < 0 {
+                return Err(negative_exponent(rhs));
+            }
+            u32::try_from(rhs).ok().and_then(|exponent| lhs.checked_pow(exponent))
+        },
+        _ => unreachable!("bitwise operations and comparisons are evaluated separately"),
+    })
+}
+
+fn big(op: &Operations, lhs: BigInt, rhs: BigInt) -> Result<Val, Error> {
+    let res = match op {
+        Operations::Add => lhs + rhs,
+        Operations::Sub => lhs - rhs,
+        Operations::Mul => lhs * rhs,
+        Operations::Div => {
+            if rhs == BigInt::ZERO {
+                return Err(division_by_zero());
+            }
+            lhs / rhs
+        },
+        Operations::Rem => {
+            if rhs == BigInt::ZERO {
+                return Err(division_by_zero());
+            }
+            lhs % rhs
+        },
+        Operations::Pow => big_pow(lhs, rhs)?,
+        _ => unreachable!("bitwise operations and comparisons are evaluated separately"),
+    };
+    Ok(normalize(res))
+}
+
+fn big_pow(base: BigInt, exponent: BigInt) -> Result<BigInt, Error> {
+    if exponent < BigInt::ZERO {
+        return Err(negative_exponent(exponent));
+    }
+
+    // These never grow, however large the exponent is.
+    let one = BigInt::from(1);
+    if base == BigInt::ZERO || base == one {
+        return Ok(if exponent == BigInt::ZERO { one } else { base });
+    }
+    if base == -one.clone() {
+        return Ok(if exponent.bit(0) { base } else { one });
+    }
+
+    match u32::try_from(&exponent) {
+        Ok(exponent) if base.bits().saturating_mul(u64::from(exponent)) <= MAX_BITS => Ok(base.pow(exponent)),
+        _ => Err(too_large(&Operations::Pow)),
+    }
+}
+
+fn float(op: &Operations, lhs: f64, rhs: f64) -> Result<Val, Error> {
+    let res = match op {
+        Operations::Add => lhs + rhs,
+        Operations::Sub => lhs - rhs,
+        Operations::Mul => lhs * rhs,
+        Operations::Div => {
+            if rhs == 0.0 {
+                return Err(division_by_zero());
+            }
+            lhs / rhs
+        },
+        Operations::Rem => {
+            if rhs == 0.0 {
+                return Err(division_by_zero());
+            }
+            lhs % rhs
+        },
+        Operations::Pow => lhs.powf(rhs),
+        _ => unreachable!("bitwise operations and comparisons are evaluated separately"),
+    };
+    if !res.is_finite() {
+        return Err(Error::new(ErrorKind::Overflow, format!("result of ‘{}’ is not a finite float", op.symbol())));
+    }
+    Ok(Val::Float(res))
+}
+
+// Numbers compare by value, whatever their representation, and NaN is neither
+// equal to nor ordered with anything. Other values can only be compared for
+// equality.
+fn compare(op: &Operations, lhs: Val, rhs: Val) -> Result<Val, Error> {
+    let ordering = match (lhs, rhs) {
+        (Val::Number(lhs), Val::Number(rhs)) => Some(lhs.cmp(&rhs)),
+        (lhs @ Val::Float(_), rhs) | (lhs, rhs @ Val::Float(_)) if is_number(&lhs) && is_number(&rhs) => {
+            to_float(&lhs).zip(to_float(&rhs)).and_then(|(lhs, rhs)| lhs.partial_cmp(&rhs))
+        },
+        (lhs, rhs) if is_number(&lhs) && is_number(&rhs) => Some(into_big(lhs).cmp(&into_big(rhs))),
+        (lhs, rhs) => {
+            return match op {
+                Operations::Eq => Ok(Val::Bool(lhs == rhs)),
+                Operations::Ne => Ok(Val::Bool(lhs != rhs)),
+                _ => Err(Error::new(
+                    ErrorKind::Type,
+                    format!("cannot compare {} with {}", lhs.type_name(), rhs.type_name()),
+                )),
+            };
+        },
+    };
+
+    let res = match op {
+        Operations::Eq => ordering == Some(Ordering::Equal),
+        Operations::Ne => ordering != Some(Ordering::Equal),
+        Operations::Lt => ordering == Some(Ordering::Less),
+        Operations::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        Operations::Gt => ordering == Some(Ordering::Greater),
+        Operations::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        _ => unreachable!("only comparisons are evaluated here"),
+    };
+    Ok(Val::Bool(res))
+}
+
+fn is_number(val: &Val) -> bool {
+    matches!(val, Val::Number(_) | Val::BigInt(_) | Val::Float(_))
+}
+
+fn checked_div(lhs: i64, rhs: i64) -> Result<Option<i64>, Error> {
+    if rhs == 0 {
+        Err(division_by_zero())
+    } else {
+        Ok(lhs.checked_div(rhs))
+    }
+}
+
+fn checked_rem(lhs: i64, rhs: i64) -> Result<Option<i64>, Error> {
+    if rhs == 0 {
+        Err(division_by_zero())
+    } else {
+        Ok(lhs.checked_rem(rhs))
+    }
+}
+
+/*
+ * Bitwise operators work on ints as if they were in two's complement with
+ * infinitely many sign bits, and as non-short-circuiting logical operators on
+ * bools. Shifts are arithmetic and only defined for ints.
+ */
+fn bitwise(op: &Operations, lhs: Val, rhs: Val) -> Result<Val, Error> {
+    match (lhs, rhs) {
+        (Val::Bool(lhs), Val::Bool(rhs)) if !op.is_shift() => Ok(Val::Bool(match op {
+            Operations::BitAnd => lhs & rhs,
+            Operations::BitOr => lhs | rhs,
+            _ => lhs ^ rhs,
+        })),
+        (Val::Number(lhs), Val::Number(rhs)) if !op.is_shift() => Ok(Val::Number(match op {
+            Operations::BitAnd => lhs & rhs,
+            Operations::BitOr => lhs | rhs,
+            _ => lhs ^ rhs,
+        })),
+        (lhs @ (Val::Number(_) | Val::BigInt(_)), Val::Number(amount)) if op.is_shift() => shift(op, lhs, amount),
+        // An amount too large for an int shifts every bit out to the right,
+        // and more bits in than any result may have to the left.
+        (lhs @ (Val::Number(_) | Val::BigInt(_)), Val::BigInt(amount)) if op.is_shift() => {
+            if amount.sign() == Sign::Minus {
+                Err(negative_shift(amount))
+            } else if matches!(op, Operations::Shr) {
+                shift(op, lhs, i64::MAX)
+            } else {
+                Err(too_large(op))
+            }
+        },
+        (lhs, rhs) => {
+            let types = (lhs.type_name(), rhs.type_name());
+            match (into_big(lhs), into_big(rhs)) {
+                (Some(lhs), Some(rhs)) if !op.is_shift() => Ok(normalize(match op {
+                    Operations::BitAnd => lhs & rhs,
+                    Operations::BitOr => lhs | rhs,
+                    _ => lhs ^ rhs,
+                })),
+                _ => Err(Error::new(
+                    ErrorKind::Type,
+                    format!("cannot evaluate ‘{}’ on {} and {}", op.symbol(), types.0, types.1),
+                )),
+            }
+        },
+    }
+}
+
+fn shift(op: &Operations, lhs: Val, amount: i64) -> Result<Val, Error> {
+    if amount < 0 {
+        return Err(negative_shift(amount));
+    }
+
+    match (op, lhs) {
+        (Operations::Shl, Val::Number(lhs)) if amount < 64 && (lhs << amount) >> amount == lhs => {
+            Ok(Val::Number(lhs << amount))
+        },
+        (Operations::Shr, Val::Number(lhs)) => Ok(Val::Number(lhs >> amount.min(63))),
+        (op, lhs) => {
+            let lhs = into_big(lhs).unwrap();
+            if matches!(op, Operations::Shr) {
+                return Ok(normalize(lhs >> amount));
+            }
+            if lhs.bits().saturating_add(amount as u64) > MAX_BITS {
+                return Err(too_large(op));
+            }
+            Ok(normalize(lhs << amount))
+        },
+    }
+}
+
+fn negative_exponent(exponent: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidOperand, format!("cannot raise an int to negative power {}", exponent))
+}
+
+fn negative_shift(amount: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidOperand, format!("cannot shift by negative amount {}", amount))
+}
+
+fn too_large(op: &Operations) -> Error {
+    Error::new(ErrorKind::Overflow, format!("result of ‘{}’ would have more than {} bits", op.symbol(), MAX_BITS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big_val(s: &str) -> Val {
+        Val::BigInt(s.parse().unwrap())
+    }
+
+    #[test]
+    fn add_small_numbers() {
+        assert_eq!(binary(&Operations::Add, Val::Number(2), Val::Number(3)), Ok(Val::Number(5)));
+    }
+
+    #[test]
+    fn promote_on_addition_overflow() {
+        assert_eq!(
+            binary(&Operations::Add, Val::Number(i64::MAX), Val::Number(1)),
+            Ok(big_val("9223372036854775808")),
+        );
+    }
+
+    #[test]
+    fn promote_on_multiplication_overflow() {
+        assert_eq!(
+            binary(&Operations::Mul, Val::Number(i64::MAX), Val::Number(i64::MAX)),
+            Ok(big_val("85070591730234615847396907784232501249")),
+        );
+    }
+
+    #[test]
+    fn promote_on_division_overflow() {
+        assert_eq!(
+            binary(&Operations::Div, Val::Number(i64::MIN), Val::Number(-1)),
+            Ok(big_val("9223372036854775808")),
+        );
+    }
+
+    #[test]
+    fn demote_when_result_fits_again() {
+        assert_eq!(
+            binary(&Operations::Sub, big_val("9223372036854775808"), Val::Number(1)),
+            Ok(Val::Number(i64::MAX)),
+        );
+    }
+
+    #[test]
+    fn divide_big_numbers() {
+        assert_eq!(
+            binary(&Operations::Div, big_val("-100000000000000000000"), Val::Number(7)),
+            Ok(big_val("-14285714285714285714")),
+        );
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        assert_eq!(
+            binary(&Operations::Div, Val::Number(1), Val::Number(0)),
+            Err(Error::new(ErrorKind::DivisionByZero, "division by zero")),
+        );
+        assert_eq!(
+            binary(&Operations::Div, big_val("100000000000000000000"), Val::Number(0)),
+            Err(Error::new(ErrorKind::DivisionByZero, "division by zero")),
+        );
+    }
+
+    #[test]
+    fn divide_floats() {
+        assert_eq!(binary(&Operations::Div, Val::Float(1.0), Val::Float(4.0)), Ok(Val::Float(0.25)));
+    }
+
+    #[test]
+    fn mix_ints_and_floats() {
+        assert_eq!(binary(&Operations::Add, Val::Number(1), Val::Float(0.5)), Ok(Val::Float(1.5)));
+        assert_eq!(binary(&Operations::Mul, Val::Float(0.5), Val::Number(3)), Ok(Val::Float(1.5)));
+        assert_eq!(
+            binary(&Operations::Sub, big_val("100000000000000000000"), Val::Float(1e20)),
+            Ok(Val::Float(0.0)),
+        );
+    }
+
+    #[test]
+    fn divide_float_by_zero() {
+        assert_eq!(
+            binary(&Operations::Div, Val::Float(1.0), Val::Number(0)),
+            Err(Error::new(ErrorKind::DivisionByZero, "division by zero")),
+        );
+    }
+
+    #[test]
+    fn reject_non_finite_float_results() {
+        assert_eq!(
+            binary(&Operations::Mul, Val::Float(1e308), Val::Number(10)),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘*’ is not a finite float")),
+        );
+        assert_eq!(
+            binary(&Operations::Pow, Val::Float(-8.0), Val::Float(0.5)),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘**’ is not a finite float")),
+        );
+        assert_eq!(
+            binary(&Operations::Add, Val::Float(1.0), Val::BigInt(BigInt::from(1) << 1100)),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘+’ is not a finite float")),
+        );
+    }
+
+    #[test]
+    fn convert_float_to_int() {
+        assert_eq!(float_to_int(-2.9), Ok(Val::Number(-2)));
+        assert_eq!(float_to_int(1e20), Ok(big_val("100000000000000000000")));
+        assert_eq!(float_to_int(f64::NAN), Err(Error::new(ErrorKind::InvalidOperand, "cannot convert NaN to an int")));
+    }
+
+    #[test]
+    fn remainder_has_sign_of_dividend() {
+        assert_eq!(binary(&Operations::Rem, Val::Number(-7), Val::Number(3)), Ok(Val::Number(-1)));
+        assert_eq!(binary(&Operations::Rem, Val::Number(i64::MIN), Val::Number(-1)), Ok(Val::Number(0)));
+        assert_eq!(binary(&Operations::Rem, Val::Float(7.5), Val::Number(2)), Ok(Val::Float(1.5)));
+        assert_eq!(
+            binary(&Operations::Rem, big_val("100000000000000000001"), Val::Number(10)),
+            Ok(Val::Number(1)),
+        );
+    }
+
+    #[test]
+    fn remainder_by_zero() {
+        assert_eq!(
+            binary(&Operations::Rem, Val::Number(1), Val::Number(0)),
+            Err(Error::new(ErrorKind::DivisionByZero, "division by zero")),
+        );
+    }
+
+    #[test]
+    fn power() {
+        assert_eq!(binary(&Operations::Pow, Val::Number(3), Val::Number(4)), Ok(Val::Number(81)));
+        assert_eq!(binary(&Operations::Pow, Val::Number(2), Val::Number(0)), Ok(Val::Number(1)));
+        assert_eq!(binary(&Operations::Pow, Val::Number(4), Val::Float(0.5)), Ok(Val::Float(2.0)));
+    }
+
+    #[test]
+    fn promote_on_power_overflow() {
+        assert_eq!(
+            binary(&Operations::Pow, Val::Number(2), Val::Number(64)),
+            Ok(big_val("18446744073709551616")),
+        );
+    }
+
+    #[test]
+    fn power_of_trivial_bases_with_huge_exponent() {
+        let huge = big_val("100000000000000000001");
+        assert_eq!(binary(&Operations::Pow, Val::Number(1), huge.clone()), Ok(Val::Number(1)));
+        assert_eq!(binary(&Operations::Pow, Val::Number(-1), huge.clone()), Ok(Val::Number(-1)));
+        assert_eq!(binary(&Operations::Pow, Val::Number(0), huge), Ok(Val::Number(0)));
+    }
+
+    #[test]
+    fn reject_power_too_large() {
+        assert_eq!(
+            binary(&Operations::Pow, Val::Number(2), Val::Number(1 << 30)),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘**’ would have more than 16777216 bits")),
+        );
+        assert_eq!(
+            binary(&Operations::Pow, Val::Number(2), big_val("100000000000000000000")),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘**’ would have more than 16777216 bits")),
+        );
+    }
+
+    #[test]
+    fn reject_negative_int_exponent() {
+        assert_eq!(
+            binary(&Operations::Pow, Val::Number(2), Val::Number(-1)),
+            Err(Error::new(ErrorKind::InvalidOperand, "cannot raise an int to negative power -1")),
+        );
+    }
+
+    #[test]
+    fn bitwise_on_ints() {
+        assert_eq!(binary(&Operations::BitAnd, Val::Number(0b1100), Val::Number(0b1010)), Ok(Val::Number(0b1000)));
+        assert_eq!(binary(&Operations::BitOr, Val::Number(0b1100), Val::Number(0b1010)), Ok(Val::Number(0b1110)));
+        assert_eq!(binary(&Operations::BitXor, Val::Number(0b1100), Val::Number(0b1010)), Ok(Val::Number(0b0110)));
+        assert_eq!(binary(&Operations::BitAnd, Val::Number(-1), Val::Number(0xff)), Ok(Val::Number(0xff)));
+    }
+
+    #[test]
+    fn bitwise_on_big_ints() {
+        assert_eq!(
+            binary(&Operations::BitAnd, big_val("18446744073709551617"), Val::Number(0xff)),
+            Ok(Val::Number(1)),
+        );
+    }
+
+    #[test]
+    fn bitwise_on_bools() {
+        assert_eq!(binary(&Operations::BitAnd, Val::Bool(true), Val::Bool(false)), Ok(Val::Bool(false)));
+        assert_eq!(binary(&Operations::BitOr, Val::Bool(true), Val::Bool(false)), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::BitXor, Val::Bool(true), Val::Bool(true)), Ok(Val::Bool(false)));
+    }
+
+    #[test]
+    fn cannot_use_bitwise_on_floats() {
+        assert_eq!(
+            binary(&Operations::BitOr, Val::Float(1.0), Val::Number(1)),
+            Err(Error::new(ErrorKind::Type, "cannot evaluate ‘|’ on float and int")),
+        );
+        assert_eq!(
+            binary(&Operations::Shl, Val::Bool(true), Val::Number(1)),
+            Err(Error::new(ErrorKind::Type, "cannot evaluate ‘<<’ on bool and int")),
+        );
+    }
+
+    #[test]
+    fn shift_left() {
+        assert_eq!(binary(&Operations::Shl, Val::Number(3), Val::Number(4)), Ok(Val::Number(48)));
+        assert_eq!(binary(&Operations::Shl, Val::Number(-1), Val::Number(63)), Ok(Val::Number(i64::MIN)));
+        assert_eq!(
+            binary(&Operations::Shl, Val::Number(1), Val::Number(64)),
+            Ok(big_val("18446744073709551616")),
+        );
+        assert_eq!(
+            binary(&Operations::Shl, Val::Number(3), Val::Number(62)),
+            Ok(big_val("13835058055282163712")),
+        );
+        assert_eq!(
+            binary(&Operations::Shl, Val::Number(1), big_val("-100000000000000000000")),
+            Err(Error::new(ErrorKind::InvalidOperand, "cannot shift by negative amount -100000000000000000000")),
+        );
+    }
+
+    #[test]
+    fn shift_right_is_arithmetic() {
+        assert_eq!(binary(&Operations::Shr, Val::Number(-16), Val::Number(2)), Ok(Val::Number(-4)));
+        assert_eq!(binary(&Operations::Shr, Val::Number(-16), Val::Number(100)), Ok(Val::Number(-1)));
+        assert_eq!(binary(&Operations::Shr, Val::Number(16), Val::Number(100)), Ok(Val::Number(0)));
+        assert_eq!(
+            binary(&Operations::Shr, big_val("18446744073709551616"), Val::Number(60)),
+            Ok(Val::Number(16)),
+        );
+        assert_eq!(
+            binary(&Operations::Shr, Val::Number(1), big_val("100000000000000000000")),
+            Ok(Val::Number(0)),
+        );
+        assert_eq!(
+            binary(&Operations::Shr, Val::Number(-1), big_val("100000000000000000000")),
+            Ok(Val::Number(-1)),
+        );
+        assert_eq!(
+            binary(&Operations::Shr, big_val("-18446744073709551616"), big_val("100000000000000000000")),
+            Ok(Val::Number(-1)),
+        );
+    }
+
+    #[test]
+    fn reject_bad_shift_amounts() {
+        assert_eq!(
+            binary(&Operations::Shl, Val::Number(1), Val::Number(-1)),
+            Err(Error::new(ErrorKind::InvalidOperand, "cannot shift by negative amount -1")),
+        );
+        assert_eq!(
+            binary(&Operations::Shl, Val::Number(1), Val::Number(1 << 30)),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘<<’ would have more than 16777216 bits")),
+        );
+        assert_eq!(
+            binary(&Operations::Shl, Val::Number(1), big_val("100000000000000000000")),
+            Err(Error::new(ErrorKind::Overflow, "result of ‘<<’ would have more than 16777216 bits")),
+        );
+    }
+
+    #[test]
+    fn negate_numbers() {
+        assert_eq!(unary(&UnaryOperations::Neg, Val::Number(5)), Ok(Val::Number(-5)));
+        assert_eq!(unary(&UnaryOperations::Neg, Val::Float(0.5)), Ok(Val::Float(-0.5)));
+    }
+
+    #[test]
+    fn negate_smallest_number_to_big_int() {
+        assert_eq!(
+            unary(&UnaryOperations::Neg, Val::Number(i64::MIN)),
+            Ok(big_val("9223372036854775808")),
+        );
+        assert_eq!(
+            unary(&UnaryOperations::Neg, big_val("9223372036854775808")),
+            Ok(Val::Number(i64::MIN)),
+        );
+    }
+
+    #[test]
+    fn not_is_logical_for_bools_and_bitwise_for_ints() {
+        assert_eq!(unary(&UnaryOperations::Not, Val::Bool(true)), Ok(Val::Bool(false)));
+        assert_eq!(unary(&UnaryOperations::Not, Val::Number(0)), Ok(Val::Number(-1)));
+        assert_eq!(
+            unary(&UnaryOperations::Not, big_val("9223372036854775808")),
+            Ok(big_val("-9223372036854775809")),
+        );
+    }
+
+    #[test]
+    fn cannot_apply_unary_to_wrong_type() {
+        assert_eq!(
+            unary(&UnaryOperations::Not, Val::Float(1.0)),
+            Err(Error::new(ErrorKind::Type, "cannot apply unary ‘!’ to float")),
+        );
+        assert_eq!(
+            unary(&UnaryOperations::Plus, Val::Bool(true)),
+            Err(Error::new(ErrorKind::Type, "cannot apply unary ‘+’ to bool")),
+        );
+    }
+
+    #[test]
+    fn cannot_add_bool() {
+        assert_eq!(
+            binary(&Operations::Add, Val::Float(1.0), Val::Bool(true)),
+            Err(not_numbers()),
+        );
+    }
+
+    #[test]
+    fn compare_numbers_by_value() {
+        assert_eq!(binary(&Operations::Lt, Val::Number(1), Val::Number(2)), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::Eq, Val::Number(2), Val::Float(2.0)), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::Ge, big_val("9223372036854775808"), Val::Number(1)), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::Le, Val::Float(2.5), big_val("9223372036854775808")), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::Ne, Val::Float(f64::NAN), Val::Float(f64::NAN)), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::Gt, Val::Float(f64::NAN), Val::Number(0)), Ok(Val::Bool(false)));
+    }
+
+    #[test]
+    fn compare_other_values_for_equality_only() {
+        assert_eq!(binary(&Operations::Eq, Val::Bool(true), Val::Bool(true)), Ok(Val::Bool(true)));
+        assert_eq!(binary(&Operations::Ne, Val::Bool(true), Val::Number(1)), Ok(Val::Bool(true)));
+        assert_eq!(
+            binary(&Operations::Lt, Val::Bool(false), Val::Bool(true)),
+            Err(Error::new(ErrorKind::Type, "cannot compare bool with bool")),
+        );
+    }
+}
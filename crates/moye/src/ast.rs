@@ -0,0 +1,499 @@
+/*
+ * The syntax tree of a program, for tools that analyse or generate moye
+ * code. Every node records the span of source code it was parsed from;
+ * `Visitor` walks a tree and `VisitorMut` rewrites it in place.
+ *
+ * The `walk_*` functions visit the children of a node, so that a visitor
+ * overriding a `visit_*` method can call them to keep walking. Enums are
+ * `#[non_exhaustive]` because the language will grow.
+ */
+
+pub use crate::annotation::{Type, TypeAnnotation};
+pub use crate::binding_def::BindingDef;
+pub use crate::expr::{
+    BigNumber, BindingUsage, Block, Bool, Expression, Float, FuncCall, IfElse, Number, Operations, UnaryOperations,
+};
+pub use crate::func_def::FuncDef;
+pub use crate::lexer::Span;
+pub use crate::statements::Statement;
+use crate::limits::MAX_NESTING;
+
+/// Walks a syntax tree. Every method visits the children of its node by
+/// default.
+pub trait Visitor {
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_binding_def(&mut self, binding_def: &BindingDef) {
+        walk_binding_def(self, binding_def);
+    }
+
+    fn visit_func_def(&mut self, func_def: &FuncDef) {
+        walk_func_def(self, func_def);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_func_call(&mut self, func_call: &FuncCall) {
+        walk_func_call(self, func_call);
+    }
+
+    fn visit_if_else(&mut self, if_else: &IfElse) {
+        walk_if_else(self, if_else);
+    }
+
+    fn visit_type_annotation(&mut self, type_annotation: &TypeAnnotation) {
+        self.visit_span(type_annotation.span);
+    }
+
+    fn visit_binding_usage(&mut self, binding_usage: &BindingUsage) {
+        self.visit_span(binding_usage.span);
+    }
+
+    fn visit_number(&mut self, number: &Number) {
+        self.visit_span(number.span);
+    }
+
+    fn visit_big_number(&mut self, number: &BigNumber) {
+        self.visit_span(number.span);
+    }
+
+    fn visit_float(&mut self, float: &Float) {
+        self.visit_span(float.span);
+    }
+
+    fn visit_bool(&mut self, boolean: &Bool) {
+        self.visit_span(boolean.span);
+    }
+
+    /// Called with the span of every node.
+    fn visit_span(&mut self, _span: Span) {}
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::BindingDef(binding_def) => visitor.visit_binding_def(binding_def),
+        Statement::Expression(expression) => visitor.visit_expression(expression),
+        Statement::FuncDef(func_def) => visitor.visit_func_def(func_def),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Number(number) => visitor.visit_number(number),
+        Expression::BigNumber(number) => visitor.visit_big_number(number),
+        Expression::Float(float) => visitor.visit_float(float),
+        Expression::Bool(boolean) => visitor.visit_bool(boolean),
+        Expression::Operation { lhs, rhs, span, .. } => {
+            visitor.visit_span(*span);
+            visitor.visit_expression(lhs);
+            visitor.visit_expression(rhs);
+        },
+        Expression::Unary { operand, span, .. } => {
+            visitor.visit_span(*span);
+            visitor.visit_expression(operand);
+        },
+        Expression::FuncCall(func_call) => visitor.visit_func_call(func_call),
+        Expression::BindingUsage(binding_usage) => visitor.visit_binding_usage(binding_usage),
+        Expression::Block(block) => visitor.visit_block(block),
+        Expression::IfElse(if_else) => visitor.visit_if_else(if_else),
+    }
+}
+
+pub fn walk_binding_def<V: Visitor + ?Sized>(visitor: &mut V, binding_def: &BindingDef) {
+    visitor.visit_span(binding_def.span);
+    if let Some(ty) = &binding_def.ty {
+        visitor.visit_type_annotation(ty);
+    }
+    visitor.visit_expression(&binding_def.value);
+}
+
+pub fn walk_func_def<V: Visitor + ?Sized>(visitor: &mut V, func_def: &FuncDef) {
+    visitor.visit_span(func_def.span);
+    for ty in func_def.param_types.iter().chain([&func_def.return_type]).flatten() {
+        visitor.visit_type_annotation(ty);
+    }
+    visitor.visit_statement(&func_def.body);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    visitor.visit_span(block.span);
+    for statement in &block.stmts {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_func_call<V: Visitor + ?Sized>(visitor: &mut V, func_call: &FuncCall) {
+    visitor.visit_span(func_call.span);
+    for param in &func_call.params {
+        visitor.visit_expression(param);
+    }
+}
+
+pub fn walk_if_else<V: Visitor + ?Sized>(visitor: &mut V, if_else: &IfElse) {
+    visitor.visit_span(if_else.span);
+    visitor.visit_expression(&if_else.condition);
+    visitor.visit_expression(&if_else.then_branch);
+    visitor.visit_expression(&if_else.else_branch);
+}
+
+/// Walks a syntax tree, possibly changing it. Every method visits the
+/// children of its node by default; to replace a node, override the method
+/// of its parent, e.g. assign to the `&mut Expression`.
+pub trait VisitorMut {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_binding_def_mut(&mut self, binding_def: &mut BindingDef) {
+        walk_binding_def_mut(self, binding_def);
+    }
+
+    fn visit_func_def_mut(&mut self, func_def: &mut FuncDef) {
+        walk_func_def_mut(self, func_def);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_func_call_mut(&mut self, func_call: &mut FuncCall) {
+        walk_func_call_mut(self, func_call);
+    }
+
+    fn visit_if_else_mut(&mut self, if_else: &mut IfElse) {
+        walk_if_else_mut(self, if_else);
+    }
+
+    fn visit_type_annotation_mut(&mut self, type_annotation: &mut TypeAnnotation) {
+        self.visit_span_mut(&mut type_annotation.span);
+    }
+
+    fn visit_binding_usage_mut(&mut self, binding_usage: &mut BindingUsage) {
+        self.visit_span_mut(&mut binding_usage.span);
+    }
+
+    fn visit_number_mut(&mut self, number: &mut Number) {
+        self.visit_span_mut(&mut number.span);
+    }
+
+    fn visit_big_number_mut(&mut self, number: &mut BigNumber) {
+        self.visit_span_mut(&mut number.span);
+    }
+
+    fn visit_float_mut(&mut self, float: &mut Float) {
+        self.visit_span_mut(&mut float.span);
+    }
+
+    fn visit_bool_mut(&mut self, boolean: &mut Bool) {
+        self.visit_span_mut(&mut boolean.span);
+    }
+
+    /// Called with the span of every node.
+    fn visit_span_mut(&mut self, _span: &mut Span) {}
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::BindingDef(binding_def) => visitor.visit_binding_def_mut(binding_def),
+        Statement::Expression(expression) => visitor.visit_expression_mut(expression),
+        Statement::FuncDef(func_def) => visitor.visit_func_def_mut(func_def),
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Number(number) => visitor.visit_number_mut(number),
+        Expression::BigNumber(number) => visitor.visit_big_number_mut(number),
+        Expression::Float(float) => visitor.visit_float_mut(float),
+        Expression::Bool(boolean) => visitor.visit_bool_mut(boolean),
+        Expression::Operation { lhs, rhs, span, .. } => {
+            visitor.visit_span_mut(span);
+            visitor.visit_expression_mut(lhs);
+            visitor.visit_expression_mut(rhs);
+        },
+        Expression::Unary { operand, span, .. } => {
+            visitor.visit_span_mut(span);
+            visitor.visit_expression_mut(operand);
+        },
+        Expression::FuncCall(func_call) => visitor.visit_func_call_mut(func_call),
+        Expression::BindingUsage(binding_usage) => visitor.visit_binding_usage_mut(binding_usage),
+        Expression::Block(block) => visitor.visit_block_mut(block),
+        Expression::IfElse(if_else) => visitor.visit_if_else_mut(if_else),
+    }
+}
+
+pub fn walk_binding_def_mut<V: VisitorMut + ?Sized>(visitor: &mut V, binding_def: &mut BindingDef) {
+    visitor.visit_span_mut(&mut binding_def.span);
+    if let Some(ty) = &mut binding_def.ty {
+        visitor.visit_type_annotation_mut(ty);
+    }
+    visitor.visit_expression_mut(&mut binding_def.value);
+}
+
+pub fn walk_func_def_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func_def: &mut FuncDef) {
+    visitor.visit_span_mut(&mut func_def.span);
+    for ty in func_def.param_types.iter_mut().chain([&mut func_def.return_type]).flatten() {
+        visitor.visit_type_annotation_mut(ty);
+    }
+    visitor.visit_statement_mut(&mut func_def.body);
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    visitor.visit_span_mut(&mut block.span);
+    for statement in &mut block.stmts {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_func_call_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func_call: &mut FuncCall) {
+    visitor.visit_span_mut(&mut func_call.span);
+    for param in &mut func_call.params {
+        visitor.visit_expression_mut(param);
+    }
+}
+
+pub fn walk_if_else_mut<V: VisitorMut + ?Sized>(visitor: &mut V, if_else: &mut IfElse) {
+    visitor.visit_span_mut(&mut if_else.span);
+    visitor.visit_expression_mut(&mut if_else.condition);
+    visitor.visit_expression_mut(&mut if_else.then_branch);
+    visitor.visit_expression_mut(&mut if_else.else_branch);
+}
+
+/// Finds the first expression or function definition nested in
+/// `MAX_NESTING` others, which is too deep, without walking any further down
+/// than that, so that trees of any depth can be checked.
+pub(crate) struct NestingChecker {
+    depth: usize,
+    too_deep: Option<Span>,
+}
+
+impl NestingChecker {
+    /// For nodes nested in `depth` others.
+    pub(crate) fn new(depth: usize) -> Self {
+        Self { depth, too_deep: None }
+    }
+
+    pub(crate) fn too_deep(&self) -> Option<Span> {
+        self.too_deep
+    }
+
+    fn nest(&mut self, span: Span, walk: impl FnOnce(&mut Self)) {
+        if self.too_deep.is_some() {
+            return;
+        }
+        if self.depth == MAX_NESTING {
+            self.too_deep = Some(span);
+            return;
+        }
+        self.depth += 1;
+        walk(self);
+        self.depth -= 1;
+    }
+}
+
+impl Visitor for NestingChecker {
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.nest(expression.span(), |checker| walk_expression(checker, expression));
+    }
+
+    fn visit_func_def(&mut self, func_def: &FuncDef) {
+        self.nest(func_def.span, |checker| walk_func_def(checker, func_def));
+    }
+}
+
+/*
+ * Parser tests compare against trees built by hand, whose spans are all
+ * `Span::default()`
+ */
+#[cfg(test)]
+pub(crate) trait EraseSpans {
+    fn erase_spans(self) -> Self;
+}
+
+#[cfg(test)]
+struct SpanEraser;
+
+#[cfg(test)]
+impl VisitorMut for SpanEraser {
+    fn visit_span_mut(&mut self, span: &mut Span) {
+        *span = Span::default();
+    }
+}
+
+#[cfg(test)]
+macro_rules! erase_spans_with {
+    ($($node:ty => $visit:ident),* $(,)?) => {
+        $(
+            impl EraseSpans for $node {
+                fn erase_spans(mut self) -> Self {
+                    SpanEraser.$visit(&mut self);
+                    self
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+erase_spans_with! {
+    Statement => visit_statement_mut,
+    Expression => visit_expression_mut,
+    BindingDef => visit_binding_def_mut,
+    FuncDef => visit_func_def_mut,
+    Block => visit_block_mut,
+    FuncCall => visit_func_call_mut,
+    BindingUsage => visit_binding_usage_mut,
+    Number => visit_number_mut,
+    BigNumber => visit_big_number_mut,
+    Float => visit_float_mut,
+    Bool => visit_bool_mut,
+    IfElse => visit_if_else_mut,
+    TypeAnnotation => visit_type_annotation_mut,
+}
+
+// Operators have no span.
+#[cfg(test)]
+impl EraseSpans for Operations {
+    fn erase_spans(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+impl EraseSpans for UnaryOperations {
+    fn erase_spans(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    // Collects the names of bindings that are used.
+    #[derive(Default)]
+    struct Usages(Vec<String>);
+
+    impl Visitor for Usages {
+        fn visit_binding_usage(&mut self, binding_usage: &BindingUsage) {
+            self.0.push(binding_usage.name.clone());
+        }
+
+        fn visit_func_call(&mut self, func_call: &FuncCall) {
+            self.0.push(func_call.callee.clone());
+            walk_func_call(self, func_call);
+        }
+    }
+
+    #[test]
+    fn visit_every_node() {
+        let parse = parse("let a = b + { c }\nfun f x => g x y\nf a\nif a then true else z").unwrap();
+        let mut usages = Usages::default();
+        for statement in parse.statements() {
+            usages.visit_statement(statement);
+        }
+        assert_eq!(usages.0, vec!["b", "c", "g", "x", "y", "f", "a", "a", "z"]);
+    }
+
+    #[test]
+    fn record_spans_of_nodes() {
+        let source = "let sum = 1 + -x\n{ double 21 }";
+        let parse = parse(source).unwrap();
+        let [Statement::BindingDef(binding_def), Statement::Expression(block)] = parse.statements() else {
+            panic!("unexpected statements: {:?}", parse.statements());
+        };
+        let Expression::Operation { rhs, .. } = &binding_def.value else {
+            panic!("unexpected value: {:?}", binding_def.value);
+        };
+        let text = |span: Span| &source[span.start..span.end];
+        assert_eq!(text(binding_def.span), "let sum = 1 + -x");
+        assert_eq!(text(binding_def.value.span()), "1 + -x");
+        assert_eq!(text(rhs.span()), "-x");
+        assert_eq!(text(block.span()), "{ double 21 }");
+    }
+
+    #[test]
+    fn visit_spans_of_parents_before_children() {
+        struct Spans(Vec<(usize, usize)>);
+
+        impl Visitor for Spans {
+            fn visit_span(&mut self, span: Span) {
+                self.0.push((span.start, span.end));
+            }
+        }
+
+        let parse = parse("fun f x => { x * 2 }\nf -1").unwrap();
+        let mut spans = Spans(Vec::new());
+        for statement in parse.statements() {
+            spans.visit_statement(statement);
+        }
+        assert_eq!(spans.0, vec![(0, 20), (11, 20), (13, 18), (13, 14), (17, 18), (21, 25), (23, 25)]);
+    }
+
+    // Replaces bindings by constants.
+    struct Inline;
+
+    impl VisitorMut for Inline {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            if let Expression::BindingUsage(BindingUsage { name, span }) = expression
+                && name == "answer"
+            {
+                *expression = Expression::Number(Number { value: 42, span: *span });
+            }
+            walk_expression_mut(self, expression);
+        }
+    }
+
+    #[test]
+    fn rewrite_tree() {
+        let mut parse = parse("answer * 2").unwrap();
+        for statement in parse.statements_mut() {
+            Inline.visit_statement_mut(statement);
+        }
+        assert_eq!(parse.eval(&mut crate::Env::default()), Ok(crate::Val::Number(84)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_to_json() {
+        let parse = parse("let a = -x").unwrap();
+        let json = serde_json::to_value(&parse).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "BindingDef": {
+                    "name": "a",
+                    "value": {
+                        "Unary": {
+                            "op": "Neg",
+                            "operand": { "BindingUsage": { "name": "x", "span": { "start": 9, "end": 10 } } },
+                            "span": { "start": 8, "end": 10 },
+                        },
+                    },
+                    "span": { "start": 0, "end": 10 },
+                },
+            }]),
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_from_json() {
+        let parse = parse("fun f x => { x ** 2.5 }\nf 2").unwrap();
+        let json = serde_json::to_string(&parse).unwrap();
+        assert_eq!(serde_json::from_str::<crate::Parse>(&json).unwrap(), parse);
+    }
+}
@@ -2,62 +2,119 @@
  * Bindings are the immutable variable definitions
  */
 
+use crate::annotation::TypeAnnotation;
 use crate::expr::Expression;
+use crate::diagnostic::{self, SyntaxError};
+use crate::lexer::{Span, Token, TokenKind};
 use crate::utils;
-use crate::env::Env;
 
+/// `let name = value`, or `let name: Type = value`.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct BindingDef {
-    pub(crate) name: String,
-    pub(crate) value: Expression,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BindingDef {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub ty: Option<TypeAnnotation>,
+    pub value: Expression,
+    pub span: Span,
 }
 
 impl BindingDef {
-    pub(crate) fn new(s:&str) -> Result<(&str, Self), String> {
-        let s = utils::tag("let", s)?;
-        let (s, _) = utils::extract_whitespaces_with_error(s)?;
-        let (s, name) = utils::extract_ident(s)?;
-        let (s, _) = utils::extract_whitespaces(s);
-        let s = utils::tag("=", s)?;
-        let (s, _) = utils::extract_whitespaces(s);
-        let (s, val) = Expression::new(s)?;
-        Ok((s, Self {
-            name: name.to_string(),
+    pub(crate) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        let start = tokens;
+        let tokens = utils::tag(&TokenKind::Let, tokens)?;
+        let (tokens, name) = utils::extract_ident_token(tokens)?;
+        let (tokens, ty) = TypeAnnotation::optional(&TokenKind::Colon, tokens)?;
+        let tokens = utils::tag(&TokenKind::Equals, tokens).map_err(|error| match ty {
+            Some(_) => error,
+            None => error.merge(SyntaxError::expected([diagnostic::token(&TokenKind::Colon)], &tokens[0])),
+        })?;
+        let (tokens, val) = Expression::new(tokens, depth)?;
+        Ok((tokens, Self {
+            name,
+            ty,
             value: val,
+            span: utils::consumed(start, tokens),
         }))
     }
-
-    pub(crate) fn eval(&self, env: &mut Env) -> Result<(), String> {
-        env.store_binding(self.name.clone(), self.value.eval(env)?);
-        Ok(())
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::parse_str;
+    use crate::annotation::Type;
     use crate::expr::{Number, Operations};
 
     #[test]
     fn parse_binding_def() {
         assert_eq!(
-            BindingDef::new("let a = 10 / 2"),
+            parse_str(|tokens| BindingDef::new(tokens, 0), "let a = 10 / 2"),
             Ok((
                 "",
                 BindingDef {
                     name: "a".to_string(),
+                    ty: None,
                     value: Expression::Operation {
-                        lhs: Box::new(Expression::Number(Number(10))),
-                        rhs: Box::new(Expression::Number(Number(2))),
+                        lhs: Box::new(Expression::Number(Number { value: 10, span: Span::default() })),
+                        rhs: Box::new(Expression::Number(Number { value: 2, span: Span::default() })),
                         op: Operations::Div,
+                        span: Span::default(),
                     },
+                    span: Span::default(),
                 },
             )),
         );
     }
 
+    #[test]
+    fn parse_binding_def_with_type_annotation() {
+        assert_eq!(
+            parse_str(|tokens| BindingDef::new(tokens, 0), "let a: Int = 1"),
+            Ok((
+                "",
+                BindingDef {
+                    name: "a".to_string(),
+                    ty: Some(TypeAnnotation { ty: Type::Int, span: Span::default() }),
+                    value: Expression::Number(Number { value: 1, span: Span::default() }),
+                    span: Span::default(),
+                },
+            )),
+        );
+        assert_eq!(parse_str(|tokens| BindingDef::new(tokens, 0), "let a 1"), Err("expected ‘:’ or ‘=’, found ‘1’".to_string()));
+        assert_eq!(parse_str(|tokens| BindingDef::new(tokens, 0), "let a: Int 1"), Err("expected ‘=’, found ‘1’".to_string()));
+    }
+
     #[test]
     fn cannot_parse_binding_def_without_space_after_let() {
-        assert_eq!(BindingDef::new("letabc=1+2"), Err("expected a space".to_string()))
+        assert_eq!(parse_str(|tokens| BindingDef::new(tokens, 0), "letabc=1+2"), Err("expected ‘let’, found ‘letabc’".to_string()))
+    }
+
+    #[test]
+    fn cannot_parse_binding_def_named_after_keyword() {
+        assert_eq!(
+            parse_str(|tokens| BindingDef::new(tokens, 0), "let let = 1"),
+            Err("‘let’ is a reserved word and cannot be used as an identifier".to_string()),
+        );
+        assert_eq!(
+            parse_str(|tokens| BindingDef::new(tokens, 0), "let fun = 1"),
+            Err("‘fun’ is a reserved word and cannot be used as an identifier".to_string()),
+        );
+    }
+
+    #[test]
+    fn parse_binding_def_with_name_starting_with_keyword() {
+        assert_eq!(
+            parse_str(|tokens| BindingDef::new(tokens, 0), "let letter = 1"),
+            Ok((
+                "",
+                BindingDef {
+                    name: "letter".to_string(),
+                    ty: None,
+                    value: Expression::Number(Number { value: 1, span: Span::default() }),
+                    span: Span::default(),
+                },
+            )),
+        );
     }
 }
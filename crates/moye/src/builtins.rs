@@ -0,0 +1,101 @@
+/*
+ * Functions available in every environment; user definitions with the same
+ * name shadow them
+ */
+
+use crate::arith;
+use crate::error::{Error, ErrorKind};
+use crate::native::NativeFunc;
+use crate::val::Val;
+
+pub(crate) fn get(name: &str) -> Option<NativeFunc> {
+    let func: fn(Val) -> Result<Val, Error> = match name {
+        "int" => int,
+        "float" => float,
+        "round" => round,
+        "floor" => floor,
+        _ => return None,
+    };
+    Some(NativeFunc::builtin(1, move |mut args| func(args.remove(0))))
+}
+
+// Truncates floats towards zero.
+fn int(val: Val) -> Result<Val, Error> {
+    match val {
+        Val::Number(_) | Val::BigInt(_) => Ok(val),
+        Val::Float(f) => arith::float_to_int(f),
+        other => Err(Error::new(ErrorKind::Type, format!("cannot convert {} to an int", other.type_name()))),
+    }
+}
+
+fn float(val: Val) -> Result<Val, Error> {
+    match arith::to_float(&val) {
+        Some(f) if f.is_finite() => Ok(Val::Float(f)),
+        Some(_) => Err(Error::new(ErrorKind::Overflow, format!("int {} is too large for a float", val))),
+        None => Err(Error::new(ErrorKind::Type, format!("cannot convert {} to a float", val.type_name()))),
+    }
+}
+
+// Rounds halfway cases away from zero.
+fn round(val: Val) -> Result<Val, Error> {
+    match val {
+        Val::Float(f) => arith::float_to_int(f.round()),
+        other => int(other),
+    }
+}
+
+fn floor(val: Val) -> Result<Val, Error> {
+    match val {
+        Val::Float(f) => arith::float_to_int(f.floor()),
+        other => int(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arg: Val) -> Result<Val, Error> {
+        get(name).unwrap().call(vec![arg])
+    }
+
+    #[test]
+    fn unknown_builtin() {
+        assert_eq!(get("nope"), None);
+    }
+
+    #[test]
+    fn convert_to_int() {
+        assert_eq!(call("int", Val::Float(-3.7)), Ok(Val::Number(-3)));
+        assert_eq!(call("int", Val::Number(3)), Ok(Val::Number(3)));
+        assert_eq!(call("int", Val::Unit), Err(Error::new(ErrorKind::Type, "cannot convert unit to an int")));
+    }
+
+    #[test]
+    fn convert_to_float() {
+        assert_eq!(call("float", Val::Number(3)), Ok(Val::Float(3.0)));
+        assert_eq!(call("float", Val::Float(0.5)), Ok(Val::Float(0.5)));
+        assert_eq!(
+            call("float", Val::Bool(true)),
+            Err(Error::new(ErrorKind::Type, "cannot convert bool to a float")),
+        );
+        assert_eq!(
+            call("float", Val::BigInt(num_bigint::BigInt::from(10).pow(400))),
+            Err(Error::new(ErrorKind::Overflow, format!("int 1{} is too large for a float", "0".repeat(400)))),
+        );
+    }
+
+    #[test]
+    fn round_half_away_from_zero() {
+        assert_eq!(call("round", Val::Float(2.5)), Ok(Val::Number(3)));
+        assert_eq!(call("round", Val::Float(-2.5)), Ok(Val::Number(-3)));
+        assert_eq!(call("round", Val::Float(2.4)), Ok(Val::Number(2)));
+    }
+
+    #[test]
+    fn floor_towards_negative_infinity() {
+        assert_eq!(call("floor", Val::Float(-2.5)), Ok(Val::Number(-3)));
+        assert_eq!(call("floor", Val::Float(2.5)), Ok(Val::Number(2)));
+        assert_eq!(call("floor", Val::Number(7)), Ok(Val::Number(7)));
+    }
+}
@@ -0,0 +1,128 @@
+/*
+ * A compact, stack-based form of resolved code for the VM. Instructions
+ * refer to constants, names and functions by their index in the `Code` they
+ * belong to, and every expression leaves exactly one value on the stack
+ */
+
+use crate::annotation::Type;
+use crate::error::ErrorKind;
+use crate::expr::{Operations, UnaryOperations};
+use crate::lexer::Span;
+use crate::native::NativeFunc;
+use crate::resolved::Function;
+use crate::val::Val;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    /// Pushes a constant.
+    Const(u32),
+    /// Pushes `Unit` as the value of a definition, which is not a step.
+    Unit,
+    Pop,
+    /// Counts the step of a block without a frame of its own.
+    Step,
+    /// Pushes the value in a slot, calling the function in it instead if the
+    /// host has replaced the binding by a function.
+    Load { depth: u32, slot: u32, name: u32 },
+    /// Pops a value into a slot of the current frame.
+    Store { slot: u32 },
+    DefineFunc { slot: u32, function: u32 },
+    /// Calls the function in a slot with the topmost `argc` values as its
+    /// arguments. `nesting` counts against `Limits::max_call_nesting`.
+    Call { depth: u32, slot: u32, argc: u32, name: u32, nesting: u32 },
+    /// Like `Call`, but in tail position of a function, so that the callee
+    /// replaces the function instead of returning to it.
+    TailCall { depth: u32, slot: u32, argc: u32, name: u32 },
+    CallBuiltin { builtin: u32, argc: u32 },
+    Binary(Operations),
+    Unary(UnaryOperations),
+    Jump(u32),
+    /// Pops a bool and jumps if it is false.
+    JumpUnless(u32),
+    PushFrame { num_slots: u32 },
+    PopFrame,
+    /// Fails with an error of `kind`, with a message from `strings`.
+    Fail { kind: ErrorKind, message: u32 },
+    /// Fails unless the value on top of the stack has the annotated type,
+    /// saying that `what`, from `strings`, must have it.
+    Check { ty: Type, what: u32 },
+    /// Leaves the current function, or finishes the code of a statement.
+    Return,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Code {
+    pub(crate) ops: Vec<Op>,
+    pub(crate) consts: Vec<Val>,
+    /// Names for error messages, and the messages of `Fail`.
+    pub(crate) strings: Vec<String>,
+    pub(crate) functions: Vec<Rc<Function>>,
+    pub(crate) builtins: Vec<(String, NativeFunc)>,
+    /// Where the code of each statement starts, by instruction; only known
+    /// for code compiled from source.
+    pub(crate) positions: Vec<(u32, Span)>,
+}
+
+impl Code {
+    /// The span of the statement instruction `idx` belongs to.
+    pub(crate) fn position(&self, idx: usize) -> Option<Span> {
+        let after = self.positions.partition_point(|(start, _)| *start as usize <= idx);
+        after.checked_sub(1).map(|position| self.positions[position].1)
+    }
+
+    /// One instruction per line, numbered, under the source line of the
+    /// statement it belongs to if `source` is known. Slots are written as
+    /// `depth:slot`.
+    pub(crate) fn disassemble(&self, source: Option<&str>, out: &mut String) {
+        let mut positions = self.positions.iter().peekable();
+        let mut line = None;
+        for (idx, op) in self.ops.iter().enumerate() {
+            while let Some((_, span)) = positions.next_if(|(start, _)| *start as usize <= idx) {
+                let Some(source) = source else { continue };
+                let number = source[..span.start].matches('\n').count() + 1;
+                if line != Some(number) {
+                    let line_start = source[..span.start].rfind('\n').map_or(0, |idx| idx + 1);
+                    let text = source[line_start..].lines().next().unwrap_or_default();
+                    out.push_str(&format!("{:>4} | {}\n", number, text));
+                    line = Some(number);
+                }
+            }
+            out.push_str(&format!("{:04} {}\n", idx, self.instruction(op)));
+        }
+    }
+
+    fn instruction(&self, op: &Op) -> String {
+        let string = |idx: u32| &self.strings[idx as usize];
+        let (name, operands) = match *op {
+            Op::Const(constant) => ("const", self.consts[constant as usize].to_string()),
+            Op::Unit => ("unit", String::new()),
+            Op::Pop => ("pop", String::new()),
+            Op::Step => ("step", String::new()),
+            Op::Load { depth, slot, name } => ("load", format!("{} {}:{}", string(name), depth, slot)),
+            Op::Store { slot } => ("store", format!("0:{}", slot)),
+            Op::DefineFunc { slot, function } => {
+                ("define", format!("{} 0:{}", self.functions[function as usize].name, slot))
+            },
+            Op::Call { depth, slot, argc, name, nesting } => {
+                ("call", format!("{} {}:{} argc={} nesting={}", string(name), depth, slot, argc, nesting))
+            },
+            Op::TailCall { depth, slot, argc, name } => {
+                ("tail-call", format!("{} {}:{} argc={}", string(name), depth, slot, argc))
+            },
+            Op::CallBuiltin { builtin, argc } => {
+                ("call-builtin", format!("{} argc={}", self.builtins[builtin as usize].0, argc))
+            },
+            Op::Binary(op) => ("binary", op.symbol().to_string()),
+            Op::Unary(op) => ("unary", op.symbol().to_string()),
+            Op::Jump(target) => ("jump", format!("{:04}", target)),
+            Op::JumpUnless(target) => ("jump-unless", format!("{:04}", target)),
+            Op::PushFrame { num_slots } => ("push-frame", num_slots.to_string()),
+            Op::PopFrame => ("pop-frame", String::new()),
+            Op::Fail { kind, message } => ("fail", format!("{:?} {:?}", kind, string(message))),
+            Op::Check { ty, what } => ("check", format!("{} {}", ty, string(what))),
+            Op::Return => ("return", String::new()),
+        };
+        format!("{:<12} {}", name, operands).trim_end().to_string()
+    }
+}
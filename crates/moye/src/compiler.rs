@@ -0,0 +1,351 @@
+/*
+ * Compiles resolved code to bytecode. Each top-level statement and each
+ * function body becomes a `Code` of its own; functions are only compiled
+ * the first time the VM calls them.
+ *
+ * The VM counts the same steps as the tree walker, one per expression, but
+ * an expression counts its step after its operands rather than before
+ */
+
+use crate::bytecode::{Code, Op};
+use crate::lexer::Span;
+use crate::resolved::{Callee, Expr, Function, Stmt};
+use crate::val::Val;
+use std::rc::Rc;
+
+pub(crate) fn compile(stmt: &Stmt) -> Code {
+    let mut compiler = Compiler::default();
+    compiler.statement(stmt, Value::Keep);
+    compiler.finish()
+}
+
+pub(crate) fn compile_function(function: &Function) -> Code {
+    let mut compiler = Compiler::default();
+    let body = function.body.as_ref().expect("functions without a body come compiled");
+    match function.return_type {
+        Some(ty) => {
+            compiler.statement(body, Value::Keep);
+            let what = compiler.string(&format!("the value of ‘{}’", function.name));
+            compiler.emit(Op::Check { ty, what });
+        },
+        None => compiler.statement(body, Value::Return),
+    }
+    compiler.finish()
+}
+
+// What becomes of the value of a statement.
+#[derive(Clone, Copy, PartialEq)]
+enum Value {
+    Discard,
+    Keep,
+    /// Returned from the function being compiled, so a call that computes
+    /// it can be a tail call.
+    Return,
+}
+
+#[derive(Default)]
+struct Compiler {
+    code: Code,
+    /// The statement being compiled.
+    span: Option<Span>,
+}
+
+impl Compiler {
+    fn finish(mut self) -> Code {
+        self.emit(Op::Return);
+        self.code
+    }
+
+    // Statements whose value is discarded leave nothing on the stack.
+    fn statement(&mut self, stmt: &Stmt, value: Value) {
+        match stmt {
+            Stmt::BindingDef { name, slot, ty, value, span } => {
+                self.position(*span);
+                self.expression(value);
+                if let Some(ty) = *ty {
+                    let what = self.string(&format!("binding ‘{}’", name));
+                    self.emit(Op::Check { ty, what });
+                }
+                self.emit(Op::Store { slot: index(*slot) });
+            },
+            Stmt::FuncDef { slot, func, span, .. } => {
+                self.position(*span);
+                self.code.functions.push(Rc::clone(func));
+                let function = index(self.code.functions.len() - 1);
+                self.emit(Op::DefineFunc { slot: index(*slot), function });
+            },
+            Stmt::Expr(expr, span) => {
+                self.position(*span);
+                match value {
+                    Value::Discard => {
+                        self.expression(expr);
+                        self.emit(Op::Pop);
+                    },
+                    Value::Keep => self.expression(expr),
+                    Value::Return => self.tail_expression(expr),
+                }
+                return;
+            },
+        }
+        if value != Value::Discard {
+            self.emit(Op::Unit);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Const(val) => {
+                let constant = self.constant(val);
+                self.emit(Op::Const(constant));
+            },
+            Expr::Binding { name, depth, slot } => {
+                let name = self.string(name);
+                self.emit(Op::Load { depth: index(*depth), slot: index(*slot), name });
+            },
+            Expr::Call { callee, args, nesting } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let argc = index(args.len());
+                match callee {
+                    Callee::Slot { name, depth, slot } => {
+                        let name = self.string(name);
+                        let nesting = index(*nesting);
+                        self.emit(Op::Call { depth: index(*depth), slot: index(*slot), argc, name, nesting });
+                    },
+                    Callee::Builtin { name, native } => {
+                        self.code.builtins.push((name.clone(), native.clone()));
+                        let builtin = index(self.code.builtins.len() - 1);
+                        self.emit(Op::CallBuiltin { builtin, argc });
+                    },
+                }
+            },
+            Expr::Operation { lhs, rhs, op } => {
+                self.expression(lhs);
+                self.expression(rhs);
+                self.emit(Op::Binary(*op));
+            },
+            Expr::Unary { op, operand } => {
+                self.expression(operand);
+                self.emit(Op::Unary(*op));
+            },
+            Expr::IfElse { condition, then_branch, else_branch } => {
+                self.if_else(condition, then_branch, else_branch, Value::Keep);
+            },
+            Expr::Block { num_slots, stmts } => self.block(*num_slots, stmts, Value::Keep),
+            Expr::Fail(error) => {
+                let message = self.string(error.message());
+                self.emit(Op::Fail { kind: error.kind(), message });
+            },
+        }
+    }
+
+    // Expressions whose value the function returns.
+    fn tail_expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call { callee: Callee::Slot { name, depth, slot }, args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let name = self.string(name);
+                let (depth, slot, argc) = (index(*depth), index(*slot), index(args.len()));
+                self.emit(Op::TailCall { depth, slot, argc, name });
+            },
+            Expr::IfElse { condition, then_branch, else_branch } => {
+                self.if_else(condition, then_branch, else_branch, Value::Return);
+            },
+            Expr::Block { num_slots, stmts } => self.block(*num_slots, stmts, Value::Return),
+            expr => self.expression(expr),
+        }
+    }
+
+    fn if_else(&mut self, condition: &Expr, then_branch: &Expr, else_branch: &Expr, value: Value) {
+        let branch = |compiler: &mut Self, expr| match value {
+            Value::Return => compiler.tail_expression(expr),
+            _ => compiler.expression(expr),
+        };
+        self.expression(condition);
+        let to_else = self.emit(Op::JumpUnless(0));
+        branch(self, then_branch);
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_else);
+        branch(self, else_branch);
+        self.patch(to_end);
+    }
+
+    // After the statements of a block, the code is for the statement around
+    // it again.
+    fn block(&mut self, num_slots: usize, stmts: &[Stmt], value: Value) {
+        let outer = self.span;
+        let Some((last, stmts)) = stmts.split_last() else {
+            let unit = self.constant(&Val::Unit);
+            self.emit(Op::Const(unit));
+            return;
+        };
+
+        if num_slots == 0 {
+            self.emit(Op::Step);
+        } else {
+            self.emit(Op::PushFrame { num_slots: index(num_slots) });
+        }
+        for stmt in stmts {
+            self.statement(stmt, Value::Discard);
+        }
+        self.statement(last, value);
+        if let Some(outer) = outer {
+            self.position(outer);
+        }
+        if num_slots != 0 {
+            self.emit(Op::PopFrame);
+        }
+    }
+
+    // The code from here on is for the statement at `span`.
+    fn position(&mut self, span: Span) {
+        let start = index(self.code.ops.len());
+        if let Some(last) = self.code.positions.last_mut()
+            && last.0 == start
+        {
+            *last = (start, span);
+        } else {
+            self.code.positions.push((start, span));
+        }
+        self.span = Some(span);
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.ops.push(op);
+        self.code.ops.len() - 1
+    }
+
+    // Makes the jump at `at` go to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = index(self.code.ops.len());
+        match &mut self.code.ops[at] {
+            Op::Jump(to) | Op::JumpUnless(to) => *to = target,
+            op => unreachable!("only jumps are patched, not {:?}", op),
+        }
+    }
+
+    fn constant(&mut self, val: &Val) -> u32 {
+        self.code.consts.push(val.clone());
+        index(self.code.consts.len() - 1)
+    }
+
+    fn string(&mut self, s: &str) -> u32 {
+        let position = self.code.strings.iter().position(|existing| existing == s);
+        index(position.unwrap_or_else(|| {
+            self.code.strings.push(s.to_string());
+            self.code.strings.len() - 1
+        }))
+    }
+}
+
+fn index(n: usize) -> u32 {
+    u32::try_from(n).expect("code has fewer than 2^32 slots, constants and instructions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::Type;
+    use crate::env::Env;
+    use crate::expr::Operations;
+    use crate::resolver;
+
+    // Compiles the first statement of `source`.
+    fn compile_str(source: &str) -> Code {
+        let parse = crate::parse(source).unwrap();
+        compile(&resolver::resolve(parse.statements(), &Env::default()).stmts[0])
+    }
+
+    #[test]
+    fn compile_definitions_to_unit() {
+        let code = compile_str("let a = 1 + 2");
+        assert_eq!(
+            code.ops,
+            vec![Op::Const(0), Op::Const(1), Op::Binary(Operations::Add), Op::Store { slot: 0 }, Op::Unit, Op::Return],
+        );
+        assert_eq!(code.consts, vec![Val::Number(1), Val::Number(2)]);
+    }
+
+    #[test]
+    fn compile_if_else_to_jumps() {
+        let code = compile_str("if true then 1 else 2");
+        assert_eq!(
+            code.ops,
+            vec![Op::Const(0), Op::JumpUnless(4), Op::Const(1), Op::Jump(5), Op::Const(2), Op::Return],
+        );
+    }
+
+    #[test]
+    fn compile_blocks_with_and_without_frames() {
+        let code = compile_str("{ let a = 1\n{ a }\n{} }");
+        assert_eq!(
+            code.ops,
+            vec![
+                Op::PushFrame { num_slots: 1 },
+                Op::Const(0),
+                Op::Store { slot: 0 },
+                Op::Step,
+                Op::Load { depth: 0, slot: 0, name: 0 },
+                Op::Pop,
+                Op::Const(1),
+                Op::PopFrame,
+                Op::Return,
+            ],
+        );
+        assert_eq!(code.strings, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn compile_calls_in_tail_position_to_tail_calls() {
+        let parse = crate::parse("fun f x => if x then { f x } + 1 else { let y = x\nf y }").unwrap();
+        let resolution = resolver::resolve(parse.statements(), &Env::default());
+        let Stmt::FuncDef { func, .. } = &resolution.stmts[0] else { unreachable!() };
+        let code = compile_function(func);
+        assert_eq!(
+            code.ops,
+            vec![
+                Op::Load { depth: 0, slot: 0, name: 0 },
+                Op::JumpUnless(8),
+                Op::Step,
+                Op::Load { depth: 0, slot: 0, name: 0 },
+                Op::Call { depth: 1, slot: 0, argc: 1, name: 1, nesting: 5 },
+                Op::Const(0),
+                Op::Binary(Operations::Add),
+                Op::Jump(14),
+                Op::PushFrame { num_slots: 1 },
+                Op::Load { depth: 1, slot: 0, name: 0 },
+                Op::Store { slot: 0 },
+                Op::Load { depth: 0, slot: 0, name: 2 },
+                Op::TailCall { depth: 2, slot: 0, argc: 1, name: 1 },
+                Op::PopFrame,
+                Op::Return,
+            ],
+        );
+    }
+
+    #[test]
+    fn compile_checks_of_annotated_types() {
+        let code = compile_str("let a: Int = 1");
+        assert_eq!(
+            code.ops,
+            vec![Op::Const(0), Op::Check { ty: Type::Int, what: 0 }, Op::Store { slot: 0 }, Op::Unit, Op::Return],
+        );
+        assert_eq!(code.strings, vec!["binding ‘a’".to_string()]);
+
+        let parse = crate::parse("fun f x -> Bool => f x").unwrap();
+        let resolution = resolver::resolve(parse.statements(), &Env::default());
+        let Stmt::FuncDef { func, .. } = &resolution.stmts[0] else { unreachable!() };
+        assert_eq!(
+            compile_function(func).ops,
+            vec![
+                Op::Load { depth: 0, slot: 0, name: 0 },
+                Op::Call { depth: 1, slot: 0, argc: 1, name: 1, nesting: 2 },
+                Op::Check { ty: Type::Bool, what: 2 },
+                Op::Return,
+            ],
+        );
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * Conversions between Rust values and moye values, used to pass arguments to
+ * and results from host functions
+ */
+
+use crate::arith;
+use crate::val::Val;
+use num_bigint::BigInt;
+use std::collections::{BTreeMap, HashMap};
+
+pub trait IntoVal {
+    fn into_val(self) -> Result<Val, String>;
+}
+
+pub trait FromVal: Sized {
+    fn from_val(val: Val) -> Result<Self, String>;
+}
+
+fn type_mismatch<T>(expected: &str, val: &Val) -> Result<T, String> {
+    Err(format!("expected {}, found {}", expected, val.type_name()))
+}
+
+impl IntoVal for Val {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(self)
+    }
+}
+
+impl FromVal for Val {
+    fn from_val(val: Val) -> Result<Self, String> {
+        Ok(val)
+    }
+}
+
+impl IntoVal for () {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Unit)
+    }
+}
+
+impl FromVal for () {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Unit => Ok(()),
+            other => type_mismatch("unit", &other),
+        }
+    }
+}
+
+impl IntoVal for i32 {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Number(i64::from(self)))
+    }
+}
+
+impl FromVal for i32 {
+    fn from_val(val: Val) -> Result<Self, String> {
+        let n = i64::from_val(val)?;
+        i32::try_from(n).map_err(|_| format!("number {} is out of range", n))
+    }
+}
+
+impl IntoVal for i64 {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Number(self))
+    }
+}
+
+impl FromVal for i64 {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Number(n) => Ok(n),
+            Val::BigInt(n) => Err(format!("number {} is out of range", n)),
+            other => type_mismatch("int", &other),
+        }
+    }
+}
+
+impl IntoVal for BigInt {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(arith::normalize(self))
+    }
+}
+
+impl FromVal for BigInt {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Number(n) => Ok(BigInt::from(n)),
+            Val::BigInt(n) => Ok(n),
+            other => type_mismatch("int", &other),
+        }
+    }
+}
+
+impl IntoVal for f64 {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Float(self))
+    }
+}
+
+/*
+ * Integers are accepted where floats are expected, as in mixed arithmetic
+ */
+impl FromVal for f64 {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Float(f) => Ok(f),
+            Val::Number(_) | Val::BigInt(_) => Ok(arith::to_float(&val).unwrap()),
+            other => type_mismatch("float", &other),
+        }
+    }
+}
+
+impl IntoVal for bool {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Bool(self))
+    }
+}
+
+impl FromVal for bool {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Bool(b) => Ok(b),
+            other => type_mismatch("bool", &other),
+        }
+    }
+}
+
+impl IntoVal for String {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Str(self))
+    }
+}
+
+impl IntoVal for &str {
+    fn into_val(self) -> Result<Val, String> {
+        Ok(Val::Str(self.to_string()))
+    }
+}
+
+impl FromVal for String {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Str(s) => Ok(s),
+            other => type_mismatch("string", &other),
+        }
+    }
+}
+
+impl<T: IntoVal> IntoVal for Vec<T> {
+    fn into_val(self) -> Result<Val, String> {
+        self.into_iter()
+            .map(IntoVal::into_val)
+            .collect::<Result<_, _>>()
+            .map(Val::List)
+    }
+}
+
+impl<T: FromVal> FromVal for Vec<T> {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::List(items) => items.into_iter().map(T::from_val).collect(),
+            other => type_mismatch("list", &other),
+        }
+    }
+}
+
+/*
+ * `None` is represented as `Unit`, so `Option<()>` cannot tell `Some(())`
+ * apart from `None`
+ */
+impl<T: IntoVal> IntoVal for Option<T> {
+    fn into_val(self) -> Result<Val, String> {
+        match self {
+            Some(inner) => inner.into_val(),
+            None => Ok(Val::Unit),
+        }
+    }
+}
+
+impl<T: FromVal> FromVal for Option<T> {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Unit => Ok(None),
+            other => T::from_val(other).map(Some),
+        }
+    }
+}
+
+impl<T: IntoVal> IntoVal for HashMap<String, T> {
+    fn into_val(self) -> Result<Val, String> {
+        self.into_iter()
+            .map(|(key, value)| Ok((key, value.into_val()?)))
+            .collect::<Result<BTreeMap<_, _>, String>>()
+            .map(Val::Map)
+    }
+}
+
+impl<T: FromVal> FromVal for HashMap<String, T> {
+    fn from_val(val: Val) -> Result<Self, String> {
+        match val {
+            Val::Map(entries) => entries
+                .into_iter()
+                .map(|(key, value)| Ok((key, T::from_val(value)?)))
+                .collect(),
+            other => type_mismatch("map", &other),
+        }
+    }
+}
+
+/*
+ * Host functions report failures by returning `Err`, which surfaces as an
+ * evaluation error at the call site
+ */
+impl<T: IntoVal> IntoVal for Result<T, String> {
+    fn into_val(self) -> Result<Val, String> {
+        self.and_then(IntoVal::into_val)
+    }
+}
+
+macro_rules! impl_tuple_conversions {
+    ($len:literal => $($name:ident),+) => {
+        impl<$($name: IntoVal),+> IntoVal for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_val(self) -> Result<Val, String> {
+                let ($($name,)+) = self;
+                Ok(Val::List(vec![$($name.into_val()?),+]))
+            }
+        }
+
+        impl<$($name: FromVal),+> FromVal for ($($name,)+) {
+            fn from_val(val: Val) -> Result<Self, String> {
+                match val {
+                    Val::List(items) if items.len() == $len => {
+                        let mut items = items.into_iter();
+                        Ok(($($name::from_val(items.next().unwrap())?,)+))
+                    },
+                    Val::List(items) => Err(format!(
+                        "expected list of {} items, found {} items",
+                        $len,
+                        items.len(),
+                    )),
+                    other => type_mismatch("list", &other),
+                }
+            }
+        }
+    };
+}
+
+impl_tuple_conversions!(1 => A);
+impl_tuple_conversions!(2 => A, B);
+impl_tuple_conversions!(3 => A, B, C);
+impl_tuple_conversions!(4 => A, B, C, D);
+impl_tuple_conversions!(5 => A, B, C, D, E);
+impl_tuple_conversions!(6 => A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_number() {
+        assert_eq!(7.into_val(), Ok(Val::Number(7)));
+        assert_eq!(i32::from_val(Val::Number(7)), Ok(7));
+    }
+
+    #[test]
+    fn convert_i64() {
+        assert_eq!(i64::MAX.into_val(), Ok(Val::Number(i64::MAX)));
+        assert_eq!(i64::from_val(Val::Number(-42)), Ok(-42));
+    }
+
+    #[test]
+    fn cannot_convert_number_out_of_range_to_i32() {
+        assert_eq!(
+            i32::from_val(Val::Number(i64::MAX)),
+            Err("number 9223372036854775807 is out of range".to_string()),
+        );
+    }
+
+    #[test]
+    fn cannot_convert_big_int_to_i64() {
+        let n: BigInt = "10000000000000000000".parse().unwrap();
+        assert_eq!(
+            i64::from_val(Val::BigInt(n)),
+            Err("number 10000000000000000000 is out of range".to_string()),
+        );
+    }
+
+    #[test]
+    fn convert_big_int() {
+        let big: BigInt = "10000000000000000000".parse().unwrap();
+        assert_eq!(big.clone().into_val(), Ok(Val::BigInt(big.clone())));
+        assert_eq!(BigInt::from(5).into_val(), Ok(Val::Number(5)));
+        assert_eq!(BigInt::from_val(Val::Number(5)), Ok(BigInt::from(5)));
+    }
+
+    #[test]
+    fn convert_float() {
+        assert_eq!(0.5.into_val(), Ok(Val::Float(0.5)));
+        assert_eq!(f64::from_val(Val::Float(0.5)), Ok(0.5));
+        assert_eq!(f64::from_val(Val::Number(2)), Ok(2.0));
+        assert_eq!(f64::from_val(Val::Unit), Err("expected float, found unit".to_string()));
+    }
+
+    #[test]
+    fn convert_strings() {
+        assert_eq!("abc".into_val(), Ok(Val::Str("abc".to_string())));
+        assert_eq!(String::from_val(Val::Str("abc".to_string())), Ok("abc".to_string()));
+    }
+
+    #[test]
+    fn cannot_convert_number_to_string() {
+        assert_eq!(
+            String::from_val(Val::Number(1)),
+            Err("expected string, found int".to_string()),
+        );
+    }
+
+    #[test]
+    fn convert_vec() {
+        assert_eq!(
+            vec![true, false].into_val(),
+            Ok(Val::List(vec![Val::Bool(true), Val::Bool(false)])),
+        );
+        assert_eq!(
+            Vec::<i32>::from_val(Val::List(vec![Val::Number(1), Val::Number(2)])),
+            Ok(vec![1, 2]),
+        );
+    }
+
+    #[test]
+    fn convert_option() {
+        assert_eq!(None::<i32>.into_val(), Ok(Val::Unit));
+        assert_eq!(Some(3).into_val(), Ok(Val::Number(3)));
+        assert_eq!(Option::<i32>::from_val(Val::Unit), Ok(None));
+        assert_eq!(Option::<i32>::from_val(Val::Number(3)), Ok(Some(3)));
+    }
+
+    #[test]
+    fn convert_hash_map() {
+        let map = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let val = map.clone().into_val().unwrap();
+        assert_eq!(
+            val,
+            Val::Map(BTreeMap::from([
+                ("a".to_string(), Val::Number(1)),
+                ("b".to_string(), Val::Number(2)),
+            ])),
+        );
+        assert_eq!(HashMap::<String, i32>::from_val(val), Ok(map));
+    }
+
+    #[test]
+    fn convert_tuple() {
+        assert_eq!(
+            (1, "x", true).into_val(),
+            Ok(Val::List(vec![Val::Number(1), Val::Str("x".to_string()), Val::Bool(true)])),
+        );
+        assert_eq!(
+            <(i32, String)>::from_val(Val::List(vec![Val::Number(1), Val::Str("x".to_string())])),
+            Ok((1, "x".to_string())),
+        );
+    }
+
+    #[test]
+    fn cannot_convert_tuple_of_wrong_length() {
+        assert_eq!(
+            <(i32, i32)>::from_val(Val::List(vec![Val::Number(1)])),
+            Err("expected list of 2 items, found 1 items".to_string()),
+        );
+    }
+
+    #[test]
+    fn convert_result() {
+        assert_eq!(Ok::<_, String>(1).into_val(), Ok(Val::Number(1)));
+        assert_eq!(
+            Err::<i32, _>("bad input".to_string()).into_val(),
+            Err("bad input".to_string()),
+        );
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * Syntax errors. Inside the parser an error is a position together with
+ * either the set of tokens that would have been accepted there or a message;
+ * when alternatives fail, the error that got furthest into the input wins.
+ * `parse` turns them into diagnostics with line and column numbers
+ */
+
+use crate::lexer::{Span, Token, TokenKind};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SyntaxError {
+    pub(crate) span: Span,
+    kind: SyntaxErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SyntaxErrorKind {
+    Expected { expected: BTreeSet<String>, found: String },
+    Message(String),
+}
+
+impl SyntaxError {
+    pub(crate) fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            kind: SyntaxErrorKind::Message(message.into()),
+        }
+    }
+
+    /// `expected` are descriptions such as `identifier`; punctuation and
+    /// keywords are described by `token`.
+    pub(crate) fn expected<S: Into<String>>(expected: impl IntoIterator<Item = S>, found: &Token) -> Self {
+        let found_description = match found.kind {
+            TokenKind::Eof => found.kind.to_string(),
+            _ => token(&found.kind),
+        };
+        Self {
+            span: found.span,
+            kind: SyntaxErrorKind::Expected {
+                expected: expected.into_iter().map(Into::into).collect(),
+                found: found_description,
+            },
+        }
+    }
+
+    // Keeps whichever error is further into the input. Errors at the same
+    // position combine their expected tokens, but a message is more specific
+    // than any set of expected tokens.
+    pub(crate) fn merge(self, other: Self) -> Self {
+        if other.span.start != self.span.start {
+            return if other.span.start > self.span.start { other } else { self };
+        }
+
+        match (self.kind, other.kind) {
+            (
+                SyntaxErrorKind::Expected { mut expected, found },
+                SyntaxErrorKind::Expected { expected: other_expected, .. },
+            ) => {
+                expected.extend(other_expected);
+                Self {
+                    span: self.span,
+                    kind: SyntaxErrorKind::Expected { expected, found },
+                }
+            },
+            (SyntaxErrorKind::Expected { .. }, message) | (message, _) => Self {
+                span: self.span,
+                kind: message,
+            },
+        }
+    }
+}
+
+// Describes punctuation and keywords in error messages.
+pub(crate) fn token(kind: &TokenKind) -> String {
+    format!("‘{}’", kind)
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SyntaxErrorKind::Message(message) => write!(f, "{}", message),
+            SyntaxErrorKind::Expected { expected, found } => {
+                write!(f, "expected ")?;
+                for (idx, item) in expected.iter().enumerate() {
+                    if idx == 0 {
+                        write!(f, "{}", item)?;
+                    } else if idx == expected.len() - 1 {
+                        write!(f, " or {}", item)?;
+                    } else {
+                        write!(f, ", {}", item)?;
+                    }
+                }
+                write!(f, ", found {}", found)
+            },
+        }
+    }
+}
+
+/// An error or warning at a position in the source code. Lines and columns
+/// start at 1; columns count characters, not bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Code that works, but probably not as intended.
+    Warning,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(source: &str, error: &SyntaxError) -> Self {
+        Self::at(source, error.span, Severity::Error, error.to_string())
+    }
+
+    pub(crate) fn at(source: &str, span: Span, severity: Severity, message: String) -> Self {
+        let before = &source[..span.start];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            severity,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "{}:{}: {}", self.line, self.column, self.message),
+            Severity::Warning => write!(f, "{}:{}: warning: {}", self.line, self.column, self.message),
+        }
+    }
+}
+
+/// Every syntax error found in the input, in source order. They are all
+/// errors rather than warnings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ParseError {
+    pub(crate) fn new(source: &str, errors: &[SyntaxError]) -> Self {
+        Self {
+            diagnostics: errors.iter().map(|error| Diagnostic::new(source, error)).collect(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, diagnostic) in self.diagnostics.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -1,69 +1,604 @@
 use crate::val::Val;
-use std::collections::HashMap;
-use crate::statements::Statement;
+use std::collections::{HashMap, HashSet};
+use crate::ast::Statement;
+use crate::native::IntoNativeFunc;
+use crate::limits::{Budget, Limits};
+use crate::cancel::CancelToken;
+use crate::error::Error;
+use crate::resolved::{self, Frame, Func, Slot, Stmt};
+use crate::resolver;
+use crate::compiler;
+use crate::program::Program;
+use crate::vm::{self, Engine};
+use std::rc::Rc;
 
+/// The bindings and functions of the host and of top-level moye code.
+/// Definitions live in the slots of a frame; code referring to a name is
+/// resolved to its slot before it runs.
+///
+/// An `Env` is tied to the thread that created it: it shares its values and
+/// functions through `Rc`s, and so is neither `Send` nor `Sync`. To stop an
+/// evaluation from another thread, create the `Env` on the thread that
+/// evaluates, and keep a clone of the `CancelToken` it evaluates with.
 #[derive(Debug, PartialEq, Default)]
-pub struct Env<'parent> {
-    named: HashMap<String, NamedInfo>,
-    parent: Option<&'parent Self>,
+pub struct Env {
+    globals: Rc<Frame>,
+    /// The slot of every name in `globals` that is still visible. Redefining a
+    /// name in moye code takes a new slot, so that functions defined earlier
+    /// keep seeing the old definition, unless no function refers to the old
+    /// slot.
+    names: HashMap<String, usize>,
+    /// The slots that functions defined in moye code refer to.
+    captured: HashSet<usize>,
+    budget: Budget,
+    engine: Engine,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum NamedInfo {
-    Binding(Val),
-    Func { params: Vec<String>, body: Statement },
-}
+impl Env {
+    /// Defines (or redefines) a binding in this environment, making it
+    /// visible to code evaluated afterwards. Functions already defined in
+    /// moye code that use the binding see the new value.
+    pub fn store_binding(&mut self, name: impl Into<String>, value: Val) {
+        self.define(name.into(), Slot::Val(value));
+    }
 
-impl NamedInfo {
-    fn into_binding(self) -> Option<Val> {
-        if let Self::Binding(val) = self {
-            Some(val)
-        } else {
-            None
+    /// Removes a binding and returns its value. Functions are left alone.
+    pub fn remove_binding(&mut self, name: &str) -> Option<Val> {
+        let slot = *self.names.get(name)?;
+        if !matches!(self.globals.get(slot), Slot::Val(_)) {
+            return None;
+        }
+        self.names.remove(name);
+        match self.globals.set(slot, Slot::Empty) {
+            Slot::Val(val) => Some(val),
+            _ => unreachable!("the slot was checked to hold a binding"),
         }
     }
 
-    fn into_func(self) -> Option<(Vec<String>, Statement)> {
-        if let Self::Func { params, body } = self {
-            Some((params, body))
-        } else {
-            None
+    /// Makes a Rust function callable from moye code under `name`, e.g.
+    /// `env.register_fn("greet", |name: String| format!("hi {}", name))`.
+    /// Arguments are converted with `FromVal` and checked before the
+    /// function runs; the result is converted back with `IntoVal`.
+    pub fn register_fn<Args>(&mut self, name: impl Into<String>, func: impl IntoNativeFunc<Args>) {
+        let native = func.into_native_func();
+        self.define(name.into(), Slot::Func(Func::Native(native)));
+    }
+
+    pub fn get_binding(&self, name: &str) -> Result<Val, Error> {
+        match self.global(name) {
+            Some((_, Slot::Val(val))) => Ok(val),
+            _ => Err(resolved::undefined("binding", name)),
         }
     }
-}
 
-impl<'parent> Env<'parent> {
-    pub(crate) fn store_binding(&mut self, name: String, value: Val) {
-        self.named.insert(name, NamedInfo::Binding(value));
+    /// Names of all bindings and functions defined in this environment, in
+    /// sorted order. Builtin functions are not included.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.names.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Calls a function defined in moye code (or registered with
+    /// `register_fn`) with already evaluated arguments.
+    pub fn call(&self, name: &str, args: &[Val]) -> Result<Val, Error> {
+        self.call_cancellable(name, args, &CancelToken::default())
+    }
+
+    /// Like `call`, but stops with an error once `cancel_token` is
+    /// cancelled, as `Parse::eval_cancellable` does.
+    pub fn call_cancellable(&self, name: &str, args: &[Val], cancel_token: &CancelToken) -> Result<Val, Error> {
+        self.start_evaluation(cancel_token);
+        match self.global(name) {
+            Some((_, Slot::Func(Func::User(function)))) => match self.engine {
+                Engine::TreeWalker => Func::User(function).call(&self.globals, args.to_vec(), &self.budget),
+                Engine::Bytecode => vm::call(&function, &self.globals, args.to_vec(), &self.budget),
+            },
+            Some((_, Slot::Func(func))) => func.call(&self.globals, args.to_vec(), &self.budget),
+            _ => Err(resolved::undefined("function", name)),
+        }
     }
 
-    pub(crate) fn store_func(&mut self, name: String, params: Vec<String>, body: Statement) {
-        self.named.insert(name, NamedInfo::Func { params, body });
+    /// Limits applied to every evaluation in this environment; see `Limits`
+    /// for the defaults.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget.set_limits(limits);
     }
 
-    pub(crate) fn get_binding(&self, name: &str) -> Result<Val, String> {
-        self.get_named_info(name)
-            .and_then(NamedInfo::into_binding)
-            .ok_or_else(|| format!("binding with name ‘{}’ does not exist", name))
+    pub fn limits(&self) -> Limits {
+        self.budget.limits()
     }
 
-    pub(crate) fn get_func(&self, name: &str) -> Result<(Vec<String>, Statement), String> {
-        self.get_named_info(name)
-            .and_then(NamedInfo::into_func)
-            .ok_or_else(|| format!("function with name ‘{}’ does not exist", name))
+    /// The engine that runs code in this environment; `Engine::Bytecode`
+    /// unless set otherwise.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
     }
 
-    pub(crate) fn create_child(&'parent self) -> Self {
-        Self {
-            named: HashMap::new(),
-            parent: Some(self),
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    // Resolves all of `stmts` before running any of them. Top-level
+    // definitions become visible to later evaluations as they run, so
+    // definitions before an error are kept.
+    pub(crate) fn eval(&mut self, stmts: &[Statement]) -> Result<Val, Error> {
+        let resolution = resolver::resolve(stmts, self);
+        self.globals.grow(resolution.num_globals);
+        self.captured.extend(&resolution.captured);
+
+        let mut val = Val::Unit;
+        for stmt in &resolution.stmts {
+            val = self.run(stmt)?;
+            if let Stmt::BindingDef { name, slot, .. } | Stmt::FuncDef { name, slot, .. } = stmt {
+                self.names.insert(name.clone(), *slot);
+            }
         }
+        Ok(val)
+    }
+
+    // Compiled programs always run on the VM. They don't record which slots
+    // their functions refer to, so none of them are taken over later.
+    pub(crate) fn run_compiled(&mut self, program: &Program) -> Result<Val, Error> {
+        self.globals.grow(program.num_globals);
+        self.captured.extend(0..program.num_globals);
+
+        let mut val = Val::Unit;
+        for stmt in &program.stmts {
+            val = vm::run(Rc::clone(&stmt.code), &self.globals, &self.budget)?;
+            if let Some((name, slot)) = &stmt.defines {
+                self.names.insert(name.clone(), *slot);
+            }
+        }
+        Ok(val)
+    }
+
+    /// The name of every slot of the frame, if it still has one.
+    pub(crate) fn slot_names(&self) -> Vec<Option<String>> {
+        let mut names = vec![None; self.num_globals()];
+        for (name, &slot) in &self.names {
+            names[slot] = Some(name.clone());
+        }
+        names
+    }
+
+    // Expressions can't define anything at the top level.
+    #[cfg(test)]
+    pub(crate) fn eval_expression(&self, expr: &crate::ast::Expression) -> Result<Val, Error> {
+        self.run(&Stmt::Expr(resolver::resolve_expression(expr, self), expr.span()))
+    }
+
+    /// The slot of a name defined at the top level, and what it holds.
+    pub(crate) fn global(&self, name: &str) -> Option<(usize, Slot)> {
+        let slot = *self.names.get(name)?;
+        Some((slot, self.globals.get(slot)))
+    }
+
+    /// The slot of a name defined at the top level, if no function refers
+    /// to it, so that a redefinition can take it over.
+    pub(crate) fn reusable_slot(&self, name: &str) -> Option<usize> {
+        let slot = *self.names.get(name)?;
+        (!self.captured.contains(&slot)).then_some(slot)
+    }
+
+    pub(crate) fn num_globals(&self) -> usize {
+        self.globals.len()
+    }
+
+    // Every evaluation gets the token it was started with, so one that was
+    // cancelled doesn't stop the next.
+    pub(crate) fn start_evaluation(&self, cancel_token: &CancelToken) {
+        self.budget.set_cancel_token(cancel_token.clone());
+        self.budget.start();
+    }
+
+    fn run(&self, stmt: &Stmt) -> Result<Val, Error> {
+        match self.engine {
+            Engine::TreeWalker => stmt.eval(&self.globals, &self.budget),
+            Engine::Bytecode => vm::run(Rc::new(compiler::compile(stmt)), &self.globals, &self.budget),
+        }
+    }
+
+    // Host definitions replace the value of an existing name in place.
+    fn define(&mut self, name: String, value: Slot) {
+        match self.names.get(&name) {
+            Some(&slot) => {
+                self.globals.set(slot, value);
+            },
+            None => {
+                let slot = self.globals.push(value);
+                self.names.insert(name, slot);
+            },
+        }
+    }
+}
+
+/// Runs `run` on a fresh `Env` for each engine, and checks that the engines
+/// agree on the result and on the number of steps its last evaluation took.
+#[cfg(test)]
+pub(crate) fn on_engines(run: impl Fn(&mut Env) -> Result<Val, Error>) -> Result<Val, Error> {
+    let [(tree_walker, tree_walker_steps), (bytecode, bytecode_steps)] =
+        [Engine::TreeWalker, Engine::Bytecode].map(|engine| {
+            let mut env = Env { engine, ..Env::default() };
+            let val = run(&mut env);
+            (val, env.budget.steps())
+        });
+
+    assert_eq!(tree_walker, bytecode, "the engines disagree");
+    // Steps are counted in a different order, so an error can stop the
+    // engines after different numbers of steps.
+    if bytecode.is_ok() {
+        assert_eq!(tree_walker_steps, bytecode_steps, "the engines took different numbers of steps");
+    }
+    bytecode
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::limits::DEFAULT_MAX_CALL_NESTING;
+    use crate::lexer::Span;
+
+    #[test]
+    fn store_and_get_binding() {
+        let mut env = Env::default();
+        env.store_binding("answer", Val::Number(42));
+        assert_eq!(env.get_binding("answer"), Ok(Val::Number(42)));
+    }
+
+    #[test]
+    fn remove_binding() {
+        let mut env = Env::default();
+        env.store_binding("gone", Val::Unit);
+        assert_eq!(env.remove_binding("gone"), Some(Val::Unit));
+        assert_eq!(env.remove_binding("gone"), None);
+        assert_eq!(
+            env.get_binding("gone"),
+            Err(Error::new(ErrorKind::Undefined, "binding with name ‘gone’ does not exist")),
+        );
+    }
+
+    #[test]
+    fn do_not_remove_func_as_binding() {
+        let mut env = Env::default();
+        env.register_fn("one", || 1);
+        assert_eq!(env.remove_binding("one"), None);
+        assert_eq!(env.names(), vec!["one".to_string()]);
+    }
+
+    #[test]
+    fn list_names_of_host_and_moye_definitions() {
+        let mut env = Env::default();
+        env.store_binding("b", Val::Number(1));
+        env.register_fn("c", || 1);
+        crate::parse("let a = 2\nlet b = 3\n{ let inner = 4 }").unwrap().eval(&mut env).unwrap();
+        assert_eq!(env.names(), vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(env.get_binding("b"), Ok(Val::Number(3)));
+    }
+
+    // Evaluates `setup` and then `source` in the same `Env`, on both engines.
+    fn eval_after(setup: &str, source: &str, limits: Limits) -> Result<Val, String> {
+        on_engines(|env| {
+            env.set_limits(limits);
+            crate::parse(setup).unwrap().eval(env)?;
+            crate::parse(source).unwrap().eval(env)
+        })
+        .map_err(|error| error.message().to_string())
+    }
+
+    #[test]
+    fn keep_earlier_definitions_for_functions_defined_before_redefinition() {
+        assert_eq!(eval_after("let a = 1\nfun f => a\nlet a = 2", "f + a", Limits::default()), Ok(Val::Number(3)));
+    }
+
+    #[test]
+    fn reuse_slots_of_redefined_names_no_function_refers_to() {
+        let result = on_engines(|env| {
+            crate::parse("let result = 1\nfun f x => x").unwrap().eval(env)?;
+            let num_globals = env.num_globals();
+            for _ in 0..1000 {
+                crate::parse("let result = 1\nfun f x => x + 1\nlet result = f result").unwrap().eval(env)?;
+            }
+            assert_eq!(env.num_globals(), num_globals);
+            env.get_binding("result")
+        });
+        assert_eq!(result, Ok(Val::Number(2)));
+    }
+
+    #[test]
+    fn show_bindings_updated_by_host_to_functions() {
+        let over = |limit: Option<i64>| {
+            on_engines(|env| {
+                env.store_binding("limit", Val::Number(1));
+                crate::parse("fun over x => x > limit").unwrap().eval(env)?;
+                match limit {
+                    Some(limit) => env.store_binding("limit", Val::Number(limit)),
+                    None => drop(env.remove_binding("limit")),
+                }
+                env.call("over", &[Val::Number(5)])
+            })
+        };
+        assert_eq!(over(Some(10)), Ok(Val::Bool(false)));
+        assert_eq!(
+            over(None).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Undefined, "binding with name ‘limit’ does not exist".to_string())),
+        );
+    }
+
+    #[test]
+    fn keep_definitions_before_error() {
+        let mut env = Env::default();
+        assert_eq!(
+            crate::parse("let a = 1\nlet b = a / 0\nlet c = 3").unwrap().eval(&mut env),
+            Err(Error::new(ErrorKind::DivisionByZero, "division by zero").within(Some(Span { start: 10, end: 23 }))),
+        );
+        assert_eq!(env.names(), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn call_func_defined_in_moye() {
+        let score = on_engines(|env| {
+            crate::parse("fun score x => x * 10").unwrap().eval(env)?;
+            env.call("score", &[Val::Number(3)])
+        });
+        assert_eq!(score, Ok(Val::Number(30)));
+    }
+
+    #[test]
+    fn call_native_func() {
+        let mut env = Env::default();
+        env.register_fn("greet", |name: String| format!("hi {}", name));
+        assert_eq!(
+            env.call("greet", &[Val::Str("bob".to_string())]),
+            Ok(Val::Str("hi bob".to_string())),
+        );
+    }
+
+    #[test]
+    fn call_with_wrong_number_of_args() {
+        let mut env = Env::default();
+        env.register_fn("one", || 1);
+        assert_eq!(
+            env.call("one", &[Val::Unit]),
+            Err(Error::new(ErrorKind::Arity, "expected 0 parameters, got 1")),
+        );
+    }
+
+    #[test]
+    fn call_builtin() {
+        let mut env = Env::default();
+        assert_eq!(crate::parse("round 2.5").unwrap().eval(&mut env), Ok(Val::Number(3)));
+        assert_eq!(crate::parse("float 7").unwrap().eval(&mut env), Ok(Val::Float(7.0)));
+        assert!(env.names().is_empty());
+    }
+
+    #[test]
+    fn shadow_builtin() {
+        let mut env = Env::default();
+        crate::parse("fun int x => 0").unwrap().eval(&mut env).unwrap();
+        assert_eq!(crate::parse("int 2.5").unwrap().eval(&mut env), Ok(Val::Number(0)));
+    }
+
+    #[test]
+    fn limit_fuel() {
+        let limits = Limits { fuel: Some(3), ..Limits::default() };
+        assert_eq!(eval_after("", "1 + 2", limits), Ok(Val::Number(3)));
+        assert_eq!(
+            eval_after("", "{ 1 + 2 }", limits),
+            Err("evaluation ran out of fuel after 3 steps".to_string()),
+        );
+    }
+
+    #[test]
+    fn limit_call_depth_of_infinite_recursion() {
+        assert_eq!(
+            eval_after("fun f x => { f x } + 1", "f 1", Limits::default()),
+            Err("maximum call depth of 100 exceeded".to_string()),
+        );
+    }
+
+    // Tests run on threads with a stack of 2 MiB, in debug builds.
+    #[test]
+    fn limit_call_depth_before_the_stack_overflows() {
+        const COUNT: &str = "fun count x => { if x == 0 then 0 else 1 + { count x - 1 } }";
+        assert_eq!(eval_after(COUNT, "count 99", Limits::default()), Ok(Val::Number(99)));
+        assert_eq!(eval_after(COUNT, "count 200", Limits::default()), Err("maximum call depth of 100 exceeded".to_string()));
+    }
+
+    #[test]
+    fn limit_nesting_of_calls_before_the_stack_overflows() {
+        const COUNT: &str = "fun count x -> Int => if x == 0 then 0 else 1 + { count x - 1 }";
+        let limits = Limits { max_call_depth: None, ..Limits::default() };
+        assert_eq!(eval_after(COUNT, "count 100", limits), Ok(Val::Number(100)));
+        assert_eq!(
+            eval_after(COUNT, "count 100_000", limits),
+            Err(format!("maximum call nesting of {} exceeded", DEFAULT_MAX_CALL_NESTING)),
+        );
+    }
+
+    // Only the tree walker takes stack of the host for calls.
+    #[test]
+    fn run_deep_recursion_without_limits_on_vm() {
+        let mut env = Env::default();
+        env.set_limits(Limits::unlimited());
+        assert_eq!(
+            crate::parse("fun count x -> Int => if x == 0 then 0 else 1 + { count x - 1 }\ncount 100_000")
+                .unwrap()
+                .eval(&mut env),
+            Ok(Val::Number(100_000)),
+        );
+    }
+
+    #[test]
+    fn limit_call_depth_for_calls_from_host() {
+        let mut env = Env::default();
+        env.set_limits(Limits { max_call_depth: Some(2), ..Limits::default() });
+        crate::parse("fun f x => { f x } + 1").unwrap().eval(&mut env).unwrap();
+        assert_eq!(
+            env.call("f", &[Val::Unit]).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Limit, "maximum call depth of 2 exceeded".to_string())),
+        );
+    }
+
+    #[test]
+    fn run_tail_calls_in_constant_space() {
+        const SUM: &str = "fun sum n acc => if n == 0 then acc else { let m = n - 1\nsum { m } { acc + n } }";
+        assert_eq!(eval_after(SUM, "sum 100_000 0", Limits::default()), Ok(Val::Number(5_000_050_000)));
+        let from_host = on_engines(|env| {
+            crate::parse(SUM).unwrap().eval(env)?;
+            env.call("sum", &[Val::Number(100_000), Val::Number(0)])
+        });
+        assert_eq!(from_host, Ok(Val::Number(5_000_050_000)));
+    }
+
+    #[test]
+    fn call_functions_defined_later() {
+        const EVEN_ODD: &str = "fun even n => if n == 0 then true else odd { n - 1 }\n\
+                                fun odd n => if n == 0 then false else even { n - 1 }";
+        assert_eq!(eval_after("", "fun g x => h x\nfun h x => x * 2\ng 4", Limits::default()), Ok(Val::Number(8)));
+        assert_eq!(eval_after(EVEN_ODD, "even 10", Limits::default()), Ok(Val::Bool(true)));
+        assert_eq!(eval_after(EVEN_ODD, "odd 7", Limits::default()), Ok(Val::Bool(true)));
+        assert_eq!(
+            eval_after("", "fun f => not_yet\nlet a = f\nfun not_yet => 1", Limits::default()),
+            Err("function with name ‘not_yet’ does not exist".to_string()),
+        );
+    }
+
+    #[test]
+    fn use_bindings_defined_later_in_functions() {
+        assert_eq!(eval_after("", "fun f => later + 1\nlet later = 1\nf", Limits::default()), Ok(Val::Number(2)));
+        assert_eq!(
+            eval_after("", "fun g => too_late\ng\nlet too_late = 1", Limits::default()),
+            Err("binding with name ‘too_late’ does not exist".to_string()),
+        );
+    }
+
+    #[test]
+    fn enforce_type_annotations() {
+        const DEFS: &str = "fun half (x: Float) -> Float => x / 2\nfun check (b: Bool) -> Int => if b then 1 else b";
+        let eval = |source| eval_after(DEFS, source, Limits::default());
+        assert_eq!(eval("let a: Float = half 3.0"), Ok(Val::Unit));
+        assert_eq!(eval("half 3"), Err("parameter ‘x’ of ‘half’ must be Float, found int".to_string()));
+        assert_eq!(eval("check false"), Err("the value of ‘check’ must be Int, found bool".to_string()));
+        assert_eq!(
+            eval("let b: Int = half 3.0\nb"),
+            Err("binding ‘b’ must be Int, found float".to_string()),
+        );
+
+        let mut env = Env::default();
+        crate::parse(DEFS).unwrap().eval(&mut env).unwrap();
+        assert_eq!(
+            env.call("half", &[Val::Str("3".to_string())]),
+            Err(Error::new(ErrorKind::Type, "parameter ‘x’ of ‘half’ must be Float, found string")),
+        );
+        assert!(crate::parse("let b: Int = half 3.0").unwrap().eval(&mut env).is_err());
+        assert_eq!(env.get_binding("b"), Err(Error::new(ErrorKind::Undefined, "binding with name ‘b’ does not exist")));
+    }
+
+    // The value of a tail call has to be checked against the return type
+    // of the caller, so the call nests.
+    #[test]
+    fn check_values_of_tail_calls() {
+        const DEFS: &str = "fun id x => x\nfun f (x: Int) -> Int => id x\nfun g n -> Int => if n == 0 then 0 else g n - 1";
+        let limits = Limits { max_call_depth: Some(10), ..Limits::default() };
+        assert_eq!(eval_after(DEFS, "f 1", limits), Ok(Val::Number(1)));
+        assert_eq!(eval_after(DEFS, "g 20", limits), Err("maximum call depth of 10 exceeded".to_string()));
+    }
+
+    #[test]
+    fn limit_fuel_of_infinite_tail_calls() {
+        assert_eq!(
+            eval_after("fun f x => f x", "f 1", Limits { fuel: Some(1000), ..Limits::default() }),
+            Err("evaluation ran out of fuel after 1000 steps".to_string()),
+        );
+    }
+
+    #[test]
+    fn limit_time() {
+        let mut env = Env::default();
+        env.set_limits(Limits { timeout: Some(std::time::Duration::ZERO), ..Limits::unlimited() });
+        crate::parse("fun f x => f x").unwrap().eval(&mut env).unwrap();
+        assert_eq!(
+            crate::parse("f 1").unwrap().eval(&mut env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Limit, "evaluation timed out after 0ns".to_string())),
+        );
+    }
+
+    #[test]
+    fn cancel_during_evaluation() {
+        let mut env = Env::default();
+        let token = CancelToken::new();
+        env.set_limits(Limits::unlimited());
+        let canceller = token.clone();
+        env.register_fn("cancel", move || canceller.cancel());
+        crate::parse("fun f x => f x").unwrap().eval(&mut env).unwrap();
+        let parse = crate::parse("f cancel").unwrap();
+        assert_eq!(
+            parse.eval_cancellable(&mut env, &token).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Cancelled, "evaluation was cancelled".to_string())),
+        );
+        // Other evaluations don't see the token.
+        assert_eq!(crate::parse("1 + 1").unwrap().eval(&mut env), Ok(Val::Number(2)));
+    }
+
+    #[test]
+    fn cancel_from_another_thread() {
+        let token = CancelToken::new();
+        let worker = {
+            let token = token.clone();
+            std::thread::spawn(move || {
+                let mut env = Env::default();
+                env.set_limits(Limits::unlimited());
+                crate::parse("fun f x => f x\nf 1")
+                    .unwrap()
+                    .eval_cancellable(&mut env, &token)
+                    .map_err(|error| error.kind())
+            })
+        };
+        token.cancel();
+        assert_eq!(worker.join().unwrap(), Err(ErrorKind::Cancelled));
+    }
+
+    #[test]
+    fn cancel_call_from_host() {
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let result = on_engines(|env| {
+            env.set_limits(Limits::unlimited());
+            let canceller = canceller.clone();
+            env.register_fn("cancel", move || canceller.cancel());
+            crate::parse("fun f x => f x\nfun g => f cancel").unwrap().eval(env)?;
+            token.reset();
+            env.call_cancellable("g", &[], &token)
+        });
+        assert_eq!(
+            result.map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Cancelled, "evaluation was cancelled".to_string())),
+        );
+    }
+
+    #[test]
+    fn call_non_existent_func() {
+        assert_eq!(
+            Env::default().call("nope", &[]),
+            Err(Error::new(ErrorKind::Undefined, "function with name ‘nope’ does not exist")),
+        );
     }
 
-    fn get_named_info(&self, name: &str) -> Option<NamedInfo> {
-        self.named
-            .get(name)
-            .cloned()
-            .or_else(|| self.parent.and_then(|parent| parent.get_named_info(name)))
+    // Calls, including calls in tail position, are part of the statement
+    // that makes them until the body of the callee starts.
+    #[test]
+    fn blame_innermost_statement() {
+        let blamed = |source: &'static str| {
+            let error = on_engines(|env| crate::parse(source).unwrap().eval(env)).unwrap_err();
+            let span = error.span().unwrap();
+            (error.kind(), &source[span.start..span.end])
+        };
+        assert_eq!(blamed("fun f x => {\nlet y = x / 0\ny }\nf 1"), (ErrorKind::DivisionByZero, "let y = x / 0"));
+        assert_eq!(blamed("fun h (n: Int) => n\nfun g x => h x\ng 1.5"), (ErrorKind::Type, "h x"));
+        assert_eq!(blamed("fun k -> Int => {\n1.5 }\nk"), (ErrorKind::Type, "{\n1.5 }"));
+        assert_eq!(blamed("let a = 1\nif a then 1 else 2"), (ErrorKind::Type, "if a then 1 else 2"));
+        assert_eq!(blamed("undefined + 1"), (ErrorKind::Undefined, "undefined + 1"));
     }
 }
@@ -0,0 +1,116 @@
+/*
+ * Errors that stop an evaluation. The kind tells hosts what went wrong
+ * without matching on messages; the span is that of the innermost statement
+ * whose code was running, including a call it made until the body of the
+ * callee starts
+ */
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::lexer::Span;
+use std::fmt;
+
+/// Boxed, so that results stay small: the tree walker nests several of
+/// them on the stack for every call it makes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(Box<Details>);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Details {
+    kind: ErrorKind,
+    span: Option<Span>,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Division or remainder by zero.
+    DivisionByZero,
+    /// A result too large to represent: an int of too many bits, or a float
+    /// that would be infinite or NaN.
+    Overflow,
+    /// An operand outside of what its operation accepts, such as a negative
+    /// exponent or shift amount.
+    InvalidOperand,
+    /// A value of the wrong type for an operator, condition, builtin or
+    /// annotation.
+    Type,
+    /// A name without a definition at the time it is used.
+    Undefined,
+    /// A call with the wrong number of parameters.
+    Arity,
+    /// The evaluation ran out of fuel, nested calls too deeply or timed out.
+    Limit,
+    /// The evaluation was stopped with its `CancelToken`.
+    Cancelled,
+    /// A host function failed, or its parameters or result didn't convert.
+    Host,
+    /// A compiled program was run in an environment with other definitions
+    /// than the one it was compiled for.
+    Incompatible,
+}
+
+impl Error {
+    pub(crate) fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self(Box::new(Details { kind, span: None, message: message.into() }))
+    }
+
+    /// Blames the statement at `span`, if it is known, unless a statement
+    /// inside it was blamed already.
+    pub(crate) fn within(mut self, span: Option<Span>) -> Self {
+        self.0.span = self.0.span.or(span);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.0.kind
+    }
+
+    /// Unknown for errors outside of moye code, such as calling a function
+    /// that doesn't exist with `Env::call`, and for programs loaded with
+    /// `Program::from_bytes`, which don't keep spans.
+    pub fn span(&self) -> Option<Span> {
+        self.0.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// The error at its line and column in `source`, the code that was
+    /// evaluated, if its span is known.
+    pub fn diagnostic(&self, source: &str) -> Option<Diagnostic> {
+        let span = self.span()?;
+        Some(Diagnostic::at(source, span, Severity::Error, self.message().to_string()))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_innermost_span() {
+        let inner = Span { start: 4, end: 9 };
+        let error = Error::new(ErrorKind::DivisionByZero, "division by zero").within(Some(inner));
+        assert_eq!(error.clone().within(Some(Span { start: 0, end: 12 })).span(), Some(inner));
+        assert_eq!(error.within(None).span(), Some(inner));
+    }
+
+    #[test]
+    fn locate_error_in_source() {
+        let error = Error::new(ErrorKind::DivisionByZero, "division by zero").within(Some(Span { start: 12, end: 17 }));
+        assert_eq!(
+            error.diagnostic("let a = 1\nb = a / 0").map(|diagnostic| diagnostic.to_string()),
+            Some("2:3: division by zero".to_string()),
+        );
+        assert_eq!(Error::new(ErrorKind::Host, "failed").diagnostic("f 1"), None);
+    }
+}
@@ -1,157 +1,733 @@
 mod block;
 mod binding_usage;
 mod func_call;
+mod if_else;
 
-pub(crate) use binding_usage::BindingUsage;
-pub(crate) use block::Block;
-pub(crate) use func_call::FuncCall;
+pub use binding_usage::BindingUsage;
+pub use block::Block;
+pub use func_call::FuncCall;
+pub use if_else::IfElse;
 
+#[cfg(test)]
 use crate::env::Env;
+use crate::ast::{NestingChecker, Visitor};
+use crate::diagnostic::{self, SyntaxError};
+use crate::lexer::{Span, Token, TokenKind};
+use crate::limits;
 use crate::utils;
+use num_bigint::BigInt;
+#[cfg(test)]
 use crate::val::Val;
 
+/// An integer literal. A `-` directly in front of the digits is part of the
+/// literal.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Number {
+    pub value: i64,
+    pub span: Span,
+}
+
+/// An integer literal that doesn't fit into a `Number`.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct Number(pub i32);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BigNumber {
+    pub value: BigInt,
+    pub span: Span,
+}
+
+// A `Number`, or a `BigNumber` if it has to be.
+fn new_integer(tokens: &[Token]) -> Result<(&[Token], Expression), SyntaxError> {
+    let (unsigned, sign) = utils::extract_minus(tokens);
+    let TokenKind::Int { literal, radix } = &unsigned[0].kind else {
+        return Err(SyntaxError::expected(["integer"], &unsigned[0]));
+    };
+    let digits = if *radix == 10 { literal.as_str() } else { &literal[2..] };
+    let digits = format!("{}{}", sign, digits.replace('_', ""));
+    let span = tokens[0].span.to(unsigned[0].span);
+    let value = BigInt::parse_bytes(digits.as_bytes(), *radix).expect("the lexer only accepts valid digits");
+    let integer = match i64::try_from(&value) {
+        Ok(value) => Expression::Number(Number { value, span }),
+        Err(_) => Expression::BigNumber(BigNumber { value, span }),
+    };
+    Ok((&unsigned[1..], integer))
+}
 
-impl Number {
-    fn new(s:&str) -> Result<(&str, Self), String> {
-        let (s, number) = utils::extract_digits(s)?;
-        Ok((s, Self(number.parse().unwrap())))
+/// A float literal, which is always finite.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Float {
+    pub value: f64,
+    pub span: Span,
+}
+
+impl Float {
+    fn new(tokens: &[Token]) -> Result<(&[Token], Self), SyntaxError> {
+        let (unsigned, sign) = utils::extract_minus(tokens);
+        let TokenKind::Float(literal) = &unsigned[0].kind else {
+            return Err(SyntaxError::expected(["float"], &unsigned[0]));
+        };
+        let span = tokens[0].span.to(unsigned[0].span);
+        match format!("{}{}", sign, literal.replace('_', "")).parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok((&unsigned[1..], Self { value, span })),
+            _ => Err(SyntaxError::new(span, format!("float literal {}{} is out of range", sign, literal))),
+        }
     }
 }
 
+/// `true` or `false`.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) enum Operations {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bool {
+    pub value: bool,
+    pub span: Span,
+}
+
+impl Bool {
+    fn new(tokens: &[Token]) -> Result<(&[Token], Self), SyntaxError> {
+        let value = match tokens[0].kind {
+            TokenKind::True => true,
+            TokenKind::False => false,
+            _ => {
+                let expected = [TokenKind::True, TokenKind::False];
+                return Err(SyntaxError::expected(expected.iter().map(diagnostic::token), &tokens[0]));
+            },
+        };
+        Ok((&tokens[1..], Self { value, span: tokens[0].span }))
+    }
+}
+
+/// A binary operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Operations {
     Add,
     Sub,
     Mul,
     Div,
+    Rem,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
 }
 
 impl Operations {
-    fn new(s:&str) -> Result<(&str, Self), String> {
-        utils::tag("+", s)
-            .map(|s| (s, Self::Add))
-            .or_else(|_| utils::tag("-", s).map(|s| (s, Self::Sub)))
-            .or_else(|_| utils::tag("*", s).map(|s| (s, Self::Mul)))
-            .or_else(|_| utils::tag("/", s).map(|s| (s, Self::Div)))
+    fn new(tokens: &[Token]) -> Result<(&[Token], Self), SyntaxError> {
+        let op = match tokens[0].kind {
+            TokenKind::Plus => Self::Add,
+            TokenKind::Minus => Self::Sub,
+            TokenKind::Star => Self::Mul,
+            TokenKind::Slash => Self::Div,
+            TokenKind::Percent => Self::Rem,
+            TokenKind::StarStar => Self::Pow,
+            TokenKind::Amp => Self::BitAnd,
+            TokenKind::Pipe => Self::BitOr,
+            TokenKind::Caret => Self::BitXor,
+            TokenKind::Shl => Self::Shl,
+            TokenKind::Shr => Self::Shr,
+            TokenKind::EqEq => Self::Eq,
+            TokenKind::NotEq => Self::Ne,
+            TokenKind::Lt => Self::Lt,
+            TokenKind::Le => Self::Le,
+            TokenKind::Gt => Self::Gt,
+            TokenKind::Ge => Self::Ge,
+            _ => return Err(SyntaxError::expected(Self::expected(), &tokens[0])),
+        };
+        Ok((&tokens[1..], op))
+    }
+
+    // Every binary operator, for error messages.
+    pub(crate) fn expected() -> impl Iterator<Item = String> {
+        [
+            TokenKind::Plus,
+            TokenKind::Minus,
+            TokenKind::Star,
+            TokenKind::Slash,
+            TokenKind::Percent,
+            TokenKind::StarStar,
+            TokenKind::Amp,
+            TokenKind::Pipe,
+            TokenKind::Caret,
+            TokenKind::Shl,
+            TokenKind::Shr,
+            TokenKind::EqEq,
+            TokenKind::NotEq,
+            TokenKind::Lt,
+            TokenKind::Le,
+            TokenKind::Gt,
+            TokenKind::Ge,
+        ]
+        .into_iter()
+        .map(|kind| diagnostic::token(&kind))
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Pow => "**",
+            Self::BitAnd => "&",
+            Self::BitOr => "|",
+            Self::BitXor => "^",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    /// Higher binds tighter: `**`, then `* / %`, `+ -`, `<< >>`, `&`, `^`, `|`
+    /// and finally the comparisons, in the same order as in Python. Unlike in
+    /// Python, unary operators bind tighter than all of them, so `-2 ** 2` is
+    /// `4`.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Pow => 8,
+            Self::Mul | Self::Div | Self::Rem => 7,
+            Self::Add | Self::Sub => 6,
+            Self::Shl | Self::Shr => 5,
+            Self::BitAnd => 4,
+            Self::BitXor => 3,
+            Self::BitOr => 2,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => 1,
+        }
+    }
+
+    /// `2 ** 3 ** 2` is `2 ** (3 ** 2)`; every other operator groups to the
+    /// left.
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, Self::Pow)
+    }
+
+    pub(crate) fn is_bitwise(&self) -> bool {
+        matches!(self, Self::BitAnd | Self::BitOr | Self::BitXor | Self::Shl | Self::Shr)
+    }
+
+    pub(crate) fn is_shift(&self) -> bool {
+        matches!(self, Self::Shl | Self::Shr)
+    }
+
+    pub(crate) fn is_comparison(&self) -> bool {
+        matches!(self, Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum UnaryOperations {
+    Neg,
+    Plus,
+    /// Logical not for bools, bitwise not for ints.
+    Not,
+}
+
+impl UnaryOperations {
+    fn new(tokens: &[Token]) -> Result<(&[Token], Self), SyntaxError> {
+        let op = match tokens[0].kind {
+            TokenKind::Minus => Self::Neg,
+            TokenKind::Plus => Self::Plus,
+            TokenKind::Bang => Self::Not,
+            _ => {
+                let expected = [TokenKind::Minus, TokenKind::Plus, TokenKind::Bang];
+                return Err(SyntaxError::expected(expected.iter().map(diagnostic::token), &tokens[0]));
+            },
+        };
+        Ok((&tokens[1..], op))
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Neg => "-",
+            Self::Plus => "+",
+            Self::Not => "!",
+        }
+    }
+}
+
+/// Anything that evaluates to a value. `Operation` and `Unary` carry the
+/// span of the whole operation, from the first operand or the operator.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) enum Expression {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Expression {
     Number(Number),
-    Operation { lhs: Box<Self>, rhs: Box<Self>, op: Operations },
+    BigNumber(BigNumber),
+    Float(Float),
+    Bool(Bool),
+    Operation { lhs: Box<Self>, rhs: Box<Self>, op: Operations, span: Span },
+    Unary { op: UnaryOperations, operand: Box<Self>, span: Span },
     FuncCall(FuncCall),
     BindingUsage(BindingUsage),
     Block(Block),
+    IfElse(IfElse),
 }
 
 impl Expression {
-    pub(crate) fn new(s:&str) -> Result<(&str, Self), String> {
-        Self::new_operation(s).or_else(|_| Self::new_non_operation(s))
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Number(number) => number.span,
+            Self::BigNumber(number) => number.span,
+            Self::Float(float) => float.span,
+            Self::Bool(boolean) => boolean.span,
+            Self::Operation { span, .. } | Self::Unary { span, .. } => *span,
+            Self::FuncCall(func_call) => func_call.span,
+            Self::BindingUsage(binding_usage) => binding_usage.span,
+            Self::Block(block) => block.span,
+            Self::IfElse(if_else) => if_else.span,
+        }
     }
 
-    fn new_non_operation(s:&str) -> Result<(&str, Self), String> {
-        Self::new_number(s)
-            .or_else(|_| FuncCall::new(s).map(|(s, func_call)| (s, Self::FuncCall(func_call))))
-            .or_else(|_| {
-                BindingUsage::new(s)
-                    .map(|(s, binding_usage)| (s, Self::BindingUsage(binding_usage)))
-            })
-            .or_else(|_| Block::new(s).map(|(s, block)| (s, Self::Block(block))))
-    }
-
-    fn new_operation(s:&str) -> Result<(&str, Self), String> {
-        let (s, lhs) = Self::new_non_operation(s)?;
-        let (s, _) = utils::extract_whitespaces(s);
-        let (s, op) = Operations::new(s)?;
-        let (s, _) = utils::extract_whitespaces(s);
-        let (s, rhs) = Self::new_non_operation(s)?;
-        Ok((s, Self::Operation {
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-            op
-        }))
+    /// `depth` is how many expressions and function definitions the
+    /// expression is nested in. Operands are nested in their operation, and
+    /// the children of other expressions in them.
+    pub(crate) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        Self::new_operation(tokens, 0, depth)
+    }
+
+    fn new_non_operation(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        utils::check_nesting(depth, &tokens[0])?;
+        let (unsigned, _) = utils::extract_minus(tokens);
+        match unsigned[0].kind {
+            TokenKind::Int { .. } => new_integer(tokens),
+            TokenKind::Float(_) => {
+                Float::new(tokens).map(|(tokens, float)| (tokens, Self::Float(float)))
+            },
+            TokenKind::True | TokenKind::False => Bool::new(tokens).map(|(tokens, boolean)| (tokens, Self::Bool(boolean))),
+            TokenKind::Ident(_) if FuncCall::has_params(tokens) => {
+                FuncCall::new(tokens, depth + 1).map(|(tokens, func_call)| (tokens, Self::FuncCall(func_call)))
+            },
+            // Reports that keywords can't be used as bindings.
+            TokenKind::Ident(_) | TokenKind::Then | TokenKind::Else => BindingUsage::new(tokens)
+                .map(|(tokens, binding_usage)| (tokens, Self::BindingUsage(binding_usage))),
+            TokenKind::LBrace => Block::new(tokens, depth + 1).map(|(tokens, block)| (tokens, Self::Block(block))),
+            TokenKind::If => IfElse::new(tokens, depth + 1).map(|(tokens, if_else)| (tokens, Self::IfElse(if_else))),
+            TokenKind::Minus | TokenKind::Plus | TokenKind::Bang => Self::new_unary(tokens, depth),
+            _ => Err(SyntaxError::expected(Self::expected(), &tokens[0])),
+        }
     }
 
-    fn new_number(s:&str) -> Result<(&str, Self), String> {
-        Number::new(s).map(|(s, number)| (s, Self::Number(number)))
+    // Everything an expression can start with, for error messages.
+    pub(crate) fn expected() -> impl Iterator<Item = String> {
+        let tokens = [
+            TokenKind::LBrace,
+            TokenKind::Minus,
+            TokenKind::Plus,
+            TokenKind::Bang,
+            TokenKind::If,
+            TokenKind::True,
+            TokenKind::False,
+        ];
+        ["number".to_string(), "identifier".to_string()]
+            .into_iter()
+            .chain(tokens.into_iter().map(|kind| diagnostic::token(&kind)))
     }
 
-    pub(crate) fn eval(&self, env: &Env) -> Result<Val, String> {
-        match self {
-            Self::Number(Number(n)) => Ok(Val::Number(*n)),
-            Self::Operation { lhs, rhs, op } => {
-                let lhs = lhs.eval(env)?;
-                let rhs = rhs.eval(env)?;
-
-                let (lhs, rhs) = match (lhs, rhs) {
-                    (Val::Number(lhs), Val::Number(rhs)) => (lhs, rhs),
-                    _ => return Err("cannot evaluate operation whose left-hand side and right-hand side are not both numbers".to_string()),
-                };
-
-                let res = match op {
-                    Operations::Add => lhs + rhs,
-                    Operations::Sub => lhs - rhs,
-                    Operations::Mul => lhs * rhs,
-                    Operations::Div => lhs / rhs,
-                };
-
-                Ok(Val::Number(res))
-            },
-            Self::FuncCall(func_call) => func_call.eval(env),
-            Self::BindingUsage(binding_usage) => binding_usage.eval(env),
-            Self::Block(block) => block.eval(env),
+    // Unary operators bind tighter than any binary operator, so `-a * b` is
+    // `(-a) * b` and `-2 ** 2` is `4`.
+    fn new_unary(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        let span = tokens[0].span;
+        let (tokens, op) = UnaryOperations::new(tokens)?;
+        let (tokens, operand) = Self::new_non_operation(tokens, depth + 1)?;
+        Ok((tokens, Self::Unary {
+            op,
+            span: span.to(operand.span()),
+            operand: Box::new(operand),
+        }))
+    }
+
+    // Parses operations whose operators have at least `min_precedence`, by
+    // precedence climbing. Every operator nests the operations before it one
+    // level deeper, without nesting the parser, so how deeply they nest is
+    // only known at the end.
+    fn new_operation(tokens: &[Token], min_precedence: u8, depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        let (mut tokens, mut lhs) = Self::new_non_operation(tokens, depth)?;
+
+        let mut lhs_depth = depth;
+        loop {
+            let (after_op, op) = match Operations::new(tokens) {
+                Ok((after_op, op)) if op.precedence() >= min_precedence => (after_op, op),
+                _ if lhs_depth == depth => return Ok((tokens, lhs)),
+                _ => {
+                    let mut nesting = NestingChecker::new(depth);
+                    nesting.visit_expression(&lhs);
+                    return match nesting.too_deep() {
+                        Some(span) => Err(SyntaxError::new(span, limits::nested_too_deeply())),
+                        None => Ok((tokens, lhs)),
+                    };
+                },
+            };
+            lhs_depth += 1;
+            utils::check_nesting(lhs_depth, &tokens[0])?;
+
+            let next_precedence = if op.is_right_associative() {
+                op.precedence()
+            } else {
+                op.precedence() + 1
+            };
+            let (remainder, rhs) = Self::new_operation(after_op, next_precedence, depth + 1)?;
+
+            tokens = remainder;
+            lhs = Self::Operation {
+                span: lhs.span().to(rhs.span()),
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+                op,
+            };
         }
     }
+
+    // Hand-built trees in tests are evaluated directly.
+    #[cfg(test)]
+    pub(crate) fn eval(&self, env: &Env) -> Result<Val, crate::error::Error> {
+        env.eval_expression(self)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorKind;
+    use crate::lexer::parse_str;
     use crate::statements::Statement;
 
     #[test]
     fn parse_number() {
-        assert_eq!(Number::new("123"), Ok(("", Number(123))));
+        assert_eq!(parse_str(new_integer, "123"), Ok(("", Expression::Number(Number { value: 123, span: Span::default() }))));
+    }
+
+    #[test]
+    fn parse_number_larger_than_32_bits() {
+        assert_eq!(parse_str(new_integer, "9000000000"), Ok(("", Expression::Number(Number { value: 9000000000, span: Span::default() }))));
+    }
+
+    #[test]
+    fn parse_prefixed_numbers() {
+        assert_eq!(parse_str(new_integer, "0xff"), Ok(("", Expression::Number(Number { value: 255, span: Span::default() }))));
+        assert_eq!(parse_str(new_integer, "0o17"), Ok(("", Expression::Number(Number { value: 15, span: Span::default() }))));
+        assert_eq!(parse_str(new_integer, "0b1010"), Ok(("", Expression::Number(Number { value: 10, span: Span::default() }))));
+    }
+
+    #[test]
+    fn parse_number_with_separators() {
+        assert_eq!(parse_str(new_integer, "1_000_000"), Ok(("", Expression::Number(Number { value: 1000000, span: Span::default() }))));
+        assert_eq!(parse_str(Float::new, "1_000.5"), Ok(("", Float { value: 1000.5, span: Span::default() })));
+    }
+
+    #[test]
+    fn parse_prefixed_number_too_large_as_big_number() {
+        assert_eq!(
+            parse_str(new_integer, "0xFFFF_FFFF_FFFF_FFFF"),
+            Ok(("", Expression::BigNumber(BigNumber { value: u64::MAX.into(), span: Span::default() }))),
+        );
+    }
+
+    #[test]
+    fn report_invalid_digit_in_expression() {
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "0b102 + 1"),
+            Err("invalid digit ‘2’ in binary literal".to_string()),
+        );
     }
 
     #[test]
     fn parse_number_as_expr() {
-        assert_eq!(Expression::new("456"), Ok(("", Expression::Number(Number(456)))));
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "456"),
+            Ok(("", Expression::Number(Number { value: 456, span: Span::default() }))),
+        );
+    }
+
+    #[test]
+    fn parse_float() {
+        assert_eq!(parse_str(Float::new, "1.5"), Ok(("", Float { value: 1.5, span: Span::default() })));
+        assert_eq!(parse_str(Float::new, "1e-3"), Ok(("", Float { value: 0.001, span: Span::default() })));
+    }
+
+    #[test]
+    fn cannot_parse_integer_as_float() {
+        assert_eq!(parse_str(Float::new, "15"), Err("expected float, found ‘15’".to_string()));
+    }
+
+    #[test]
+    fn cannot_parse_float_too_large() {
+        assert_eq!(parse_str(Float::new, "1e999"), Err("float literal 1e999 is out of range".to_string()));
+    }
+
+    #[test]
+    fn parse_negative_literals() {
+        assert_eq!(parse_str(new_integer, "-5"), Ok(("", Expression::Number(Number { value: -5, span: Span::default() }))));
+        assert_eq!(parse_str(new_integer, "-0x10"), Ok(("", Expression::Number(Number { value: -16, span: Span::default() }))));
+        assert_eq!(
+            parse_str(new_integer, "-9223372036854775808"),
+            Ok(("", Expression::Number(Number { value: i64::MIN, span: Span::default() }))),
+        );
+        assert_eq!(parse_str(Float::new, "-1.5"), Ok(("", Float { value: -1.5, span: Span::default() })));
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "-7"),
+            Ok(("", Expression::Number(Number { value: -7, span: Span::default() }))),
+        );
+    }
+
+    #[test]
+    fn parse_unary_operators() {
+        assert_eq!(parse_str(UnaryOperations::new, "-"), Ok(("", UnaryOperations::Neg)));
+        assert_eq!(parse_str(UnaryOperations::new, "+"), Ok(("", UnaryOperations::Plus)));
+        assert_eq!(parse_str(UnaryOperations::new, "!"), Ok(("", UnaryOperations::Not)));
+    }
+
+    #[test]
+    fn parse_negated_binding_usage() {
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "-x"),
+            Ok((
+                "",
+                Expression::Unary {
+                    op: UnaryOperations::Neg,
+                    operand: Box::new(Expression::BindingUsage(BindingUsage {
+                        name: "x".to_string(),
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn unary_binds_tighter_than_binary() {
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "!a * 2"),
+            Ok((
+                "",
+                Expression::Operation {
+                    lhs: Box::new(Expression::Unary {
+                        op: UnaryOperations::Not,
+                        operand: Box::new(Expression::BindingUsage(BindingUsage {
+                            name: "a".to_string(),
+                            span: Span::default(),
+                        })),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expression::Number(Number { value: 2, span: Span::default() })),
+                    op: Operations::Mul,
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_subtraction_of_negative_literal() {
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "3 - -2"),
+            Ok((
+                "",
+                Expression::Operation {
+                    lhs: Box::new(Expression::Number(Number { value: 3, span: Span::default() })),
+                    rhs: Box::new(Expression::Number(Number { value: -2, span: Span::default() })),
+                    op: Operations::Sub,
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_subtraction_of_bindings() {
+        for s in ["a - b", "a-b"] {
+            assert_eq!(
+                parse_str(|tokens| Expression::new(tokens, 0), s),
+                Ok((
+                    "",
+                    Expression::Operation {
+                        lhs: Box::new(Expression::BindingUsage(BindingUsage {
+                            name: "a".to_string(),
+                            span: Span::default(),
+                        })),
+                        rhs: Box::new(Expression::BindingUsage(BindingUsage {
+                            name: "b".to_string(),
+                            span: Span::default(),
+                        })),
+                        op: Operations::Sub,
+                        span: Span::default(),
+                    },
+                )),
+            );
+        }
+    }
+
+    #[test]
+    fn eval_unary_operators() {
+        let env = Env::default();
+        let unary = |op, operand| Expression::Unary { op, operand: Box::new(operand), span: Span::default() };
+        assert_eq!(
+            unary(UnaryOperations::Neg, Expression::Float(Float { value: 2.5, span: Span::default() })).eval(&env),
+            Ok(Val::Float(-2.5)),
+        );
+        assert_eq!(
+            unary(UnaryOperations::Plus, Expression::Number(Number { value: 4, span: Span::default() })).eval(&env),
+            Ok(Val::Number(4)),
+        );
+        assert_eq!(
+            unary(UnaryOperations::Not, Expression::Number(Number { value: 0b101, span: Span::default() })).eval(&env),
+            Ok(Val::Number(-6)),
+        );
+    }
+
+    #[test]
+    fn parse_float_as_expr() {
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "2.25"),
+            Ok(("", Expression::Float(Float { value: 2.25, span: Span::default() }))),
+        );
     }
 
     #[test]
     fn parse_addition() {
-        assert_eq!(Operations::new("+"), Ok(("", Operations::Add)));
+        assert_eq!(parse_str(Operations::new, "+"), Ok(("", Operations::Add)));
     }
 
     #[test]
     fn parse_subtraction() {
-        assert_eq!(Operations::new("-"), Ok(("", Operations::Sub)));
+        assert_eq!(parse_str(Operations::new, "-"), Ok(("", Operations::Sub)));
     }
 
     #[test]
     fn parse_multiplication() {
-        assert_eq!(Operations::new("*"), Ok(("", Operations::Mul)));
+        assert_eq!(parse_str(Operations::new, "*"), Ok(("", Operations::Mul)));
     }
 
     #[test]
     fn parse_division() {
-        assert_eq!(Operations::new("/"), Ok(("", Operations::Div)));
+        assert_eq!(parse_str(Operations::new, "/"), Ok(("", Operations::Div)));
+    }
+
+    #[test]
+    fn parse_longer_operators_first() {
+        assert_eq!(parse_str(Operations::new, "**"), Ok(("", Operations::Pow)));
+        assert_eq!(parse_str(Operations::new, "<<"), Ok(("", Operations::Shl)));
+        assert_eq!(parse_str(Operations::new, ">>"), Ok(("", Operations::Shr)));
+        assert_eq!(parse_str(Operations::new, "% "), Ok(("", Operations::Rem)));
+        assert_eq!(parse_str(Operations::new, "^"), Ok(("", Operations::BitXor)));
+        assert_eq!(parse_str(Operations::new, "<="), Ok(("", Operations::Le)));
+        assert_eq!(parse_str(Operations::new, "!="), Ok(("", Operations::Ne)));
+    }
+
+    fn eval_str(s: &str) -> Result<Val, String> {
+        let (rest, expr) = parse_str(|tokens| Expression::new(tokens, 0), s)?;
+        assert_eq!(rest, "");
+        crate::env::on_engines(|env| env.eval_expression(&expr)).map_err(|error| error.message().to_string())
+    }
+
+    #[test]
+    fn chain_operations_by_precedence() {
+        assert_eq!(eval_str("1 + 2 * 3"), Ok(Val::Number(7)));
+        assert_eq!(eval_str("2 * 3 + 1"), Ok(Val::Number(7)));
+        assert_eq!(eval_str("1 + 2 ** 3 * 2"), Ok(Val::Number(17)));
+        assert_eq!(eval_str("1 << 2 + 1"), Ok(Val::Number(8)));
+        assert_eq!(eval_str("6 & 3 ^ 1 | 8"), Ok(Val::Number(11)));
+        assert_eq!(eval_str("7 % 4 * 2"), Ok(Val::Number(6)));
+    }
+
+    #[test]
+    fn left_associative_operations() {
+        assert_eq!(eval_str("10 - 4 - 3"), Ok(Val::Number(3)));
+        assert_eq!(eval_str("64 / 4 / 2"), Ok(Val::Number(8)));
+        assert_eq!(eval_str("256 >> 2 >> 1"), Ok(Val::Number(32)));
+    }
+
+    #[test]
+    fn compare_after_every_other_operation() {
+        assert_eq!(eval_str("1 + 2 == 3"), Ok(Val::Bool(true)));
+        assert_eq!(eval_str("1 | 2 < 3"), Ok(Val::Bool(false)));
+        assert_eq!(eval_str("2 ** 3 >= 8.0"), Ok(Val::Bool(true)));
+        assert_eq!(eval_str("true != false == true"), Ok(Val::Bool(true)));
+    }
+
+    #[test]
+    fn parse_bools() {
+        assert_eq!(parse_str(Bool::new, "true"), Ok(("", Bool { value: true, span: Span::default() })));
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "!false"),
+            Ok((
+                "",
+                Expression::Unary {
+                    op: UnaryOperations::Not,
+                    operand: Box::new(Expression::Bool(Bool { value: false, span: Span::default() })),
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval_str("2 ** 3 ** 2"), Ok(Val::Number(512)));
+    }
+
+    #[test]
+    fn unary_binds_tighter_than_power() {
+        assert_eq!(eval_str("-2 ** 2"), Ok(Val::Number(4)));
+        assert_eq!(eval_str("0 - 2 ** 2"), Ok(Val::Number(-4)));
+        assert_eq!(eval_str("2 ** -1.0"), Ok(Val::Float(0.5)));
+    }
+
+    #[test]
+    fn parse_nested_operations() {
+        let num = |n| Box::new(Expression::Number(Number { value: n, span: Span::default() }));
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "1 - 2 * 3 4"),
+            Ok((
+                "4",
+                Expression::Operation {
+                    lhs: num(1),
+                    rhs: Box::new(Expression::Operation {
+                        lhs: num(2),
+                        rhs: num(3),
+                        op: Operations::Mul,
+                        span: Span::default(),
+                    }),
+                    op: Operations::Sub,
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn report_missing_right_hand_side() {
+        assert!(parse_str(|tokens| Expression::new(tokens, 0), "1 + 2 *").is_err());
     }
 
     #[test]
     fn add_numbers() {
         assert_eq!(
-            Expression::new("1+2"),
+            parse_str(|tokens| Expression::new(tokens, 0), "1+2"),
             Ok((
                 "",
                 Expression::Operation {
-                    lhs: Box::new(Expression::Number(Number(1))),
-                    rhs: Box::new(Expression::Number(Number(2))),
+                    lhs: Box::new(Expression::Number(Number { value: 1, span: Span::default() })),
+                    rhs: Box::new(Expression::Number(Number { value: 2, span: Span::default() })),
                     op: Operations::Add,
+                    span: Span::default(),
                 }
             )),
         );
@@ -160,13 +736,14 @@ mod tests {
     #[test]
     fn parse_expression_with_whitespace() {
         assert_eq!(
-            Expression::new("1 * 2"),
+            parse_str(|tokens| Expression::new(tokens, 0), "1 * 2"),
             Ok((
                 "",
                 Expression::Operation {
-                    lhs: Box::new(Expression::Number(Number(1))),
-                    rhs: Box::new(Expression::Number(Number(2))),
+                    lhs: Box::new(Expression::Number(Number { value: 1, span: Span::default() })),
+                    rhs: Box::new(Expression::Number(Number { value: 2, span: Span::default() })),
                     op: Operations::Mul,
+                    span: Span::default(),
                 }
             )),
         );
@@ -176,9 +753,10 @@ mod tests {
     fn eval_add() {
         assert_eq!(
             Expression::Operation {
-                lhs: Box::new(Expression::Number(Number(10))),
-                rhs: Box::new(Expression::Number(Number(5))),
+                lhs: Box::new(Expression::Number(Number { value: 10, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 5, span: Span::default() })),
                 op: Operations::Add,
+                span: Span::default(),
             }.eval(&Env::default()),
             Ok(Val::Number(15)),
         );
@@ -188,9 +766,10 @@ mod tests {
     fn eval_subtract() {
         assert_eq!(
             Expression::Operation {
-                lhs: Box::new(Expression::Number(Number(5))),
-                rhs: Box::new(Expression::Number(Number(2))),
+                lhs: Box::new(Expression::Number(Number { value: 5, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 2, span: Span::default() })),
                 op: Operations::Sub,
+                span: Span::default(),
             }.eval(&Env::default()),
             Ok(Val::Number(3)),
         );
@@ -200,9 +779,10 @@ mod tests {
     fn eval_multiply() {
         assert_eq!(
             Expression::Operation {
-                lhs: Box::new(Expression::Number(Number(2))),
-                rhs: Box::new(Expression::Number(Number(3))),
+                lhs: Box::new(Expression::Number(Number { value: 2, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 3, span: Span::default() })),
                 op: Operations::Mul,
+                span: Span::default(),
             }.eval(&Env::default()),
             Ok(Val::Number(6)),
         );
@@ -212,22 +792,92 @@ mod tests {
     fn eval_division() {
         assert_eq!(
             Expression::Operation {
-                lhs: Box::new(Expression::Number(Number(500))),
-                rhs: Box::new(Expression::Number(Number(50))),
+                lhs: Box::new(Expression::Number(Number { value: 500, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 50, span: Span::default() })),
                 op: Operations::Div,
+                span: Span::default(),
             }.eval(&Env::default()),
             Ok(Val::Number(10)),
         );
     }
 
+    #[test]
+    fn eval_mixed_int_and_float_division() {
+        assert_eq!(
+            Expression::Operation {
+                lhs: Box::new(Expression::Number(Number { value: 7, span: Span::default() })),
+                rhs: Box::new(Expression::Float(Float { value: 2.0, span: Span::default() })),
+                op: Operations::Div,
+                span: Span::default(),
+            }.eval(&Env::default()),
+            Ok(Val::Float(3.5)),
+        );
+    }
+
+    #[test]
+    fn eval_division_by_zero() {
+        assert_eq!(
+            Expression::Operation {
+                lhs: Box::new(Expression::Number(Number { value: 1, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 0, span: Span::default() })),
+                op: Operations::Div,
+                span: Span::default(),
+            }.eval(&Env::default()).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::DivisionByZero, "division by zero".to_string())),
+        );
+    }
+
+    #[test]
+    fn eval_addition_overflow_to_big_int() {
+        assert_eq!(
+            Expression::Operation {
+                lhs: Box::new(Expression::Number(Number { value: i64::MAX, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 1, span: Span::default() })),
+                op: Operations::Add,
+                span: Span::default(),
+            }.eval(&Env::default()),
+            Ok(Val::BigInt("9223372036854775808".parse().unwrap())),
+        );
+    }
+
+    #[test]
+    fn eval_multiplication_beyond_32_bits() {
+        assert_eq!(
+            Expression::Operation {
+                lhs: Box::new(Expression::Number(Number { value: 65536, span: Span::default() })),
+                rhs: Box::new(Expression::Number(Number { value: 65536, span: Span::default() })),
+                op: Operations::Mul,
+                span: Span::default(),
+            }.eval(&Env::default()),
+            Ok(Val::Number(4294967296)),
+        );
+    }
+
+    #[test]
+    fn parse_number_too_large_as_big_number() {
+        assert_eq!(
+            parse_str(new_integer, "9223372036854775808"),
+            Ok(("", Expression::BigNumber(BigNumber { value: (i64::MAX as u64 + 1).into(), span: Span::default() }))),
+        );
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "99999999999999999999").unwrap().1.eval(&Env::default()),
+            Ok(Val::BigInt("99999999999999999999".parse().unwrap())),
+        );
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "0 - 9223372036854775808").unwrap().1.eval(&Env::default()),
+            Ok(Val::Number(i64::MIN)),
+        );
+    }
+
     #[test]
     fn parse_binding_usage() {
         assert_eq!(
-            Expression::new("bar"),
+            parse_str(|tokens| Expression::new(tokens, 0), "bar"),
             Ok((
                 "",
                 Expression::BindingUsage(BindingUsage {
                     name: "bar".to_string(),
+                    span: Span::default(),
                 }),
             )),
         );
@@ -236,11 +886,12 @@ mod tests {
     #[test]
     fn parse_block() {
         assert_eq!(
-            Expression::new("{ 200 }"),
+            parse_str(|tokens| Expression::new(tokens, 0), "{ 200 }"),
             Ok((
                 "",
                 Expression::Block(Block {
-                    stmts: vec![Statement::Expression(Expression::Number(Number(200)))],
+                    stmts: vec![Statement::Expression(Expression::Number(Number { value: 200, span: Span::default() }))],
+                    span: Span::default(),
                 }),
             )),
         );
@@ -253,6 +904,7 @@ mod tests {
         assert_eq!(
             Expression::BindingUsage(BindingUsage {
                 name: "ten".to_string(),
+                span: Span::default(),
             }).eval(&env),
             Ok(Val::Number(10)),
         );
@@ -262,24 +914,29 @@ mod tests {
     fn eval_non_number_operation() {
         assert_eq!(
             Expression::Operation {
-                lhs: Box::new(Expression::Number(Number(10))),
-                rhs: Box::new(Expression::Block(Block { stmts: Vec::new() })),
+                lhs: Box::new(Expression::Number(Number { value: 10, span: Span::default() })),
+                rhs: Box::new(Expression::Block(Block { stmts: Vec::new(), span: Span::default() })),
                 op: Operations::Add,
+                span: Span::default(),
             }
-            .eval(&Env::default()),
-            Err("cannot evaluate operation whose left-hand side and right-hand side are not both numbers".to_string()),
+            .eval(&Env::default()).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Type, "cannot evaluate operation whose left-hand side and right-hand side are not both numbers".to_string())),
         );
     }
 
     #[test]
     fn parse_func_call() {
         assert_eq!(
-            Expression::new("add 1 2"),
+            parse_str(|tokens| Expression::new(tokens, 0), "add 1 2"),
             Ok((
                 "",
                 Expression::FuncCall(FuncCall {
                     callee: "add".to_string(),
-                    params: vec![Expression::Number(Number(1)), Expression::Number(Number(2))],
+                    params: vec![
+                        Expression::Number(Number { value: 1, span: Span::default() }),
+                        Expression::Number(Number { value: 2, span: Span::default() }),
+                    ],
+                    span: Span::default(),
                 }),
             )),
         );
@@ -289,24 +946,16 @@ mod tests {
     fn eval_func_call() {
         let mut env = Env::default();
 
-        env.store_func(
-            "add".to_string(),
-            vec!["x".to_string(), "y".to_string()],
-            Statement::Expression(Expression::Operation {
-                lhs: Box::new(Expression::BindingUsage(BindingUsage {
-                    name: "x".to_string(),
-                })),
-                rhs: Box::new(Expression::BindingUsage(BindingUsage {
-                    name: "y".to_string(),
-                })),
-                op: Operations::Add,
-            }),
-        );
+        crate::parse("fun add x y => x + y").unwrap().eval(&mut env).unwrap();
 
         assert_eq!(
             Expression::FuncCall(FuncCall {
                 callee: "add".to_string(),
-                params: vec![Expression::Number(Number(2)), Expression::Number(Number(2))],
+                params: vec![
+                    Expression::Number(Number { value: 2, span: Span::default() }),
+                    Expression::Number(Number { value: 2, span: Span::default() }),
+                ],
+                span: Span::default(),
             })
             .eval(&env),
             Ok(Val::Number(4)),
@@ -1,52 +1,41 @@
+use crate::diagnostic::SyntaxError;
+use crate::lexer::{Span, Token};
 use crate::utils;
-use crate::env::Env;
-use crate::val::Val;
-use crate::expr::FuncCall;
 
+/// A binding, or a call of a function without parameters.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct BindingUsage {
-    pub(crate) name: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BindingUsage {
+    pub name: String,
+    pub span: Span,
 }
 
 impl BindingUsage {
-    pub(super) fn new(s:&str) -> Result<(&str, Self), String> {
-        let (s, name) = utils::extract_ident(s)?;
-        Ok((
-            s,
-            Self {
-                name: name.to_string(),
-            },
-        ))
-    }
-
-    pub(super) fn eval(&self, env: &Env) -> Result<Val, String> {
-        env.get_binding(&self.name)
-            .or_else(|error_msg| {
-                if env.get_func(&self.name).is_ok() {
-                    FuncCall {
-                        callee: self.name.clone(),
-                        params: Vec::new(),
-                    }.eval(env)
-                } else {
-                    Err(error_msg)
-                }
-            }
-        )
+    pub(super) fn new(tokens: &[Token]) -> Result<(&[Token], Self), SyntaxError> {
+        let span = tokens[0].span;
+        let (tokens, name) = utils::extract_ident_token(tokens)?;
+        Ok((tokens, Self { name, span }))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::env::Env;
+    use crate::error::ErrorKind;
+    use crate::expr::Expression;
+    use crate::lexer::parse_str;
+    use crate::val::Val;
 
     #[test]
     fn parse_binding_usage() {
         assert_eq!(
-            BindingUsage::new("abc"),
+            parse_str(BindingUsage::new, "abc"),
             Ok((
                 "",
                 BindingUsage {
                     name: "abc".to_string(),
+                    span: Span::default(),
                 },
             )),
         );
@@ -57,9 +46,10 @@ mod tests {
         let mut env = Env::default();
         env.store_binding("foo".to_string(), Val::Number(10));
         assert_eq!(
-            BindingUsage {
+            Expression::BindingUsage(BindingUsage {
                 name: "foo".to_string(),
-            }.eval(&env),
+                span: Span::default(),
+            }).eval(&env),
             Ok(Val::Number(10)),
         );
     }
@@ -69,11 +59,12 @@ mod tests {
         let empty_env = Env::default();
 
         assert_eq!(
-            BindingUsage {
+            Expression::BindingUsage(BindingUsage {
                 name: "i_dont_exist".to_string(),
-            }
-            .eval(&empty_env),
-            Err("binding with name ‘i_dont_exist’ does not exist".to_string()),
+                span: Span::default(),
+            })
+            .eval(&empty_env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Undefined, "binding with name ‘i_dont_exist’ does not exist".to_string())),
         );
     }
 }
@@ -1,64 +1,72 @@
+use crate::diagnostic::SyntaxError;
+use crate::lexer::{Span, Token, TokenKind};
 use crate::utils;
 use crate::statements::Statement;
-use crate::env::Env;
-use crate::val::Val;
 
+/// Statements in braces, evaluating to the value of the last one. Bindings
+/// made inside don't outlive the block.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct Block {
-    pub(crate) stmts: Vec<Statement>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Block {
+    pub stmts: Vec<Statement>,
+    pub span: Span,
 }
 
 impl Block {
-    pub(super) fn new(s:&str) -> Result<(&str, Self), String> {
-        let s = utils::tag("{", s)?;
-        let (s, _) = utils::extract_whitespaces(s);
+    pub(super) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        let start = tokens;
+        let tokens = utils::tag(&TokenKind::LBrace, tokens)?;
 
-        let (s, stmts) = utils::sequence(Statement::new, utils::extract_whitespaces, s)?;
+        let (tokens, stmts, error) = Statement::new_sequence(tokens, depth);
 
-        let (s, _) = utils::extract_whitespaces(s);
-        let s = utils::tag("}", s)?;
+        let tokens = utils::tag(&TokenKind::RBrace, tokens).map_err(|closing| closing.merge(error))?;
 
-        Ok((s, Block {stmts}))
-    }
-
-    pub(super) fn eval(&self, env: &Env) -> Result<Val, String> {
-        if self.stmts.is_empty() {
-            return Ok(Val::Unit);
-        }
-        let mut env = env.create_child();
-        let stmts_except_last = &self.stmts[..self.stmts.len() - 1];
-        for stmt in stmts_except_last {
-            stmt.eval(&mut env)?;
-        }
-
-        self.stmts.last().unwrap().eval(&mut env)
+        Ok((tokens, Block { stmts, span: utils::consumed(start, tokens) }))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::env::Env;
+    use crate::lexer::parse_str;
+    use crate::val::Val;
     use super::super::{BindingUsage, Expression, Number, Operations};
     use crate::binding_def::BindingDef;
 
     #[test]
     fn parse_empty_block() {
-        assert_eq!(Block::new("{}"), Ok(("", Block {stmts: Vec::new()} )));
+        assert_eq!(parse_str(|tokens| Block::new(tokens, 0), "{}"), Ok(("", Block {stmts: Vec::new(), span: Span::default() } )));
     }
 
     #[test]
     fn parse_empty_block_with_whitespace() {
-        assert_eq!(Block::new("{   }"), Ok(("", Block { stmts: Vec::new() })));
+        assert_eq!(parse_str(|tokens| Block::new(tokens, 0), "{   }"), Ok(("", Block { stmts: Vec::new(), span: Span::default() })));
     }
 
     #[test]
     fn parse_block_with_one_stmt() {
         assert_eq!(
-            Block::new("{ 5 }"),
+            parse_str(|tokens| Block::new(tokens, 0), "{ 5 }"),
             Ok((
                 "",
                 Block {
-                    stmts: vec![Statement::Expression(Expression::Number(Number(5)))],
+                    stmts: vec![Statement::Expression(Expression::Number(Number { value: 5, span: Span::default() }))],
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_block_with_comments_and_crlf() {
+        assert_eq!(
+            parse_str(|tokens| Block::new(tokens, 0), "{\r\n\t// the answer\r\n\t/* still /* the */ answer */ 42\r\n}"),
+            Ok((
+                "",
+                Block {
+                    stmts: vec![Statement::Expression(Expression::Number(Number { value: 42, span: Span::default() }))],
+                    span: Span::default(),
                 },
             )),
         );
@@ -67,7 +75,7 @@ mod tests {
     #[test]
     fn parse_block_with_multiple_statements() {
         assert_eq!(
-            Block::new("{
+            parse_str(|tokens| Block::new(tokens, 0), "{
                 let a = 10
                 let b = a
                 b
@@ -78,18 +86,25 @@ mod tests {
                     stmts: vec![
                         Statement::BindingDef(BindingDef {
                             name: "a".to_string(),
-                            value: Expression::Number(Number(10)),
+                            ty: None,
+                            value: Expression::Number(Number { value: 10, span: Span::default() }),
+                            span: Span::default(),
                         }),
                         Statement::BindingDef(BindingDef {
                             name: "b".to_string(),
+                            ty: None,
                             value: Expression::BindingUsage(BindingUsage {
                                 name: "a".to_string(),
+                                span: Span::default(),
                             }),
+                            span: Span::default(),
                         }),
                         Statement::Expression(Expression::BindingUsage(BindingUsage {
                             name: "b".to_string(),
+                            span: Span::default(),
                         })),
                     ],
+                    span: Span::default(),
                 },
             ))
         );
@@ -98,8 +113,11 @@ mod tests {
     #[test]
     fn eval_block() {
         assert_eq!(
-            Expression::Block(Block { stmts: vec![Statement::Expression(Expression::Number(Number(10)))] })
-                .eval(&Env::default()),
+            Expression::Block(Block {
+                stmts: vec![Statement::Expression(Expression::Number(Number { value: 10, span: Span::default() }))],
+                span: Span::default(),
+            })
+            .eval(&Env::default()),
             Ok(Val::Number(10)),
         );
     }
@@ -107,7 +125,7 @@ mod tests {
     #[test]
     fn eval_empty_block() {
         assert_eq!(
-            Block { stmts: Vec::new() }.eval(&Env::default()),
+            Expression::Block(Block { stmts: Vec::new(), span: Span::default() }).eval(&Env::default()),
             Ok(Val::Unit),
         );
     }
@@ -115,9 +133,10 @@ mod tests {
     #[test]
     fn eval_block_with_one_expr() {
         assert_eq!(
-            Block {
-                stmts: vec![Statement::Expression(Expression::Number(Number(25)))],
-            }
+            Expression::Block(Block {
+                stmts: vec![Statement::Expression(Expression::Number(Number { value: 25, span: Span::default() }))],
+                span: Span::default(),
+            })
             .eval(&Env::default()),
             Ok(Val::Number(25)),
         );
@@ -126,17 +145,21 @@ mod tests {
     #[test]
     fn eval_block_with_binding_def_and_usage() {
         assert_eq!(
-            Block {
+            Expression::Block(Block {
                 stmts: vec![
                     Statement::BindingDef(BindingDef {
                         name: "one".to_string(),
-                        value: Expression::Number(Number(1)),
+                        ty: None,
+                        value: Expression::Number(Number { value: 1, span: Span::default() }),
+                        span: Span::default(),
                     }),
                     Statement::Expression(Expression::BindingUsage(BindingUsage {
                         name: "one".to_string(),
+                        span: Span::default(),
                     })),
                 ],
-            }
+                span: Span::default(),
+            })
             .eval(&Env::default()),
             Ok(Val::Number(1)),
         );
@@ -145,22 +168,29 @@ mod tests {
     #[test]
     fn eval_block_with_multiple_binding_defs() {
         assert_eq!(
-            Block {
+            Expression::Block(Block {
                 stmts: vec![
                     Statement::BindingDef(BindingDef {
                         name: "foo".to_string(),
-                        value: Expression::Number(Number(5)),
+                        ty: None,
+                        value: Expression::Number(Number { value: 5, span: Span::default() }),
+                        span: Span::default(),
                     }),
                     Statement::BindingDef(BindingDef {
                         name: "bar".to_string(),
-                        value: Expression::Number(Number(4)),
+                        ty: None,
+                        value: Expression::Number(Number { value: 4, span: Span::default() }),
+                        span: Span::default(),
                     }),
                     Statement::BindingDef(BindingDef {
                         name: "baz".to_string(),
-                        value: Expression::Number(Number(3)),
+                        ty: None,
+                        value: Expression::Number(Number { value: 3, span: Span::default() }),
+                        span: Span::default(),
                     }),
                 ],
-            }
+                span: Span::default(),
+            })
             .eval(&Env::default()),
             Ok(Val::Unit),
         );
@@ -169,17 +199,19 @@ mod tests {
     #[test]
     fn eval_block_with_multiple_exprs() {
         assert_eq!(
-            Block {
+            Expression::Block(Block {
                 stmts: vec![
-                    Statement::Expression(Expression::Number(Number(100))),
-                    Statement::Expression(Expression::Number(Number(30))),
+                    Statement::Expression(Expression::Number(Number { value: 100, span: Span::default() })),
+                    Statement::Expression(Expression::Number(Number { value: 30, span: Span::default() })),
                     Statement::Expression(Expression::Operation {
-                        lhs: Box::new(Expression::Number(Number(10))),
-                        rhs: Box::new(Expression::Number(Number(7))),
+                        lhs: Box::new(Expression::Number(Number { value: 10, span: Span::default() })),
+                        rhs: Box::new(Expression::Number(Number { value: 7, span: Span::default() })),
                         op: Operations::Sub,
+                        span: Span::default(),
                     }),
                 ],
-            }
+                span: Span::default(),
+            })
             .eval(&Env::default()),
             Ok(Val::Number(3)),
         );
@@ -191,19 +223,24 @@ mod tests {
         env.store_binding("foo".to_string(), Val::Number(2));
 
         assert_eq!(
-            Block {
+            Expression::Block(Block {
                 stmts: vec![
                     Statement::BindingDef(BindingDef {
                         name: "baz".to_string(),
+                        ty: None,
                         value: Expression::BindingUsage(BindingUsage {
                             name: "foo".to_string(),
+                            span: Span::default(),
                         }),
+                        span: Span::default(),
                     }),
                     Statement::Expression(Expression::BindingUsage(BindingUsage {
                         name: "baz".to_string(),
+                        span: Span::default(),
                     })),
                 ],
-            }
+                span: Span::default(),
+            })
             .eval(&env),
             Ok(Val::Number(2)),
         );
@@ -1,152 +1,273 @@
+use crate::diagnostic::SyntaxError;
+use crate::lexer::{Span, Token, TokenKind};
 use crate::utils;
-use super::{Expression, Val, Env};
+use super::Expression;
 
+/// A call with at least one parameter; calls without parameters look like
+/// a `BindingUsage`.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct FuncCall {
-    pub(crate) callee: String,
-    pub(crate) params: Vec<Expression>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FuncCall {
+    pub callee: String,
+    pub params: Vec<Expression>,
+    pub span: Span,
 }
 
 impl FuncCall {
-    pub(super) fn new(s:&str) -> Result<(&str, Self), String> {
-        let (s, callee) = utils::extract_ident(s)?;
-        let (s, _) = utils::take_while(|c| c == ' ', s);
-        let (s, params) = utils::non_empty_sequence(Expression::new, |s| utils::take_while(|c| c == ' ', s), s)?;
-
-        Ok((
-            s,
-            Self {
-                callee: callee.to_string(),
-                params,
-            },
-        ))
-    }
-
-    pub(super) fn eval(&self, env: &Env) -> Result<Val, String> {
-        let mut child_env = env.create_child();
-        let (param_names, body) = env.get_func(&self.callee)?;
-
-        let num_expected_params = param_names.len();
-        let num_actual_params = self.params.len();
-
-        if num_expected_params != num_actual_params {
-            return Err(format!(
-                "expected {} parameters, got {}",
-                num_expected_params, num_actual_params,
-            ));
+    pub(super) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        if !Self::has_params(tokens) {
+            return Err(SyntaxError::new(tokens[0].span, "expected parameters"));
         }
+        let start = tokens;
+        let mut previous = &tokens[0];
+        let (mut tokens, callee) = utils::extract_ident_token(tokens)?;
 
-        for (param_name, param_expr) in param_names.into_iter().zip(&self.params) {
-            let param_val = param_expr.eval(&child_env)?;
-            child_env.store_binding(param_name, param_val);
+        // Parameters have to be on the same line as the callee.
+        let mut params = Vec::new();
+        while starts_param(previous, tokens) {
+            let (remainder, param) = Expression::new(tokens, depth)?;
+            previous = &tokens[tokens.len() - remainder.len() - 1];
+            tokens = remainder;
+            params.push(param);
         }
 
-        body.eval(&mut child_env)
+        Ok((tokens, Self { callee, params, span: utils::consumed(start, tokens) }))
+    }
+
+    // Whether `tokens` start with an identifier that is followed by a
+    // parameter, i.e. is a call rather than a binding usage.
+    pub(super) fn has_params(tokens: &[Token]) -> bool {
+        match tokens.split_first() {
+            Some((callee @ Token { kind: TokenKind::Ident(_), .. }, remainder)) => starts_param(callee, remainder),
+            _ => false,
+        }
+    }
+}
+
+// `f -x` passes `-x` to `f`, but `f - x` and `f-x` are subtractions, so a
+// parameter may only start with a sign that is preceded by whitespace and
+// directly followed by its operand. This depends on spacing alone, not on
+// what `f` names, so `x -1` is a call even if `x` is a binding.
+fn starts_param(previous: &Token, tokens: &[Token]) -> bool {
+    let first = &tokens[0];
+    if first.newline_before {
+        return false;
+    }
+
+    match first.kind {
+        TokenKind::Int { .. }
+        | TokenKind::Float(_)
+        | TokenKind::Ident(_)
+        | TokenKind::True
+        | TokenKind::False
+        | TokenKind::If
+        | TokenKind::LBrace
+        | TokenKind::Bang => true,
+        TokenKind::Minus | TokenKind::Plus => {
+            first.is_separated_from(previous)
+                && tokens[1].kind != TokenKind::Eof
+                && !tokens[1].is_separated_from(first)
+        },
+        _ => false,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use super::super::{BindingUsage, Number, Operations};
-    use crate::statements::Statement;
+    use crate::lexer::parse_str;
+    use super::super::{BindingUsage, Number, UnaryOperations};
+    use crate::env::Env;
+    use crate::error::ErrorKind;
+    use crate::val::Val;
 
     #[test]
     fn parse_func_call_with_one_parameter() {
         assert_eq!(
-            FuncCall::new("factorial 10"),
+            parse_str(|tokens| FuncCall::new(tokens, 0), "factorial 10"),
             Ok((
                 "",
                 FuncCall {
                     callee: "factorial".to_string(),
-                    params: vec![Expression::Number(Number(10))],
+                    params: vec![Expression::Number(Number { value: 10, span: Span::default() })],
+                    span: Span::default(),
                 },
             )),
         );
     }
 
     #[test]
-    fn eval_func_call() {
+    fn parse_func_call_with_negative_parameter() {
+        assert_eq!(
+            parse_str(|tokens| FuncCall::new(tokens, 0), "round -x"),
+            Ok((
+                "",
+                FuncCall {
+                    callee: "round".to_string(),
+                    params: vec![Expression::Unary {
+                        op: UnaryOperations::Neg,
+                        operand: Box::new(Expression::BindingUsage(BindingUsage {
+                            name: "x".to_string(),
+                            span: Span::default(),
+                        })),
+                        span: Span::default(),
+                    }],
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_func_call_with_comments_between_parameters() {
+        assert_eq!(
+            parse_str(|tokens| FuncCall::new(tokens, 0), "add /* lhs */ 1\t/* rhs */ 2 // sum\n3"),
+            Ok((
+                "3",
+                FuncCall {
+                    callee: "add".to_string(),
+                    params: vec![
+                        Expression::Number(Number { value: 1, span: Span::default() }),
+                        Expression::Number(Number { value: 2, span: Span::default() }),
+                    ],
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn do_not_parse_subtraction_as_func_call() {
+        assert!(parse_str(|tokens| FuncCall::new(tokens, 0), "a - b").is_err());
+        assert!(parse_str(|tokens| FuncCall::new(tokens, 0), "a-b").is_err());
+    }
+
+    #[test]
+    fn pass_signed_parameters_by_spacing_alone() {
         let mut env = Env::default();
-        env.store_func(
-            "id".to_string(),
-            vec!["x".to_string()],
-            Statement::Expression(Expression::BindingUsage(BindingUsage {
-                name: "x".to_string(),
-            })),
+        assert_eq!(crate::parse("fun neg y => 0 - y\nneg -1").unwrap().eval(&mut env), Ok(Val::Number(1)));
+        assert_eq!(crate::parse("neg -1").unwrap().eval(&mut env), Ok(Val::Number(1)));
+        assert_eq!(crate::parse("round -1.5").unwrap().eval(&mut env), Ok(Val::Number(-2)));
+        assert_eq!(crate::parse("let x = 5\nx - 1").unwrap().eval(&mut env), Ok(Val::Number(4)));
+        assert_eq!(crate::parse("x-1").unwrap().eval(&mut env), Ok(Val::Number(4)));
+        assert_eq!(
+            crate::parse("x -1").unwrap().eval(&mut env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Undefined, "function with name ‘x’ does not exist".to_string())),
         );
+    }
+
+    #[test]
+    fn eval_func_call() {
+        let mut env = Env::default();
+        crate::parse("fun id x => x").unwrap().eval(&mut env).unwrap();
         assert_eq!(
-            FuncCall {
+            Expression::FuncCall(FuncCall {
                 callee: "id".to_string(),
-                params: vec![Expression::Number(Number(10))],
-            }
+                params: vec![Expression::Number(Number { value: 10, span: Span::default() })],
+                span: Span::default(),
+            })
             .eval(&env),
             Ok(Val::Number(10)),
         );
     }
 
+    // Parameters are evaluated where the call is, so they can't see the
+    // parameters the callee has been given so far.
+    #[test]
+    fn eval_parameters_in_scope_of_caller() {
+        let parse = crate::parse("let x = 1\nfun second x y => y\nsecond 5 x").unwrap();
+        assert_eq!(crate::env::on_engines(|env| parse.eval(env)), Ok(Val::Number(1)));
+    }
+
     #[test]
     fn eval_non_existent_func_call() {
         let env = Env::default();
         assert_eq!(
-            FuncCall {
+            Expression::FuncCall(FuncCall {
                 callee: "i_dont_exist".to_string(),
-                params: vec![Expression::Number(Number(1))],
-            }
-            .eval(&env),
-            Err("function with name ‘i_dont_exist’ does not exist".to_string()),
+                params: vec![Expression::Number(Number { value: 1, span: Span::default() })],
+                span: Span::default(),
+            })
+            .eval(&env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Undefined, "function with name ‘i_dont_exist’ does not exist".to_string())),
         );
     }
 
     #[test]
     fn eval_func_call_with_too_few_parameters() {
         let mut env = Env::default();
-        env.store_func(
-            "mul".to_string(),
-            vec!["a".to_string(), "b".to_string()],
-            Statement::Expression(Expression::Operation {
-                lhs: Box::new(Expression::BindingUsage(BindingUsage {
-                    name: "a".to_string(),
-                })),
-                rhs: Box::new(Expression::BindingUsage(BindingUsage {
-                    name: "b".to_string(),
-                })),
-                op: Operations::Mul,
-            }),
-        );
+        crate::parse("fun mul a b => a * b").unwrap().eval(&mut env).unwrap();
         assert_eq!(
-            FuncCall {
+            Expression::FuncCall(FuncCall {
                 callee: "mul".to_string(),
-                params: vec![Expression::Number(Number(100))],
-            }
-            .eval(&env),
-            Err("expected 2 parameters, got 1".to_string()),
+                params: vec![Expression::Number(Number { value: 100, span: Span::default() })],
+                span: Span::default(),
+            })
+            .eval(&env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Arity, "expected 2 parameters, got 1".to_string())),
         );
     }
 
     #[test]
     fn eval_func_call_with_too_many_parameters() {
         let mut env = Env::default();
-        env.store_func(
-            "square".to_string(),
-            vec!["n".to_string()],
-            Statement::Expression(Expression::Operation {
-                lhs: Box::new(Expression::BindingUsage(BindingUsage {
-                    name: "n".to_string(),
-                })),
-                rhs: Box::new(Expression::BindingUsage(BindingUsage {
-                    name: "n".to_string(),
-                })),
-                op: Operations::Mul,
-            }),
-        );
+        crate::parse("fun square n => n * n").unwrap().eval(&mut env).unwrap();
         assert_eq!(
-            FuncCall {
+            Expression::FuncCall(FuncCall {
                 callee: "square".to_string(),
-                params: vec![Expression::Number(Number(5)), Expression::Number(Number(42))],
-            }
+                params: vec![
+                    Expression::Number(Number { value: 5, span: Span::default() }),
+                    Expression::Number(Number { value: 42, span: Span::default() }),
+                ],
+                span: Span::default(),
+            })
+            .eval(&env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Arity, "expected 1 parameters, got 2".to_string())),
+        );
+    }
+
+    #[test]
+    fn eval_native_func_call() {
+        let mut env = Env::default();
+        env.register_fn("double", |n: i32| n * 2);
+        assert_eq!(
+            Expression::FuncCall(FuncCall {
+                callee: "double".to_string(),
+                params: vec![Expression::Number(Number { value: 21, span: Span::default() })],
+                span: Span::default(),
+            })
             .eval(&env),
-            Err("expected 1 parameters, got 2".to_string()),
+            Ok(Val::Number(42)),
+        );
+    }
+
+    #[test]
+    fn eval_native_func_call_with_wrong_number_of_parameters() {
+        let mut env = Env::default();
+        env.register_fn("concat", |a: String, b: String| a + &b);
+        assert_eq!(
+            Expression::FuncCall(FuncCall {
+                callee: "concat".to_string(),
+                params: vec![Expression::Number(Number { value: 1, span: Span::default() })],
+                span: Span::default(),
+            })
+            .eval(&env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Arity, "expected 2 parameters, got 1".to_string())),
+        );
+    }
+
+    #[test]
+    fn eval_native_func_call_with_wrong_parameter_type() {
+        let mut env = Env::default();
+        env.register_fn("shout", |s: String| s.to_uppercase());
+        assert_eq!(
+            Expression::FuncCall(FuncCall {
+                callee: "shout".to_string(),
+                params: vec![Expression::Number(Number { value: 1, span: Span::default() })],
+                span: Span::default(),
+            })
+            .eval(&env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Host, "invalid parameter 1: expected string, found int".to_string())),
         );
     }
 }
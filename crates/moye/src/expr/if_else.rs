@@ -0,0 +1,156 @@
+use crate::diagnostic::SyntaxError;
+use crate::lexer::{Span, Token, TokenKind};
+use crate::utils;
+use super::{Expression, Operations};
+
+/// `if condition then then_branch else else_branch`. The condition has to be
+/// a bool, and only the branch it selects is evaluated. Both branches are
+/// required, so that the whole is a value either way.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IfElse {
+    pub condition: Box<Expression>,
+    pub then_branch: Box<Expression>,
+    pub else_branch: Box<Expression>,
+    pub span: Span,
+}
+
+impl IfElse {
+    pub(super) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        let start = tokens;
+        let tokens = utils::tag(&TokenKind::If, tokens)?;
+        let (tokens, condition) = Expression::new(tokens, depth)?;
+        let tokens = tag_after_expression(&TokenKind::Then, tokens)?;
+        let (tokens, then_branch) = Expression::new(tokens, depth)?;
+        let tokens = tag_after_expression(&TokenKind::Else, tokens)?;
+        // Like a binding's value, the else branch extends as far as possible,
+        // so `if c then 1 else 2 + 3` adds 3 only in the else branch.
+        let (tokens, else_branch) = Expression::new(tokens, depth)?;
+
+        Ok((tokens, Self {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+            span: utils::consumed(start, tokens),
+        }))
+    }
+}
+
+// An operator could have continued the expression before `kind` too.
+fn tag_after_expression<'t>(kind: &TokenKind, tokens: &'t [Token]) -> Result<&'t [Token], SyntaxError> {
+    utils::tag(kind, tokens).map_err(|error| error.merge(SyntaxError::expected(Operations::expected(), &tokens[0])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use crate::error::ErrorKind;
+    use crate::lexer::parse_str;
+    use crate::val::Val;
+    use super::super::{BindingUsage, Bool, Number};
+
+    fn number(value: i64) -> Box<Expression> {
+        Box::new(Expression::Number(Number { value, span: Span::default() }))
+    }
+
+    fn boolean(value: bool) -> Box<Expression> {
+        Box::new(Expression::Bool(Bool { value, span: Span::default() }))
+    }
+
+    #[test]
+    fn parse_if_else() {
+        assert_eq!(
+            parse_str(|tokens| IfElse::new(tokens, 0), "if ready then 1 else 2"),
+            Ok((
+                "",
+                IfElse {
+                    condition: Box::new(Expression::BindingUsage(BindingUsage {
+                        name: "ready".to_string(),
+                        span: Span::default(),
+                    })),
+                    then_branch: number(1),
+                    else_branch: number(2),
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_if_else_across_lines() {
+        assert_eq!(
+            parse_str(|tokens| IfElse::new(tokens, 0), "if true\nthen 1\nelse if false then 2 else 3"),
+            Ok((
+                "",
+                IfElse {
+                    condition: boolean(true),
+                    then_branch: number(1),
+                    else_branch: Box::new(Expression::IfElse(IfElse {
+                        condition: boolean(false),
+                        then_branch: number(2),
+                        else_branch: number(3),
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn cannot_parse_if_without_else() {
+        assert_eq!(
+            parse_str(|tokens| IfElse::new(tokens, 0), "if true then 1"),
+            Err("expected ‘!=’, ‘%’, ‘&’, ‘**’, ‘*’, ‘+’, ‘-’, ‘/’, ‘<<’, ‘<=’, ‘<’, ‘==’, ‘>=’, ‘>>’, ‘>’, \
+                 ‘^’, ‘else’ or ‘|’, found end of input"
+                .to_string()),
+        );
+    }
+
+    #[test]
+    fn eval_selected_branch_only() {
+        let if_else = |condition| Expression::IfElse(IfElse {
+            condition,
+            then_branch: number(1),
+            else_branch: Box::new(Expression::Operation {
+                lhs: number(1),
+                rhs: number(0),
+                op: Operations::Div,
+                span: Span::default(),
+            }),
+            span: Span::default(),
+        });
+        assert_eq!(if_else(boolean(true)).eval(&Env::default()), Ok(Val::Number(1)));
+        assert_eq!(
+            if_else(boolean(false)).eval(&Env::default()).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::DivisionByZero, "division by zero".to_string())),
+        );
+    }
+
+    #[test]
+    fn eval_non_bool_condition() {
+        assert_eq!(
+            Expression::IfElse(IfElse {
+                condition: number(1),
+                then_branch: number(1),
+                else_branch: number(2),
+                span: Span::default(),
+            })
+            .eval(&Env::default()).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Type, "condition of ‘if’ must be a bool, found int".to_string())),
+        );
+    }
+
+    #[test]
+    fn cannot_use_keywords_of_conditionals_as_identifiers() {
+        assert_eq!(
+            parse_str(|tokens| Expression::new(tokens, 0), "1 + else"),
+            Err("‘else’ is a reserved word and cannot be used as an identifier".to_string()),
+        );
+        assert_eq!(
+            parse_str(|tokens| crate::binding_def::BindingDef::new(tokens, 0), "let then = 1"),
+            Err("‘then’ is a reserved word and cannot be used as an identifier".to_string()),
+        );
+    }
+}
@@ -0,0 +1,348 @@
+/*
+ * Prints programs in a canonical layout: one statement per line, blocks
+ * indented by four spaces and single spaces around binary operators, with at
+ * most one blank line kept between statements. Comments are kept too;
+ * comments inside a statement (but outside its blocks) move to the line
+ * before it, unless they end its last line
+ */
+
+use crate::ast::{Block, Expression, Span, Statement, UnaryOperations, Visitor};
+use crate::diagnostic::ParseError;
+use crate::lexer;
+
+const INDENT: &str = "    ";
+
+/// Formats `source`, or reports its syntax errors. The result parses to the
+/// same program as `source`.
+pub fn format(source: &str) -> Result<String, ParseError> {
+    let parse = crate::parse(source)?;
+    let (_, comments) = lexer::lex_with_comments(source).expect("source that parses can be lexed");
+
+    let mut formatter = Formatter {
+        source,
+        comments: comments.into_iter().map(|span| (span, false)).collect(),
+        out: String::new(),
+        depth: 0,
+        last_end: None,
+    };
+    formatter.statements(parse.statements(), source.len());
+    Ok(formatter.out)
+}
+
+struct Formatter<'s> {
+    source: &'s str,
+    /// Every comment, and whether it has been printed.
+    comments: Vec<(Span, bool)>,
+    out: String,
+    depth: usize,
+    /// Where the last statement or comment printed ends in `source`, unless
+    /// it was in an enclosing block.
+    last_end: Option<usize>,
+}
+
+impl<'s> Formatter<'s> {
+    // Prints statements with the comments between them; comments before `end`
+    // that are left over go after the statements.
+    fn statements(&mut self, stmts: &[Statement], end: usize) {
+        self.last_end = None;
+
+        for stmt in stmts {
+            let span = stmt.span();
+            let mut blocks = Blocks(Vec::new());
+            blocks.visit_statement(stmt);
+            self.comment_lines(|comment| {
+                comment.start < span.end && !blocks.0.iter().any(|block| contains(*block, comment))
+            });
+
+            self.start_line(span.start);
+            self.statement(stmt);
+            self.last_end = Some(span.end);
+            self.trailing_comment(end);
+            self.out.push('\n');
+        }
+
+        self.comment_lines(|comment| comment.start < end);
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::BindingDef(binding_def) => {
+                self.out.push_str(&format!("let {}", binding_def.name));
+                if let Some(ty) = &binding_def.ty {
+                    self.out.push_str(&format!(": {}", ty.ty));
+                }
+                self.out.push_str(" = ");
+                self.expression(&binding_def.value);
+            },
+            Statement::FuncDef(func_def) => {
+                self.out.push_str(&format!("fun {}", func_def.name));
+                for (param, ty) in func_def.params.iter().zip(&func_def.param_types) {
+                    match ty {
+                        Some(ty) => self.out.push_str(&format!(" ({}: {})", param, ty.ty)),
+                        None => self.out.push_str(&format!(" {}", param)),
+                    }
+                }
+                if let Some(ty) = &func_def.return_type {
+                    self.out.push_str(&format!(" -> {}", ty.ty));
+                }
+                self.out.push_str(" => ");
+                self.statement(&func_def.body);
+            },
+            Statement::Expression(expr) => self.expression(expr),
+        }
+    }
+
+    // Expressions are printed on one line, except for the statements in
+    // blocks, so function parameters stay on the line of their function.
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            // As written, so that `0xff` and `1_000` stay as they are.
+            Expression::Number(number) => self.out.push_str(self.text(number.span)),
+            Expression::BigNumber(number) => self.out.push_str(self.text(number.span)),
+            Expression::Float(float) => self.out.push_str(self.text(float.span)),
+            Expression::Bool(boolean) => self.out.push_str(if boolean.value { "true" } else { "false" }),
+            Expression::Operation { lhs, rhs, op, .. } => {
+                self.expression(lhs);
+                self.out.push_str(&format!(" {} ", op.symbol()));
+                self.expression(rhs);
+            },
+            Expression::Unary { op, operand, .. } => {
+                self.out.push_str(op.symbol());
+                // `-1` would be a negative literal rather than a negation.
+                let literal = matches!(**operand, Expression::Number(_) | Expression::BigNumber(_) | Expression::Float(_));
+                if *op == UnaryOperations::Neg && literal && !self.text(operand.span()).starts_with('-') {
+                    self.out.push(' ');
+                }
+                self.expression(operand);
+            },
+            Expression::FuncCall(func_call) => {
+                self.out.push_str(&func_call.callee);
+                for param in &func_call.params {
+                    self.out.push(' ');
+                    self.expression(param);
+                }
+            },
+            Expression::BindingUsage(binding_usage) => self.out.push_str(&binding_usage.name),
+            Expression::Block(block) => self.block(block),
+            Expression::IfElse(if_else) => {
+                self.out.push_str("if ");
+                self.expression(&if_else.condition);
+                self.out.push_str(" then ");
+                self.expression(&if_else.then_branch);
+                self.out.push_str(" else ");
+                self.expression(&if_else.else_branch);
+            },
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let has_comments = self.comments.iter().any(|(comment, printed)| !printed && contains(block.span, *comment));
+        if block.stmts.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.statements(&block.stmts, block.span.end);
+        self.depth -= 1;
+        self.out.push_str(&INDENT.repeat(self.depth));
+        self.out.push('}');
+    }
+
+    // Prints the comments accepted by `select` that haven't been printed yet,
+    // each on its own line.
+    fn comment_lines(&mut self, select: impl Fn(Span) -> bool) {
+        for idx in 0..self.comments.len() {
+            let (comment, printed) = self.comments[idx];
+            if printed || !select(comment) {
+                continue;
+            }
+            self.start_line(comment.start);
+            self.out.push_str(self.text(comment).trim_end());
+            self.out.push('\n');
+            self.last_end = Some(comment.end);
+            self.comments[idx].1 = true;
+        }
+    }
+
+    // Prints the next comment if it is on the line that was just printed.
+    fn trailing_comment(&mut self, end: usize) {
+        let Some(last_end) = self.last_end else { return };
+        let Some(idx) = self.comments.iter().position(|(_, printed)| !printed) else { return };
+        let comment = self.comments[idx].0;
+
+        if comment.start >= last_end && comment.start < end && !self.source[last_end..comment.start].contains('\n') {
+            self.out.push(' ');
+            self.out.push_str(self.text(comment).trim_end());
+            self.last_end = Some(comment.end);
+            self.comments[idx].1 = true;
+        }
+    }
+
+    // Indents, after a blank line if there was one before `start`.
+    fn start_line(&mut self, start: usize) {
+        if let Some(last_end) = self.last_end
+            && last_end < start
+            && self.source[last_end..start].matches('\n').count() > 1
+        {
+            self.out.push('\n');
+        }
+        self.out.push_str(&INDENT.repeat(self.depth));
+    }
+
+    fn text(&self, span: Span) -> &'s str {
+        &self.source[span.start..span.end]
+    }
+}
+
+// Collects the outermost blocks of a statement.
+struct Blocks(Vec<Span>);
+
+impl Visitor for Blocks {
+    fn visit_block(&mut self, block: &Block) {
+        self.0.push(block.span);
+    }
+}
+
+fn contains(outer: Span, inner: Span) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::EraseSpans;
+    use proptest::prelude::*;
+
+    fn fmt(source: &str) -> String {
+        format(source).unwrap()
+    }
+
+    #[test]
+    fn format_spacing_around_operators() {
+        assert_eq!(fmt("let   a=1+2*  3\nfun  f x=>x*2"), "let a = 1 + 2 * 3\nfun f x => x * 2\n");
+    }
+
+    #[test]
+    fn format_type_annotations() {
+        assert_eq!(
+            fmt("let a :Int=1\nfun f ( x:Float )y->Bool=>true"),
+            "let a: Int = 1\nfun f (x: Float) y -> Bool => true\n",
+        );
+    }
+
+    #[test]
+    fn indent_nested_blocks() {
+        assert_eq!(
+            fmt("let a = { let b = { 1 }\n b + 1 }\nfun f => {}"),
+            "let a = {\n    let b = {\n        1\n    }\n    b + 1\n}\nfun f => {}\n",
+        );
+    }
+
+    #[test]
+    fn keep_parameters_on_the_line_of_their_function() {
+        assert_eq!(fmt("f { 1 }   2"), "f {\n    1\n} 2\n");
+        assert_eq!(fmt("round   -x"), "round -x\n");
+        assert_eq!(fmt("x   -1"), "x -1\n");
+        assert_eq!(fmt("x   - 1"), "x - 1\n");
+    }
+
+    #[test]
+    fn format_conditionals() {
+        assert_eq!(
+            fmt("if  x<=1 then{ 1 }else  if !true then 2 else 3"),
+            "if x <= 1 then {\n    1\n} else if !true then 2 else 3\n",
+        );
+    }
+
+    #[test]
+    fn keep_literals_as_written() {
+        assert_eq!(fmt("0xFF+1_000*2.5e3"), "0xFF + 1_000 * 2.5e3\n");
+    }
+
+    #[test]
+    fn keep_negations_of_literals_apart_from_negative_literals() {
+        assert_eq!(fmt("- 1 + -1 - -x * - -1"), "- 1 + -1 - -x * --1\n");
+    }
+
+    #[test]
+    fn keep_comments() {
+        assert_eq!(
+            fmt("// header\n\nlet a = 1   // one\nlet b = 1 + /* two */ 2\n{\n// inside\n}\r\n/* end */"),
+            "// header\n\nlet a = 1 // one\n/* two */\nlet b = 1 + 2\n{\n    // inside\n}\n/* end */\n",
+        );
+    }
+
+    #[test]
+    fn keep_comments_at_the_end_of_blocks() {
+        assert_eq!(
+            fmt("fun f x => {\n  x /* last */\n  // done\n} // f"),
+            "fun f x => {\n    x /* last */\n    // done\n} // f\n",
+        );
+    }
+
+    #[test]
+    fn keep_at_most_one_blank_line() {
+        assert_eq!(fmt("\n\na\n\n\n\nb\nc\n\n"), "a\n\nb\nc\n");
+    }
+
+    #[test]
+    fn do_not_format_invalid_source() {
+        assert_eq!(format("let = 1").unwrap_err().to_string(), "1:5: expected identifier, found ‘=’");
+    }
+
+    // Joins tokens with arbitrary whitespace and comments, which may change
+    // what the tokens parse to, or make them fail to parse.
+    fn source() -> impl Strategy<Value = String> {
+        let leaf = prop::sample::select(vec!["0", "42", "0xff", "1_000", "2.5", "1e3", "true", "a", "b", "f", "x"])
+            .prop_map(|token| vec![token.to_string()]);
+
+        let expr = leaf.prop_recursive(4, 32, 4, |inner| {
+            let op = prop::sample::select(vec![
+                "+", "-", "*", "/", "%", "**", "&", "|", "^", "<<", ">>", "==", "!=", "<", "<=", ">", ">=",
+            ]);
+            let unary = prop::sample::select(vec!["-", "+", "!"]);
+            prop_oneof![
+                (inner.clone(), op, inner.clone()).prop_map(|(lhs, op, rhs)| [lhs, vec![op.to_string()], rhs].concat()),
+                (unary, inner.clone()).prop_map(|(op, operand)| [vec![op.to_string()], operand].concat()),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(|stmts| [vec!["{".to_string()], stmts.concat(), vec!["}".to_string()]].concat()),
+                prop::collection::vec(inner.clone(), 1..3)
+                    .prop_map(|params| [vec!["f".to_string()], params.concat()].concat()),
+                inner.clone().prop_map(|value| [vec!["let".to_string(), "y".to_string(), "=".to_string()], value].concat()),
+                (inner.clone(), inner.clone(), inner.clone()).prop_map(|(condition, then_branch, else_branch)| {
+                    let keyword = |keyword: &str| vec![keyword.to_string()];
+                    [keyword("if"), condition, keyword("then"), then_branch, keyword("else"), else_branch].concat()
+                }),
+                inner.prop_map(|body| {
+                    [vec!["fun".to_string(), "g".to_string(), "x".to_string(), "=>".to_string()], body].concat()
+                }),
+            ]
+        });
+
+        let trivia = prop::sample::select(vec![" ", " ", " ", "", "\t", "\n", "\r\n\n", " /* c */ ", " // c\n", "/**/"]);
+        prop::collection::vec(expr, 1..4).prop_map(|stmts| stmts.concat()).prop_flat_map(move |tokens| {
+            prop::collection::vec(trivia.clone(), tokens.len()).prop_map(move |trivia| {
+                tokens.iter().zip(trivia).map(|(token, trivia)| format!("{}{}", token, trivia)).collect()
+            })
+        })
+    }
+
+    fn statements(source: &str) -> Vec<Statement> {
+        crate::parse(source).unwrap().into_statements().into_iter().map(EraseSpans::erase_spans).collect()
+    }
+
+    proptest! {
+        // Some of the sources don't parse.
+        #![proptest_config(ProptestConfig { max_global_rejects: 4096, ..ProptestConfig::default() })]
+
+        #[test]
+        fn formatting_preserves_the_program(source in source()) {
+            prop_assume!(crate::parse(&source).is_ok());
+            let formatted = fmt(&source);
+            prop_assert_eq!(statements(&formatted), statements(&source), "formatted:\n{}", formatted);
+            prop_assert_eq!(fmt(&formatted), formatted);
+        }
+    }
+}
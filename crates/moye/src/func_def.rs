@@ -1,64 +1,107 @@
+use crate::annotation::TypeAnnotation;
+use crate::diagnostic::{self, SyntaxError};
+use crate::lexer::{Span, Token, TokenKind};
 use crate::utils;
-use crate::env::Env;
 use crate::statements::Statement;
 
+/// `fun name params => body`, where parameters are names or annotated as
+/// `(name: Type)`, and `-> Type` after them annotates the value of the body.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct FuncDef {
-    pub(crate) name: String,
-    pub(crate) params: Vec<String>,
-    pub(crate) body: Box<Statement>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FuncDef {
+    pub name: String,
+    pub params: Vec<String>,
+    /// The annotation of each parameter, if any; as long as `params`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub param_types: Vec<Option<TypeAnnotation>>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub return_type: Option<TypeAnnotation>,
+    pub body: Box<Statement>,
+    pub span: Span,
 }
 
 impl FuncDef {
-    pub(crate) fn new(s: &str) -> Result<(&str, Self), String> {
-        let s = utils::tag("fun", s)?;
-        let (s, _) = utils::extract_whitespaces_with_error(s)?;
-        let (s, name) = utils::extract_ident(s)?;
-        dbg!(s);
-        dbg!(name);
-        let (s, _) = utils::extract_whitespaces(s);
+    pub(crate) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        utils::check_nesting(depth, &tokens[0])?;
+        let start = tokens;
+        let tokens = utils::tag(&TokenKind::Fun, tokens)?;
+        let (mut tokens, name) = utils::extract_ident_token(tokens)?;
 
-        let (s, params) = utils::sequence(
-            |s| utils::extract_ident(s).map(|(s, ident)| (s, ident.to_string())),
-            utils::extract_whitespaces,
-            s,
-        )?;
+        let (mut params, mut param_types) = (Vec::new(), Vec::new());
+        loop {
+            let (remainder, (param, ty)) = match tokens[0].kind {
+                TokenKind::LParen => {
+                    let (remainder, (param, ty)) = Self::annotated_param(tokens)?;
+                    (remainder, (param, Some(ty)))
+                },
+                _ => match utils::extract_ident_token(tokens) {
+                    Ok((remainder, param)) => (remainder, (param, None)),
+                    Err(_) => break,
+                },
+            };
+            tokens = remainder;
+            params.push(param);
+            param_types.push(ty);
+        }
 
-        dbg!(s);
-        let s = utils::tag("=>", s)?;
-        let (s, _) = utils::extract_whitespaces(s);
-        let (s, body) = Statement::new(s)?;
+        let (tokens, return_type) = TypeAnnotation::optional(&TokenKind::Arrow, tokens)?;
+        let tokens = utils::tag(&TokenKind::FatArrow, tokens).map_err(|error| match return_type {
+            Some(_) => error,
+            None => error.merge(SyntaxError::expected(
+                ["identifier".to_string(), diagnostic::token(&TokenKind::LParen), diagnostic::token(&TokenKind::Arrow)],
+                &tokens[0],
+            )),
+        })?;
+        let (tokens, body) = Statement::new(tokens, depth + 1)?;
         Ok((
-            s,
+            tokens,
             Self {
-                name: name.to_string(),
+                name,
                 params,
+                param_types,
+                return_type,
                 body: Box::new(body),
+                span: utils::consumed(start, tokens),
             },
         ))
     }
+}
 
-    pub(crate) fn eval(&self, env: &mut Env) -> Result<(), String> {
-        env.store_func(self.name.clone(), self.params.clone(), *self.body.clone());
-        Ok(())
+impl FuncDef {
+    // `(name: Type)`
+    fn annotated_param(tokens: &[Token]) -> Result<(&[Token], (String, TypeAnnotation)), SyntaxError> {
+        let tokens = utils::tag(&TokenKind::LParen, tokens)?;
+        let (tokens, param) = utils::extract_ident_token(tokens)?;
+        let tokens = utils::tag(&TokenKind::Colon, tokens)?;
+        let (tokens, ty) = TypeAnnotation::new(tokens)?;
+        let tokens = utils::tag(&TokenKind::RParen, tokens)?;
+        Ok((tokens, (param, ty)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::parse_str;
+    use crate::annotation::Type;
     use crate::expr::{BindingUsage, Operations, Block, Expression};
 
     #[test]
     fn parse_func_def_with_no_params_and_empty_body() {
         assert_eq!(
-            FuncDef::new("fun nothing => {}"),
+            parse_str(|tokens| FuncDef::new(tokens, 0), "fun nothing => {}"),
             Ok((
                 "",
                 FuncDef {
                     name: "nothing".to_string(),
                     params: Vec::new(),
-                    body: Box::new(Statement::Expression(Expression::Block(Block { stmts: Vec::new() }))),
+                    param_types: Vec::new(),
+                    return_type: None,
+                    body: Box::new(Statement::Expression(Expression::Block(Block {
+                        stmts: Vec::new(),
+                        span: Span::default(),
+                    }))),
+                    span: Span::default(),
                 }
             )),
         );
@@ -67,13 +110,19 @@ mod tests {
     #[test]
     fn parse_func_def_with_one_param_and_empty_body() {
         assert_eq!(
-            FuncDef::new("fun greet name => {}"),
+            parse_str(|tokens| FuncDef::new(tokens, 0), "fun greet name => {}"),
             Ok((
                 "",
                 FuncDef {
                     name: "greet".to_string(),
                     params: vec!["name".to_string()],
-                    body: Box::new(Statement::Expression(Expression::Block(Block { stmts: Vec::new() }))),
+                    param_types: vec![None],
+                    return_type: None,
+                    body: Box::new(Statement::Expression(Expression::Block(Block {
+                        stmts: Vec::new(),
+                        span: Span::default(),
+                    }))),
+                    span: Span::default(),
                 },
             )),
         );
@@ -82,23 +131,62 @@ mod tests {
     #[test]
     fn parse_func_def_with_multiple_params_and_non_empty_body() {
         assert_eq!(
-            FuncDef::new("fun add x y => x + y"),
+            parse_str(|tokens| FuncDef::new(tokens, 0), "fun add x y => x + y"),
             Ok((
                 "",
                 FuncDef {
                     name: "add".to_string(),
                     params: vec!["x".to_string(), "y".to_string()],
+                    param_types: vec![None, None],
+                    return_type: None,
                     body: Box::new(Statement::Expression(Expression::Operation {
                         lhs: Box::new(Expression::BindingUsage(BindingUsage {
-                            name: "x".to_string()
+                            name: "x".to_string(),
+                            span: Span::default(),
                         })),
                         rhs: Box::new(Expression::BindingUsage(BindingUsage {
-                            name: "y".to_string()
+                            name: "y".to_string(),
+                            span: Span::default(),
                         })),
-                        op: Operations::Add
-                    }))
+                        op: Operations::Add,
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
                 }
             ))
         );
     }
+
+    #[test]
+    fn parse_func_def_with_type_annotations() {
+        let annotation = |ty| Some(TypeAnnotation { ty, span: Span::default() });
+        assert_eq!(
+            parse_str(|tokens| FuncDef::new(tokens, 0), "fun scale (x: Float) n -> Float => x"),
+            Ok((
+                "",
+                FuncDef {
+                    name: "scale".to_string(),
+                    params: vec!["x".to_string(), "n".to_string()],
+                    param_types: vec![annotation(Type::Float), None],
+                    return_type: annotation(Type::Float),
+                    body: Box::new(Statement::Expression(Expression::BindingUsage(BindingUsage {
+                        name: "x".to_string(),
+                        span: Span::default(),
+                    }))),
+                    span: Span::default(),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn cannot_parse_func_def_with_incomplete_annotations() {
+        assert_eq!(parse_str(|tokens| FuncDef::new(tokens, 0), "fun f (x) => x"), Err("expected ‘:’, found ‘)’".to_string()));
+        assert_eq!(parse_str(|tokens| FuncDef::new(tokens, 0), "fun f (x: Int => x"), Err("expected ‘)’, found ‘=>’".to_string()));
+        assert_eq!(parse_str(|tokens| FuncDef::new(tokens, 0), "fun f -> => 1"), Err("expected type, found ‘=>’".to_string()));
+        assert_eq!(
+            parse_str(|tokens| FuncDef::new(tokens, 0), "fun f x 1 => x"),
+            Err("expected identifier, ‘(’, ‘->’ or ‘=>’, found ‘1’".to_string()),
+        );
+    }
 }
@@ -0,0 +1,546 @@
+/*
+ * Splits source code into tokens, skipping whitespace and comments, so that
+ * the parser never has to look at characters
+ */
+
+use crate::diagnostic::SyntaxError;
+use crate::utils::{self, NumberLiteral};
+use std::fmt;
+
+/// Byte offsets into the source code, `end` exclusive. Nodes built by hand
+/// rather than parsed can use `Span::default()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// From the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    pub(crate) span: Span,
+    /// Whether there is a line break between this token and the previous
+    /// one. Line breaks inside block comments don't count.
+    pub(crate) newline_before: bool,
+}
+
+impl Token {
+    // Whether there is whitespace or a comment between `previous` and this
+    // token.
+    pub(crate) fn is_separated_from(&self, previous: &Token) -> bool {
+        self.span.start > previous.span.end
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+    Ident(String),
+    /// As written, including the radix prefix and `_` separators.
+    Int { literal: String, radix: u32 },
+    /// As written, including `_` separators.
+    Float(String),
+
+    Let,
+    Fun,
+    If,
+    Then,
+    Else,
+    True,
+    False,
+
+    Equals,
+    FatArrow,
+    Arrow,
+    Colon,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+
+    Plus,
+    Minus,
+    Star,
+    StarStar,
+    Slash,
+    Percent,
+    Amp,
+    Pipe,
+    Caret,
+    Shl,
+    Shr,
+    Bang,
+    EqEq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    /// Always the last token, so that errors at the end of the input have a
+    /// position too.
+    Eof,
+}
+
+impl TokenKind {
+    fn keyword(ident: &str) -> Option<Self> {
+        match ident {
+            "let" => Some(Self::Let),
+            "fun" => Some(Self::Fun),
+            // Conditionals and bools.
+            "if" => Some(Self::If),
+            "then" => Some(Self::Then),
+            "else" => Some(Self::Else),
+            "true" => Some(Self::True),
+            "false" => Some(Self::False),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_keyword(&self) -> bool {
+        matches!(self, Self::Let | Self::Fun | Self::If | Self::Then | Self::Else | Self::True | Self::False)
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Self::Ident(name) => name,
+            Self::Int { literal, .. } => literal,
+            Self::Float(literal) => literal,
+            Self::Let => "let",
+            Self::Fun => "fun",
+            Self::If => "if",
+            Self::Then => "then",
+            Self::Else => "else",
+            Self::True => "true",
+            Self::False => "false",
+            Self::Equals => "=",
+            Self::FatArrow => "=>",
+            Self::Arrow => "->",
+            Self::Colon => ":",
+            Self::LBrace => "{",
+            Self::RBrace => "}",
+            Self::LParen => "(",
+            Self::RParen => ")",
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Star => "*",
+            Self::StarStar => "**",
+            Self::Slash => "/",
+            Self::Percent => "%",
+            Self::Amp => "&",
+            Self::Pipe => "|",
+            Self::Caret => "^",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::Bang => "!",
+            Self::EqEq => "==",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eof => "end of input",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// Longer punctuation comes first so that `**` isn't read as `*`.
+const PUNCTUATION: &[(&str, TokenKind)] = &[
+    ("=>", TokenKind::FatArrow),
+    ("->", TokenKind::Arrow),
+    ("**", TokenKind::StarStar),
+    ("<<", TokenKind::Shl),
+    (">>", TokenKind::Shr),
+    ("==", TokenKind::EqEq),
+    ("!=", TokenKind::NotEq),
+    ("<=", TokenKind::Le),
+    (">=", TokenKind::Ge),
+    ("=", TokenKind::Equals),
+    ("<", TokenKind::Lt),
+    (">", TokenKind::Gt),
+    ("{", TokenKind::LBrace),
+    ("}", TokenKind::RBrace),
+    ("(", TokenKind::LParen),
+    (")", TokenKind::RParen),
+    (":", TokenKind::Colon),
+    ("+", TokenKind::Plus),
+    ("-", TokenKind::Minus),
+    ("*", TokenKind::Star),
+    ("/", TokenKind::Slash),
+    ("%", TokenKind::Percent),
+    ("&", TokenKind::Amp),
+    ("|", TokenKind::Pipe),
+    ("^", TokenKind::Caret),
+    ("!", TokenKind::Bang),
+];
+
+// Invalid characters and number literals are skipped after being reported,
+// so that every one of them is reported.
+pub(crate) fn lex(source: &str) -> Result<Vec<Token>, Vec<SyntaxError>> {
+    lex_with_comments(source).map(|(tokens, _)| tokens)
+}
+
+/// Also returns the spans of comments, in source order.
+pub(crate) fn lex_with_comments(source: &str) -> Result<(Vec<Token>, Vec<Span>), Vec<SyntaxError>> {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
+    let mut s = source;
+    let offset = |s: &str| source.len() - s.len();
+
+    loop {
+        let (after_trivia, newline_before) = match extract_whitespaces_and_comments(s, &mut |comment, len| {
+            comments.push(Span { start: offset(comment), end: offset(comment) + len });
+        }) {
+            Ok(extracted) => extracted,
+            Err((comment, message)) => {
+                let span = Span { start: offset(comment), end: source.len() };
+                errors.push(SyntaxError::new(span, message));
+                ("", false)
+            },
+        };
+
+        let start = offset(after_trivia);
+        if after_trivia.is_empty() {
+            tokens.push(Token {
+                kind: TokenKind::Eof,
+                span: Span { start, end: start },
+                newline_before,
+            });
+            return if errors.is_empty() { Ok((tokens, comments)) } else { Err(errors) };
+        }
+
+        match extract_token(after_trivia) {
+            Ok((remainder, kind)) => {
+                tokens.push(Token {
+                    kind,
+                    span: Span { start, end: offset(remainder) },
+                    newline_before,
+                });
+                s = remainder;
+            },
+            Err((remainder, message)) => {
+                errors.push(SyntaxError::new(Span { start, end: offset(remainder) }, message));
+                s = remainder;
+            },
+        }
+    }
+}
+
+// On errors, also returns the input after the invalid characters.
+fn extract_token(s: &str) -> Result<(&str, TokenKind), (&str, String)> {
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        return extract_number(s).map_err(|message| {
+            let (remainder, _) = utils::take_while(|c| c == '.' || unicode_ident::is_xid_continue(c), s);
+            (remainder, message)
+        });
+    }
+
+    if let Ok((remainder, ident)) = utils::extract_ident(s) {
+        let kind = TokenKind::keyword(ident).unwrap_or_else(|| TokenKind::Ident(ident.to_string()));
+        return Ok((remainder, kind));
+    }
+
+    for (text, kind) in PUNCTUATION {
+        if let Some(remainder) = s.strip_prefix(text) {
+            return Ok((remainder, kind.clone()));
+        }
+    }
+
+    let unexpected = s.chars().next().unwrap();
+    Err((&s[unexpected.len_utf8()..], format!("unexpected character ‘{}’", unexpected)))
+}
+
+fn extract_number(s: &str) -> Result<(&str, TokenKind), String> {
+    let (remainder, literal) = utils::extract_number(s)?;
+
+    // `12abc` is a typo rather than a number followed by an identifier.
+    let (_, suffix) = utils::take_while(unicode_ident::is_xid_continue, remainder);
+    if !suffix.is_empty() {
+        return Err(format!("invalid suffix ‘{}’ on number literal", suffix));
+    }
+
+    let kind = match literal {
+        NumberLiteral::Integer { radix, .. } => TokenKind::Int {
+            literal: s[..s.len() - remainder.len()].to_string(),
+            radix,
+        },
+        NumberLiteral::Float(literal) => TokenKind::Float(literal.to_string()),
+    };
+    Ok((remainder, kind))
+}
+
+// Also reports whether a line break was skipped, and passes the input from
+// every comment on, with the length of the comment, to `comment`. Line
+// comments run up to (but not including) the next newline; block comments
+// nest. An unterminated block comment is returned with the error.
+fn extract_whitespaces_and_comments<'s>(
+    mut s: &'s str,
+    comment: &mut impl FnMut(&'s str, usize),
+) -> Result<(&'s str, bool), (&'s str, String)> {
+    let mut newline = false;
+
+    loop {
+        let (remainder, whitespace) = utils::take_while(|c| matches!(c, ' ' | '\t' | '\r' | '\n'), s);
+        newline |= whitespace.contains('\n');
+        s = remainder;
+
+        let remainder = if let Some(after_slashes) = s.strip_prefix("//") {
+            utils::take_while(|c| c != '\n', after_slashes).0
+        } else if s.starts_with("/*") {
+            extract_block_comment(s).map_err(|message| (s, message))?
+        } else {
+            return Ok((s, newline));
+        };
+        comment(s, s.len() - remainder.len());
+        s = remainder;
+    }
+}
+
+fn extract_block_comment(s: &str) -> Result<&str, String> {
+    let mut remainder = &s[2..];
+    let mut depth = 1;
+
+    while depth > 0 {
+        if let Some(after_open) = remainder.strip_prefix("/*") {
+            depth += 1;
+            remainder = after_open;
+        } else if let Some(after_close) = remainder.strip_prefix("*/") {
+            depth -= 1;
+            remainder = after_close;
+        } else {
+            let mut chars = remainder.chars();
+            if chars.next().is_none() {
+                return Err("unterminated block comment".to_string());
+            }
+            remainder = chars.as_str();
+        }
+    }
+
+    Ok(remainder)
+}
+
+/*
+ * Runs a parser on the tokens of `s`, returning the source code that was not
+ * consumed, so that parser tests can be written in terms of source code.
+ * Spans are left out of the result
+ */
+#[cfg(test)]
+pub(crate) fn parse_str<T: crate::ast::EraseSpans>(
+    parser: impl for<'t> Fn(&'t [Token]) -> Result<(&'t [Token], T), SyntaxError>,
+    s: &str,
+) -> Result<(&str, T), String> {
+    let tokens = lex(s).map_err(|errors| errors[0].to_string())?;
+    let (remainder, parsed) = parser(&tokens).map_err(|error| error.to_string())?;
+    Ok((&s[remainder[0].span.start..], parsed.erase_spans()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Leaves out the final `Eof`, and reports only the first error.
+    fn kinds(s: &str) -> Result<Vec<TokenKind>, String> {
+        let mut tokens = lex(s).map_err(|errors| errors[0].to_string())?;
+        assert_eq!(tokens.pop().map(|token| token.kind), Some(TokenKind::Eof));
+        Ok(tokens.into_iter().map(|token| token.kind).collect())
+    }
+
+    fn ident(name: &str) -> TokenKind {
+        TokenKind::Ident(name.to_string())
+    }
+
+    #[test]
+    fn lex_binding_def() {
+        assert_eq!(
+            kinds("let a = 10"),
+            Ok(vec![
+                TokenKind::Let,
+                ident("a"),
+                TokenKind::Equals,
+                TokenKind::Int { literal: "10".to_string(), radix: 10 },
+            ]),
+        );
+    }
+
+    #[test]
+    fn lex_type_annotations() {
+        assert_eq!(
+            kinds("fun f (x: Int) -> Int =>"),
+            Ok(vec![
+                TokenKind::Fun,
+                ident("f"),
+                TokenKind::LParen,
+                ident("x"),
+                TokenKind::Colon,
+                ident("Int"),
+                TokenKind::RParen,
+                TokenKind::Arrow,
+                ident("Int"),
+                TokenKind::FatArrow,
+            ]),
+        );
+    }
+
+    #[test]
+    fn lex_signs_as_separate_tokens() {
+        assert_eq!(
+            kinds("a - -1"),
+            Ok(vec![ident("a"), TokenKind::Minus, TokenKind::Minus, TokenKind::Int { literal: "1".to_string(), radix: 10 }]),
+        );
+    }
+
+    #[test]
+    fn lex_keyword_prefix_as_ident() {
+        assert_eq!(kinds("letter funny"), Ok(vec![ident("letter"), ident("funny")]));
+    }
+
+    #[test]
+    fn lex_conditional_keywords() {
+        assert_eq!(
+            kinds("if true then x else false"),
+            Ok(vec![TokenKind::If, TokenKind::True, TokenKind::Then, ident("x"), TokenKind::Else, TokenKind::False]),
+        );
+        assert_eq!(kinds("iffy"), Ok(vec![ident("iffy")]));
+    }
+
+    #[test]
+    fn lex_idents_with_underscores_and_unicode() {
+        assert_eq!(kinds("_a b_2 café"), Ok(vec![ident("_a"), ident("b_2"), ident("café")]));
+    }
+
+    #[test]
+    fn lex_longest_punctuation() {
+        assert_eq!(
+            kinds("=>=**<<>>*"),
+            Ok(vec![
+                TokenKind::FatArrow,
+                TokenKind::Equals,
+                TokenKind::StarStar,
+                TokenKind::Shl,
+                TokenKind::Shr,
+                TokenKind::Star,
+            ]),
+        );
+        assert_eq!(
+            kinds("==!=!<=<>=>"),
+            Ok(vec![
+                TokenKind::EqEq,
+                TokenKind::NotEq,
+                TokenKind::Bang,
+                TokenKind::Le,
+                TokenKind::Lt,
+                TokenKind::Ge,
+                TokenKind::Gt,
+            ]),
+        );
+    }
+
+    #[test]
+    fn lex_number_literals() {
+        assert_eq!(
+            kinds("0xFF_FF 1_000 2.5e3"),
+            Ok(vec![
+                TokenKind::Int { literal: "0xFF_FF".to_string(), radix: 16 },
+                TokenKind::Int { literal: "1_000".to_string(), radix: 10 },
+                TokenKind::Float("2.5e3".to_string()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn lex_minus_separately_from_number() {
+        assert_eq!(
+            kinds("-5"),
+            Ok(vec![TokenKind::Minus, TokenKind::Int { literal: "5".to_string(), radix: 10 }]),
+        );
+    }
+
+    #[test]
+    fn reject_number_with_suffix() {
+        assert_eq!(kinds("12abc"), Err("invalid suffix ‘abc’ on number literal".to_string()));
+        assert_eq!(kinds("0b102"), Err("invalid digit ‘2’ in binary literal".to_string()));
+    }
+
+    #[test]
+    fn reject_unexpected_character() {
+        assert_eq!(kinds("a @ b"), Err("unexpected character ‘@’".to_string()));
+    }
+
+    #[test]
+    fn record_spans() {
+        let tokens = lex("ab  +\n1").unwrap();
+        let spans: Vec<_> = tokens.iter().map(|token| (token.span.start, token.span.end)).collect();
+        assert_eq!(spans, vec![(0, 2), (4, 5), (6, 7), (7, 7)]);
+    }
+
+    #[test]
+    fn record_newlines_before_tokens() {
+        let tokens = lex("a /* \n */ b // c\r\n\td\n").unwrap();
+        let newlines: Vec<_> = tokens.iter().map(|token| token.newline_before).collect();
+        assert_eq!(newlines, vec![false, false, true, true]);
+    }
+
+    #[test]
+    fn skip_tabs_and_carriage_returns() {
+        assert_eq!(kinds("\ta\r\n\tb\r\n"), Ok(vec![ident("a"), ident("b")]));
+    }
+
+    #[test]
+    fn skip_nested_block_comments() {
+        assert_eq!(kinds("/* a /* b */ c */ x"), Ok(vec![ident("x")]));
+        assert_eq!(kinds("/**/x"), Ok(vec![ident("x")]));
+    }
+
+    #[test]
+    fn skip_line_comment_at_end_of_input() {
+        assert_eq!(kinds("x // to the end"), Ok(vec![ident("x")]));
+    }
+
+    #[test]
+    fn record_comments() {
+        let source = "// first\r\na /* b /* c */ */ // last";
+        let (_, comments) = lex_with_comments(source).unwrap();
+        let texts: Vec<_> = comments.iter().map(|span| &source[span.start..span.end]).collect();
+        assert_eq!(texts, vec!["// first\r", "/* b /* c */ */", "// last"]);
+    }
+
+    #[test]
+    fn reject_unterminated_block_comment() {
+        assert_eq!(kinds("1 /* a /* b */ x"), Err("unterminated block comment".to_string()));
+    }
+
+    #[test]
+    fn report_every_invalid_token() {
+        let errors = lex("a @ 12abc + 0b2 #").unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|error| (error.span.start, error.to_string())).collect();
+        assert_eq!(
+            messages,
+            vec![
+                (2, "unexpected character ‘@’".to_string()),
+                (4, "invalid suffix ‘abc’ on number literal".to_string()),
+                (12, "invalid digit ‘2’ in binary literal".to_string()),
+                (16, "unexpected character ‘#’".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn tell_separated_tokens_apart() {
+        let tokens = lex("f -x-/**/y").unwrap();
+        assert!(tokens[1].is_separated_from(&tokens[0]));
+        assert!(!tokens[2].is_separated_from(&tokens[1]));
+        assert!(!tokens[3].is_separated_from(&tokens[2]));
+        assert!(tokens[4].is_separated_from(&tokens[3]));
+    }
+}
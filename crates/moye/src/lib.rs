@@ -3,28 +3,287 @@ mod expr;
 mod binding_def;
 mod statements;
 mod func_def;
+mod annotation;
+mod convert;
+mod native;
+mod limits;
+mod cancel;
+mod error;
+mod arith;
+mod builtins;
 
 mod env;
+mod resolved;
+mod bytecode;
+mod compiler;
+mod vm;
+mod program;
+mod moyec;
+pub mod ast;
+mod formatter;
+mod optimizer;
+mod resolver;
+mod typechecker;
+mod diagnostic;
+mod lexer;
 mod utils;
 
 pub use env::Env;
+pub use vm::Engine;
+pub use program::{compile, Program};
 pub use val::Val;
+pub use num_bigint::BigInt;
+pub use convert::{FromVal, IntoVal};
+pub use native::{IntoNativeFunc, NativeFunc};
+pub use limits::{Limits, DEFAULT_MAX_CALL_DEPTH, DEFAULT_MAX_CALL_NESTING, MAX_NESTING};
+pub use cancel::CancelToken;
+pub use error::{Error, ErrorKind};
+pub use diagnostic::{Diagnostic, ParseError, Severity};
+pub use formatter::format;
+pub use optimizer::Passes;
+pub use resolver::check;
+pub use typechecker::check_types;
 
-#[derive(Debug)]
-pub struct Parse(statements::Statement);
+/// A parsed program.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parse(Vec<ast::Statement>);
 
 impl Parse {
-    pub fn eval(&self, env: &mut Env) -> Result<Val, String> {
-        self.0.eval(env)
+    pub fn statements(&self) -> &[ast::Statement] {
+        &self.0
     }
+
+    pub fn statements_mut(&mut self) -> &mut Vec<ast::Statement> {
+        &mut self.0
+    }
+
+    pub fn into_statements(self) -> Vec<ast::Statement> {
+        self.0
+    }
+
+    /// Rewrites the program into an equivalent one that takes fewer steps to
+    /// evaluate, with the given passes. Programs nested deeper than
+    /// `MAX_NESTING`, which only generated ones can be, stay as they are.
+    pub fn optimize(&mut self, passes: Passes) {
+        optimizer::optimize(&mut self.0, passes);
+    }
+
+    /// Evaluates the statements in order, returning the value of the last one
+    /// (or `Unit` if there are none). Top-level definitions made before an
+    /// error stay in `env`.
+    pub fn eval(&self, env: &mut Env) -> Result<Val, Error> {
+        self.eval_cancellable(env, &CancelToken::default())
+    }
+
+    /// Like `eval`, but stops with an error at the next loop iteration or
+    /// function call once `cancel_token` is cancelled.
+    pub fn eval_cancellable(&self, env: &mut Env, cancel_token: &CancelToken) -> Result<Val, Error> {
+        env.start_evaluation(cancel_token);
+        env.eval(&self.0)
+    }
+}
+
+/// For programs generated rather than parsed.
+impl From<Vec<ast::Statement>> for Parse {
+    fn from(stmts: Vec<ast::Statement>) -> Self {
+        Self(stmts)
+    }
+}
+
+/// Reports every syntax error in `s`: after an error, parsing resumes with
+/// the next statement. `f -x` passes `-x` to `f`, while `f - x` and `f-x`
+/// subtract `x` from `f`, whatever `f` names.
+pub fn parse(s:&str) -> Result<Parse, ParseError> {
+    let tokens = lexer::lex(s).map_err(|errors| ParseError::new(s, &errors))?;
+    let (stmts, errors) = parse_tokens(&tokens);
+    if errors.is_empty() { Ok(Parse(stmts)) } else { Err(ParseError::new(s, &errors)) }
 }
 
-pub fn parse(s:&str) -> Result<Parse, String> {
-    let (s, stmt) = statements::Statement::new(s)?;
-    if s.is_empty() {
-        Ok(Parse(stmt))
-    } else {
-        Err("input was not consumed fully by parser".to_string())
+fn parse_tokens(tokens: &[lexer::Token]) -> (Vec<ast::Statement>, Vec<diagnostic::SyntaxError>) {
+    let mut tokens = tokens;
+    let mut stmts = Vec::new();
+    let mut errors = Vec::new();
+
+    loop {
+        let (remainder, parsed, error) = statements::Statement::new_sequence(tokens, 0);
+        stmts.extend(parsed);
+        if remainder[0].kind == lexer::TokenKind::Eof {
+            break;
+        }
+        tokens = statements::Statement::recover(remainder, &error);
+        errors.push(error);
     }
+    (stmts, errors)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn parse_with_surrounding_comments() {
+        let parse = parse("// leading\n1 + /* two */ 2 // trailing").unwrap();
+        assert_eq!(env::on_engines(|env| parse.eval(env)), Ok(Val::Number(3)));
+    }
+
+    #[test]
+    fn parse_multiple_statements() {
+        let parse = parse("let letter = 2\nfun double x => x * 2\ndouble letter").unwrap();
+        assert_eq!(env::on_engines(|env| parse.eval(env)), Ok(Val::Number(4)));
+    }
+
+    #[test]
+    fn eval_recursive_function() {
+        let parse = parse("fun fib n => if n < 2 then n else { fib n - 1 } + { fib n - 2 }\nfib 20").unwrap();
+        assert_eq!(env::on_engines(|env| parse.eval(env)), Ok(Val::Number(6765)));
+    }
+
+    #[test]
+    fn eval_conditionals_on_comparisons() {
+        let parse = parse(
+            "fun sign x => if x < 0 then -1 else if x == 0 then 0 else 1\n\
+             let signs = { sign -2.5 } + { sign 0 } * 10 + { sign 7 } * 100\n\
+             let between = { 1 <= signs } & { signs < 100 } | false\n\
+             if between then signs else 0",
+        )
+        .unwrap();
+        assert_eq!(env::on_engines(|env| parse.eval(env)), Ok(Val::Number(99)));
+        assert_eq!(
+            env::on_engines(|env| super::parse("if 1 then 2 else 3").unwrap().eval(env)).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Type, "condition of ‘if’ must be a bool, found int".to_string())),
+        );
+        assert_eq!(
+            env::on_engines(|env| super::parse("true < false").unwrap().eval(env)).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Type, "cannot compare bool with bool".to_string())),
+        );
+    }
+
+    #[test]
+    fn parse_empty_input() {
+        assert_eq!(parse(" // nothing\n").unwrap().eval(&mut Env::default()), Ok(Val::Unit));
+    }
+
+    #[test]
+    fn report_unterminated_block_comment() {
+        assert_eq!(
+            parse("1 /* never closed").unwrap_err().to_string(),
+            "1:3: unterminated block comment",
+        );
+    }
+
+    #[test]
+    fn report_furthest_failure_with_expected_tokens() {
+        assert_eq!(
+            parse("let x = {\n  1 +\n}").unwrap_err().to_string(),
+            "3:1: expected identifier, number, ‘!’, ‘+’, ‘-’, ‘false’, ‘if’, ‘true’ or ‘{’, found ‘}’",
+        );
+        assert_eq!(
+            parse("fun f x 1 => x").unwrap_err().to_string(),
+            "1:9: expected identifier, ‘(’, ‘->’ or ‘=>’, found ‘1’",
+        );
+    }
+
+    #[test]
+    fn report_all_expected_tokens_after_statement() {
+        assert_eq!(
+            parse("{ 1 = }").unwrap_err().to_string(),
+            "1:5: expected identifier, number, ‘!=’, ‘!’, ‘%’, ‘&’, ‘**’, ‘*’, ‘+’, ‘-’, ‘/’, ‘<<’, ‘<=’, ‘<’, \
+             ‘==’, ‘>=’, ‘>>’, ‘>’, ‘^’, ‘false’, ‘fun’, ‘if’, ‘let’, ‘true’, ‘{’, ‘|’ or ‘}’, found ‘=’",
+        );
+    }
+
+    #[test]
+    fn report_errors_in_every_statement() {
+        let error = parse("let a = 1 +\nlet b = 2\nfun f => {\n  let = 3\n}\nlet c 4\nb").unwrap_err();
+        let positions: Vec<_> = error.diagnostics.iter().map(|d| (d.line, d.column)).collect();
+        assert_eq!(positions, vec![(2, 1), (4, 7), (6, 7)]);
+        assert_eq!(error.diagnostics[2].message, "expected ‘:’ or ‘=’, found ‘4’");
+    }
+
+    #[test]
+    fn report_every_lexer_error() {
+        let error = parse("1 @ 2\n3 $").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:3: unexpected character ‘@’\n2:3: unexpected character ‘$’",
+        );
+    }
+
+    #[test]
+    fn propagate_errors_as_boxed_errors() {
+        let run = |source: &str| -> Result<Val, Box<dyn std::error::Error>> {
+            Ok(parse(source)?.eval(&mut Env::default())?)
+        };
+        assert_eq!(run("1 @ 2").unwrap_err().to_string(), "1:3: unexpected character ‘@’");
+        assert_eq!(run("1 / 0").unwrap_err().to_string(), "division by zero");
+    }
+
+    #[test]
+    fn reject_code_nested_too_deeply() {
+        let too_deep = [
+            format!("{}1{}", "{ ".repeat(10_000), " }".repeat(10_000)),
+            format!("1{}", " + 1".repeat(10_000)),
+            format!("{}1", "- ".repeat(10_000)),
+            format!("2{}", " ** 1".repeat(10_000)),
+            format!("{}1{}", "if true then ".repeat(10_000), " else 0".repeat(10_000)),
+            format!("{}1", "fun f x => ".repeat(10_000)),
+            format!("{}1{}", "{ 1 + ".repeat(10_000), " }".repeat(10_000)),
+        ];
+        for source in too_deep {
+            let error = parse(&source).unwrap_err();
+            assert_eq!(error.diagnostics[0].message, "code nested more than 128 levels deep");
+        }
+        assert_eq!(
+            parse(&format!("{}1{}", "{ ".repeat(MAX_NESTING), " }".repeat(MAX_NESTING))).unwrap_err().to_string(),
+            "1:257: code nested more than 128 levels deep",
+        );
+    }
+
+    #[test]
+    fn accept_code_nested_as_deeply_as_allowed() {
+        let sources = [
+            format!("{}1{}", "{ ".repeat(MAX_NESTING - 1), " }".repeat(MAX_NESTING - 1)),
+            format!("1{}", " + 1".repeat(MAX_NESTING - 1)),
+            format!("{}{{ 1 }}{}", "{ 1 + ".repeat(MAX_NESTING / 2 - 1), " }".repeat(MAX_NESTING / 2 - 1)),
+        ];
+        for source in sources {
+            let mut parse = parse(&source).unwrap();
+            assert_eq!(check(&source, &Env::default()), Ok(Vec::new()));
+            assert!(check_types(&source, &Env::default()).is_ok());
+            assert!(format(&source).is_ok());
+            parse.optimize(Passes::default());
+            assert!(env::on_engines(|env| parse.eval(env)).is_ok());
+        }
+    }
+
+    #[test]
+    fn leave_generated_code_nested_too_deeply_to_fail() {
+        let mut expression = ast::Expression::Number(ast::Number { value: 1, span: ast::Span::default() });
+        for _ in 0..MAX_NESTING {
+            expression = ast::Expression::Unary {
+                op: ast::UnaryOperations::Neg,
+                operand: Box::new(expression),
+                span: ast::Span::default(),
+            };
+        }
+        let mut parse = Parse::from(vec![ast::Statement::Expression(expression)]);
+        let generated = parse.clone();
+        parse.optimize(Passes::default());
+        assert_eq!(parse, generated);
+        assert_eq!(
+            env::on_engines(|env| parse.eval(env)).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Limit, "code nested more than 128 levels deep".to_string())),
+        );
+    }
+
+    #[test]
+    fn count_columns_in_characters() {
+        assert_eq!(
+            parse("größe +").unwrap_err().to_string(),
+            "1:8: expected identifier, number, ‘!’, ‘+’, ‘-’, ‘false’, ‘if’, ‘true’ or ‘{’, found end of input",
+        );
+    }
+}
@@ -0,0 +1,289 @@
+/*
+ * Limits on how much work a single evaluation may do, so that runaway or
+ * malicious scripts fail with an error instead of hanging or overflowing the
+ * stack of the host
+ */
+
+use crate::cancel::CancelToken;
+use crate::error::{Error, ErrorKind};
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+/// A call of a small recursive function, like `fun f x => { if x == 0 then 0
+/// else 1 + { f x - 1 } }`, takes about 9.5 KiB of the stack of the host on
+/// the tree walker in debug builds, and 2.5 KiB in release builds, so this
+/// many fit into half of the default 2 MiB stack of spawned threads.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100;
+
+/// How deeply expressions and function definitions may nest in a program.
+/// Parsing nests deepest on the stack of the host, with about 6 KiB per level
+/// in debug builds, so the parser, the resolver and the optimizer stay within
+/// 1 MiB.
+pub const MAX_NESTING: usize = 128;
+
+pub(crate) fn nested_too_deeply() -> String {
+    format!("code nested more than {} levels deep", MAX_NESTING)
+}
+
+/// The tree walker takes up to 1.8 KiB of the stack of the host per level of
+/// call nesting in debug builds, so this many levels take about 1.1 MiB,
+/// which leaves room for the deepest code within the last call.
+pub const DEFAULT_MAX_CALL_NESTING: usize = 640;
+
+/// The deadline and the cancel token are only checked every this many steps,
+/// and at every call, since reading them is much slower than a step itself.
+const STEPS_BETWEEN_CHECKS: u64 = 1024;
+
+/// The defaults stop recursion before it overflows the stack of the host,
+/// but not loops written as calls in tail position, like `fun f x => f x`,
+/// which run forever in constant space. Evaluations of code that isn't
+/// trusted need `fuel` or a `timeout` as well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Maximum number of evaluation steps (roughly, expressions evaluated).
+    pub fuel: Option<u64>,
+    /// Maximum number of nested calls to functions defined in moye code.
+    /// Calls in tail position replace their caller rather than nest in it.
+    pub max_call_depth: Option<usize>,
+    /// Maximum sum of how deeply the calls in progress are nested, each in
+    /// the expressions of the function body it is made in, so that `f { g
+    /// x }` counts more than `f x`. Calls from the host count 1. Both
+    /// engines enforce it the same way, but only the tree walker needs it:
+    /// without it, deep recursion may overflow the stack of the host there.
+    pub max_call_nesting: Option<usize>,
+    /// Maximum wall-clock time an evaluation may take.
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self {
+            fuel: None,
+            max_call_depth: None,
+            max_call_nesting: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_call_nesting: Some(DEFAULT_MAX_CALL_NESTING),
+            ..Self::unlimited()
+        }
+    }
+}
+
+/*
+ * Tracks how much of the limits the current evaluation has used up; shared
+ * by an environment and all of its children
+ */
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Budget {
+    limits: Cell<Limits>,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    nesting: Cell<usize>,
+    deadline: Cell<Option<Instant>>,
+    cancel_token: RefCell<CancelToken>,
+}
+
+impl Budget {
+    pub(crate) fn set_limits(&self, limits: Limits) {
+        self.limits.set(limits);
+    }
+
+    pub(crate) fn limits(&self) -> Limits {
+        self.limits.get()
+    }
+
+    pub(crate) fn set_cancel_token(&self, cancel_token: CancelToken) {
+        self.cancel_token.replace(cancel_token);
+    }
+
+    pub(crate) fn start(&self) {
+        self.steps.set(0);
+        self.depth.set(0);
+        self.nesting.set(0);
+        self.deadline.set(self.limits().timeout.map(|timeout| Instant::now() + timeout));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn steps(&self) -> u64 {
+        self.steps.get()
+    }
+
+    #[inline]
+    pub(crate) fn step(&self) -> Result<(), Error> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+
+        if let Some(fuel) = self.limits().fuel
+            && steps > fuel
+        {
+            return Err(Error::new(ErrorKind::Limit, format!("evaluation ran out of fuel after {} steps", fuel)));
+        }
+
+        if steps.is_multiple_of(STEPS_BETWEEN_CHECKS) {
+            self.check_cancelled()?;
+            self.check_deadline()?;
+        }
+
+        Ok(())
+    }
+
+    /// `nesting` is how deeply the call is nested in the body it is made in.
+    pub(crate) fn enter_call(&self, nesting: usize) -> Result<CallGuard<'_>, Error> {
+        let depth = self.depth.get() + 1;
+
+        if let Some(max_call_depth) = self.limits().max_call_depth
+            && depth > max_call_depth
+        {
+            return Err(Error::new(ErrorKind::Limit, format!("maximum call depth of {} exceeded", max_call_depth)));
+        }
+
+        let total_nesting = self.nesting.get() + nesting;
+        if let Some(max_call_nesting) = self.limits().max_call_nesting
+            && total_nesting > max_call_nesting
+        {
+            return Err(Error::new(ErrorKind::Limit, format!("maximum call nesting of {} exceeded", max_call_nesting)));
+        }
+
+        self.check_cancelled()?;
+        self.check_deadline()?;
+        self.depth.set(depth);
+        self.nesting.set(total_nesting);
+        Ok(CallGuard(self, nesting))
+    }
+
+    /// Checks what `enter_call` checks apart from the depth, for a call that
+    /// replaces the current one.
+    pub(crate) fn tail_call(&self) -> Result<(), Error> {
+        self.check_cancelled()?;
+        self.check_deadline()
+    }
+
+    fn check_cancelled(&self) -> Result<(), Error> {
+        if self.cancel_token.borrow().is_cancelled() {
+            Err(Error::new(ErrorKind::Cancelled, "evaluation was cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_deadline(&self) -> Result<(), Error> {
+        match (self.deadline.get(), self.limits().timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                Err(Error::new(ErrorKind::Limit, format!("evaluation timed out after {:?}", timeout)))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/*
+ * Leaves the call again when dropped, so the depth is correct even when the
+ * call fails
+ */
+pub(crate) struct CallGuard<'a>(&'a Budget, usize);
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.0.depth.set(self.0.depth.get() - 1);
+        self.0.nesting.set(self.0.nesting.get() - self.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_out_of_fuel() {
+        let budget = Budget::default();
+        budget.set_limits(Limits { fuel: Some(2), ..Limits::default() });
+        budget.start();
+        assert_eq!(budget.step(), Ok(()));
+        assert_eq!(budget.step(), Ok(()));
+        assert_eq!(budget.step(), Err(Error::new(ErrorKind::Limit, "evaluation ran out of fuel after 2 steps")));
+    }
+
+    #[test]
+    fn start_refuels() {
+        let budget = Budget::default();
+        budget.set_limits(Limits { fuel: Some(1), ..Limits::default() });
+        budget.start();
+        assert_eq!(budget.step(), Ok(()));
+        budget.start();
+        assert_eq!(budget.step(), Ok(()));
+    }
+
+    #[test]
+    fn exceed_call_depth() {
+        let budget = Budget::default();
+        budget.set_limits(Limits { max_call_depth: Some(1), ..Limits::default() });
+        budget.start();
+        let guard = budget.enter_call(0).unwrap();
+        assert_eq!(
+            budget.enter_call(0).err(),
+            Some(Error::new(ErrorKind::Limit, "maximum call depth of 1 exceeded")),
+        );
+        drop(guard);
+        assert!(budget.enter_call(0).is_ok());
+    }
+
+    #[test]
+    fn exceed_call_nesting() {
+        let budget = Budget::default();
+        budget.set_limits(Limits { max_call_nesting: Some(3), ..Limits::default() });
+        budget.start();
+        let guard = budget.enter_call(2).unwrap();
+        assert_eq!(
+            budget.enter_call(2).err(),
+            Some(Error::new(ErrorKind::Limit, "maximum call nesting of 3 exceeded")),
+        );
+        drop(guard);
+        assert!(budget.enter_call(3).is_ok());
+    }
+
+    #[test]
+    fn time_out() {
+        let budget = Budget::default();
+        budget.set_limits(Limits { timeout: Some(Duration::ZERO), ..Limits::default() });
+        budget.start();
+        assert_eq!(
+            budget.enter_call(0).err(),
+            Some(Error::new(ErrorKind::Limit, "evaluation timed out after 0ns")),
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let budget = Budget::default();
+        let token = CancelToken::new();
+        budget.set_cancel_token(token.clone());
+        budget.start();
+        assert_eq!(budget.step(), Ok(()));
+        token.cancel();
+        assert_eq!(budget.enter_call(0).err(), Some(Error::new(ErrorKind::Cancelled, "evaluation was cancelled")));
+        assert_eq!(
+            (0..STEPS_BETWEEN_CHECKS).find_map(|_| budget.step().err()),
+            Some(Error::new(ErrorKind::Cancelled, "evaluation was cancelled")),
+        );
+    }
+
+    #[test]
+    fn unlimited_by_default_except_for_calls() {
+        assert_eq!(
+            Limits::default(),
+            Limits {
+                fuel: None,
+                max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+                max_call_nesting: Some(DEFAULT_MAX_CALL_NESTING),
+                timeout: None,
+            },
+        );
+    }
+}
@@ -0,0 +1,766 @@
+/*
+ * The `.moyec` format of compiled programs. A file starts with the magic
+ * bytes `MOYEC` and a format version, and ends with a CRC-32 of everything
+ * before it. In between, numbers are little-endian, lengths and indices are
+ * u32, and strings are UTF-8 prefixed with their length. Source positions
+ * aren't kept
+ */
+
+use crate::annotation::{self, Type};
+use crate::builtins;
+use crate::bytecode::{Code, Op};
+use crate::error::ErrorKind;
+use crate::expr::{Operations, UnaryOperations};
+use crate::program::{CompiledStmt, Program};
+use crate::resolved::Function;
+use crate::val::Val;
+use num_bigint::BigInt;
+use std::cell::OnceCell;
+use std::rc::Rc;
+
+const MAGIC: &[u8] = b"MOYEC";
+
+/// Bumped whenever the format or the meaning of the bytecode changes.
+const VERSION: u32 = 5;
+
+/// How deeply functions may be defined inside each other. Decoding and
+/// verifying recurse once per level, so a crafted program must not be able
+/// to nest them arbitrarily deep.
+const MAX_NESTED_FUNCTIONS: usize = 256;
+
+// The position of an operator or kind of error in these lists is its
+// encoding.
+const OPERATIONS: [Operations; 17] = [
+    Operations::Add,
+    Operations::Sub,
+    Operations::Mul,
+    Operations::Div,
+    Operations::Rem,
+    Operations::Pow,
+    Operations::BitAnd,
+    Operations::BitOr,
+    Operations::BitXor,
+    Operations::Shl,
+    Operations::Shr,
+    Operations::Eq,
+    Operations::Ne,
+    Operations::Lt,
+    Operations::Le,
+    Operations::Gt,
+    Operations::Ge,
+];
+const UNARY_OPERATIONS: [UnaryOperations; 3] = [UnaryOperations::Neg, UnaryOperations::Plus, UnaryOperations::Not];
+const ERROR_KINDS: [ErrorKind; 10] = [
+    ErrorKind::DivisionByZero,
+    ErrorKind::Overflow,
+    ErrorKind::InvalidOperand,
+    ErrorKind::Type,
+    ErrorKind::Undefined,
+    ErrorKind::Arity,
+    ErrorKind::Limit,
+    ErrorKind::Cancelled,
+    ErrorKind::Host,
+    ErrorKind::Incompatible,
+];
+
+pub(crate) fn encode(program: &Program) -> Vec<u8> {
+    let mut writer = Writer(MAGIC.to_vec());
+    writer.u32(VERSION);
+
+    writer.len(program.env.len());
+    for name in &program.env {
+        writer.option(name.as_deref(), Writer::string);
+    }
+    writer.len(program.num_globals);
+    writer.len(program.stmts.len());
+    for stmt in &program.stmts {
+        writer.code(&stmt.code);
+        writer.option(stmt.defines.as_ref(), |writer, (name, slot)| {
+            writer.string(name);
+            writer.len(*slot);
+        });
+    }
+
+    let checksum = crc32(&writer.0);
+    writer.u32(checksum);
+    writer.0
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<Program, String> {
+    let Some(body) = bytes.strip_prefix(MAGIC) else {
+        return Err("not a compiled moye program".to_string());
+    };
+    let mut reader = Reader(body);
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(format!("compiled program has version {}, but only version {} is supported", version, VERSION));
+    }
+    let (checked, checksum) = match bytes.split_last_chunk() {
+        Some((checked, checksum)) if checked.len() >= MAGIC.len() + 4 => (checked, checksum),
+        _ => return Err(corrupt("unexpected end")),
+    };
+    if crc32(checked) != u32::from_le_bytes(*checksum) {
+        return Err(corrupt("wrong checksum"));
+    }
+    reader.0 = &checked[MAGIC.len() + 4..];
+
+    let env = reader.list(|reader| reader.option(Reader::string))?;
+    let num_globals = reader.len()?;
+    let stmts = reader.list(|reader| {
+        Ok(CompiledStmt {
+            code: Rc::new(reader.code(0)?),
+            defines: reader.option(|reader| Ok((reader.string()?, reader.len()?)))?,
+        })
+    })?;
+    if !reader.0.is_empty() {
+        return Err(corrupt("data after the last statement"));
+    }
+
+    // Every slot of the global frame belongs to the environment or to a
+    // top-level definition.
+    if num_globals > env.len() + stmts.len() {
+        return Err(corrupt("too many slots"));
+    }
+    for stmt in &stmts {
+        if stmt.defines.as_ref().is_some_and(|(_, slot)| *slot >= num_globals) {
+            return Err(corrupt("slot out of range"));
+        }
+        verify(&stmt.code, &[num_globals], 0)?;
+    }
+    Ok(Program { env, num_globals, stmts })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.0.extend(n.to_le_bytes());
+    }
+
+    fn len(&mut self, n: usize) {
+        self.u32(u32::try_from(n).expect("compiled programs have fewer than 2^32 of everything"));
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.0.extend(bytes);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                write(self, value);
+            },
+            None => self.u8(0),
+        }
+    }
+
+    fn code(&mut self, code: &Code) {
+        self.len(code.ops.len());
+        for op in &code.ops {
+            self.op(op);
+        }
+        self.len(code.consts.len());
+        for val in &code.consts {
+            self.val(val);
+        }
+        self.len(code.strings.len());
+        for s in &code.strings {
+            self.string(s);
+        }
+        self.len(code.functions.len());
+        for function in &code.functions {
+            self.string(&function.name);
+            self.len(function.arity);
+            for (param, ty) in &function.param_types {
+                self.string(param);
+                self.option(*ty, Writer::ty);
+            }
+            self.option(function.return_type, Writer::ty);
+            self.len(function.num_slots);
+            self.code(&function.code());
+        }
+        self.len(code.builtins.len());
+        for (name, _) in &code.builtins {
+            self.string(name);
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        match *op {
+            Op::Const(constant) => self.op_with(0, &[constant]),
+            Op::Unit => self.op_with(1, &[]),
+            Op::Pop => self.op_with(2, &[]),
+            Op::Step => self.op_with(3, &[]),
+            Op::Load { depth, slot, name } => self.op_with(4, &[depth, slot, name]),
+            Op::Store { slot } => self.op_with(5, &[slot]),
+            Op::DefineFunc { slot, function } => self.op_with(6, &[slot, function]),
+            Op::Call { depth, slot, argc, name, nesting } => self.op_with(7, &[depth, slot, argc, name, nesting]),
+            Op::CallBuiltin { builtin, argc } => self.op_with(8, &[builtin, argc]),
+            Op::Binary(op) => {
+                let position = OPERATIONS.iter().position(|candidate| *candidate == op);
+                self.op_with(9, &[position.expect("every operator is listed") as u32]);
+            },
+            Op::Unary(op) => {
+                let position = UNARY_OPERATIONS.iter().position(|candidate| *candidate == op);
+                self.op_with(10, &[position.expect("every operator is listed") as u32]);
+            },
+            Op::Jump(target) => self.op_with(11, &[target]),
+            Op::JumpUnless(target) => self.op_with(12, &[target]),
+            Op::PushFrame { num_slots } => self.op_with(13, &[num_slots]),
+            Op::PopFrame => self.op_with(14, &[]),
+            Op::Fail { kind, message } => {
+                let position = ERROR_KINDS.iter().position(|candidate| *candidate == kind);
+                self.op_with(15, &[position.expect("every kind of error is listed") as u32, message]);
+            },
+            Op::Return => self.op_with(16, &[]),
+            Op::TailCall { depth, slot, argc, name } => self.op_with(17, &[depth, slot, argc, name]),
+            Op::Check { ty, what } => {
+                self.u8(18);
+                self.ty(ty);
+                self.u32(what);
+            },
+        }
+    }
+
+    // Types are encoded like operators, by their position in their list.
+    fn ty(&mut self, ty: Type) {
+        let position = annotation::TYPES.iter().position(|candidate| *candidate == ty);
+        self.u8(position.expect("every type is listed") as u8);
+    }
+
+    fn op_with(&mut self, tag: u8, operands: &[u32]) {
+        self.u8(tag);
+        for operand in operands {
+            self.u32(*operand);
+        }
+    }
+
+    fn val(&mut self, val: &Val) {
+        match val {
+            Val::Number(n) => {
+                self.u8(0);
+                self.0.extend(n.to_le_bytes());
+            },
+            Val::BigInt(n) => {
+                self.u8(1);
+                self.bytes(&n.to_signed_bytes_le());
+            },
+            Val::Float(x) => {
+                self.u8(2);
+                self.0.extend(x.to_bits().to_le_bytes());
+            },
+            Val::Bool(b) => {
+                self.u8(3);
+                self.u8(u8::from(*b));
+            },
+            Val::Unit => self.u8(7),
+            Val::Str(_) | Val::List(_) | Val::Map(_) => {
+                unreachable!("constants are made from literals, which are numbers and bools")
+            },
+        }
+    }
+}
+
+struct Reader<'b>(&'b [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let Some((taken, rest)) = self.0.split_first_chunk() else {
+            return Err(corrupt("unexpected end"));
+        };
+        self.0 = rest;
+        Ok(*taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&[u8], String> {
+        let len = self.len()?;
+        if self.0.len() < len {
+            return Err(corrupt("unexpected end"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| corrupt("invalid UTF-8"))
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<Option<T>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            _ => Err(corrupt("invalid option")),
+        }
+    }
+
+    // Doesn't preallocate, since a corrupt length could be huge.
+    fn list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let len = self.len()?;
+        let mut items = Vec::new();
+        for _ in 0..len {
+            items.push(read(self)?);
+        }
+        Ok(items)
+    }
+
+    // `nesting` is the number of functions the code is defined in.
+    fn code(&mut self, nesting: usize) -> Result<Code, String> {
+        if nesting > MAX_NESTED_FUNCTIONS {
+            return Err(corrupt("functions nested too deeply"));
+        }
+        let ops = self.list(Reader::op)?;
+        let consts = self.list(Reader::val)?;
+        let strings = self.list(Reader::string)?;
+        let functions = self.list(|reader| {
+            let name = reader.string()?;
+            let arity = reader.len()?;
+            let mut param_types = Vec::new();
+            for _ in 0..arity {
+                param_types.push((reader.string()?, reader.option(Reader::ty)?));
+            }
+            let return_type = reader.option(Reader::ty)?;
+            let num_slots = reader.len()?;
+            let code = reader.code(nesting + 1)?;
+            // Each slot besides the parameters is for a definition.
+            if num_slots < arity || num_slots > arity + code.ops.len() {
+                return Err(corrupt("function with fewer slots than parameters or too many"));
+            }
+            Ok(Rc::new(Function {
+                name,
+                arity,
+                param_types,
+                return_type,
+                num_slots,
+                body: None,
+                code: OnceCell::from(Rc::new(code)),
+            }))
+        })?;
+        let builtins = self.list(|reader| {
+            let name = reader.string()?;
+            match builtins::get(&name) {
+                Some(native) => Ok((name, native)),
+                None => Err(format!("compiled program calls unknown builtin ‘{}’", name)),
+            }
+        })?;
+
+        Ok(Code { ops, consts, strings, functions, builtins, positions: Vec::new() })
+    }
+
+    fn op(&mut self) -> Result<Op, String> {
+        Ok(match self.u8()? {
+            0 => Op::Const(self.u32()?),
+            1 => Op::Unit,
+            2 => Op::Pop,
+            3 => Op::Step,
+            4 => Op::Load { depth: self.u32()?, slot: self.u32()?, name: self.u32()? },
+            5 => Op::Store { slot: self.u32()? },
+            6 => Op::DefineFunc { slot: self.u32()?, function: self.u32()? },
+            7 => Op::Call {
+                depth: self.u32()?,
+                slot: self.u32()?,
+                argc: self.u32()?,
+                name: self.u32()?,
+                nesting: self.u32()?,
+            },
+            8 => Op::CallBuiltin { builtin: self.u32()?, argc: self.u32()? },
+            9 => Op::Binary(*OPERATIONS.get(self.len()?).ok_or_else(|| corrupt("unknown operator"))?),
+            10 => Op::Unary(*UNARY_OPERATIONS.get(self.len()?).ok_or_else(|| corrupt("unknown operator"))?),
+            11 => Op::Jump(self.u32()?),
+            12 => Op::JumpUnless(self.u32()?),
+            13 => Op::PushFrame { num_slots: self.u32()? },
+            14 => Op::PopFrame,
+            15 => Op::Fail {
+                kind: *ERROR_KINDS.get(self.len()?).ok_or_else(|| corrupt("unknown kind of error"))?,
+                message: self.u32()?,
+            },
+            16 => Op::Return,
+            17 => Op::TailCall { depth: self.u32()?, slot: self.u32()?, argc: self.u32()?, name: self.u32()? },
+            18 => Op::Check { ty: self.ty()?, what: self.u32()? },
+            tag => return Err(corrupt(&format!("unknown instruction {}", tag))),
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        annotation::TYPES.get(usize::from(self.u8()?)).copied().ok_or_else(|| corrupt("unknown type"))
+    }
+
+    // Strings, lists and maps (4 to 6) can't be constants, so they never
+    // need to be read, let alone nested.
+    fn val(&mut self) -> Result<Val, String> {
+        Ok(match self.u8()? {
+            0 => Val::Number(i64::from_le_bytes(self.take()?)),
+            1 => Val::BigInt(BigInt::from_signed_bytes_le(self.bytes()?)),
+            2 => Val::Float(f64::from_bits(u64::from_le_bytes(self.take()?))),
+            3 => Val::Bool(self.u8()? != 0),
+            7 => Val::Unit,
+            tag => return Err(corrupt(&format!("unknown value {}", tag))),
+        })
+    }
+}
+
+/*
+ * Checks that the VM can run `code` without panicking, in a frame with as
+ * many slots as the last of `frames` and ancestors with the others: indices
+ * refer to things that exist, no instruction pops more values than the code
+ * pushed, and frames are popped only after being pushed, and all of them
+ * before returning. Instructions are checked in order, with the stack depth
+ * and frames every path reaching them agrees on; jumps only go forward, so
+ * every loop is a call, which counts against the budget.
+ *
+ * Each function is checked at the one instruction that defines it, in the
+ * frames it will be called in; `nesting` is the number of functions `code`
+ * is defined in.
+ */
+fn verify(code: &Code, frames: &[usize], nesting: usize) -> Result<(), String> {
+    let in_range = |idx: u32, len: usize| (idx as usize) < len;
+    let check = |valid: bool, what: &str| if valid { Ok(()) } else { Err(corrupt(what)) };
+    check(nesting <= MAX_NESTED_FUNCTIONS, "functions nested too deeply")?;
+    let has_slot = |frames: &[usize], depth: u32, slot: u32| {
+        (depth as usize) < frames.len() && in_range(slot, frames[frames.len() - 1 - depth as usize])
+    };
+
+    // The stack depth and frames before each instruction a path reaches.
+    let mut states: Vec<Option<(usize, Vec<usize>)>> = vec![None; code.ops.len()];
+    let mut defined = vec![false; code.functions.len()];
+    let reach = |states: &mut Vec<Option<(usize, Vec<usize>)>>, target: usize, state: (usize, Vec<usize>)| {
+        match &states.get(target) {
+            None => Err(corrupt("jump past the last instruction")),
+            Some(None) => {
+                states[target] = Some(state);
+                Ok(())
+            },
+            Some(Some(reached)) => check(*reached == state, "paths with different stacks or frames"),
+        }
+    };
+    reach(&mut states, 0, (0, frames.to_vec()))?;
+
+    for (idx, op) in code.ops.iter().enumerate() {
+        // Code after a jump or failure that nothing jumps to never runs.
+        let Some((mut depth, mut frames_here)) = states[idx].take() else { continue };
+        let mut pop = |count: u32| {
+            depth = depth.checked_sub(count as usize).ok_or_else(|| corrupt("stack underflow"))?;
+            Ok::<_, String>(())
+        };
+        let mut pushes = 0;
+        match *op {
+            Op::Const(constant) => {
+                check(in_range(constant, code.consts.len()), "constant out of range")?;
+                pushes = 1;
+            },
+            Op::Unit => pushes = 1,
+            Op::Pop => pop(1)?,
+            Op::Step => {},
+            Op::Load { depth: load_depth, slot, name } => {
+                check(in_range(name, code.strings.len()), "string out of range")?;
+                check(has_slot(&frames_here, load_depth, slot), "slot out of range")?;
+                pushes = 1;
+            },
+            Op::Store { slot } => {
+                pop(1)?;
+                check(has_slot(&frames_here, 0, slot), "slot out of range")?;
+            },
+            Op::DefineFunc { slot, function } => {
+                check(has_slot(&frames_here, 0, slot), "slot out of range")?;
+                check(in_range(function, code.functions.len()), "function out of range")?;
+                check(!defined[function as usize], "function defined twice")?;
+                defined[function as usize] = true;
+                let function = &code.functions[function as usize];
+                verify(&function.code(), &[&frames_here[..], &[function.num_slots]].concat(), nesting + 1)?;
+            },
+            Op::Call { depth: call_depth, slot, argc, name, .. } | Op::TailCall { depth: call_depth, slot, argc, name } => {
+                check(in_range(name, code.strings.len()), "string out of range")?;
+                check(has_slot(&frames_here, call_depth, slot), "slot out of range")?;
+                pop(argc)?;
+                pushes = 1;
+            },
+            Op::CallBuiltin { builtin, argc } => {
+                check(in_range(builtin, code.builtins.len()), "builtin out of range")?;
+                let arity = code.builtins[builtin as usize].1.arity();
+                check(arity == argc as usize, "builtin called with the wrong number of parameters")?;
+                pop(argc)?;
+                pushes = 1;
+            },
+            Op::Binary(_) => {
+                pop(2)?;
+                pushes = 1;
+            },
+            Op::Unary(_) => {
+                pop(1)?;
+                pushes = 1;
+            },
+            Op::Jump(target) => {
+                check(target as usize > idx, "jump backwards")?;
+                reach(&mut states, target as usize, (depth, frames_here))?;
+                continue;
+            },
+            Op::JumpUnless(target) => {
+                pop(1)?;
+                check(target as usize > idx, "jump backwards")?;
+                reach(&mut states, target as usize, (depth, frames_here.clone()))?;
+            },
+            // Each slot is for a definition in the block.
+            Op::PushFrame { num_slots } => {
+                check(num_slots as usize <= code.ops.len(), "too many slots")?;
+                frames_here.push(num_slots as usize);
+            },
+            Op::PopFrame => {
+                check(frames_here.len() > frames.len(), "frame popped without being pushed")?;
+                frames_here.pop();
+            },
+            Op::Fail { message, .. } => {
+                check(in_range(message, code.strings.len()), "string out of range")?;
+                continue;
+            },
+            Op::Check { what, .. } => {
+                check(in_range(what, code.strings.len()), "string out of range")?;
+                check(depth > 0, "stack underflow")?;
+            },
+            Op::Return => {
+                pop(1)?;
+                check(frames_here.len() == frames.len(), "frame pushed without being popped")?;
+                continue;
+            },
+        }
+        reach(&mut states, idx + 1, (depth + pushes, frames_here))?;
+    }
+    Ok(())
+}
+
+fn corrupt(what: &str) -> String {
+    format!("compiled program is corrupt: {}", what)
+}
+
+// CRC-32 as in zlib and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use crate::error::ErrorKind;
+
+    const SOURCE: &str = "fun fib (n: Int) -> Int => if n < 2 then n else { fib n - 1 } + { fib n - 2 }\n\
+                          let big: Int = 2 ** 70\n{ fib 10 } + int 2.5";
+
+    fn encoded() -> Vec<u8> {
+        crate::compile(SOURCE, &Env::default()).unwrap().to_bytes()
+    }
+
+    #[test]
+    fn compute_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn run_decoded_program() {
+        let program = Program::from_bytes(&encoded()).unwrap();
+        let mut env = Env::default();
+        assert_eq!(program.run(&mut env), Ok(Val::Number(57)));
+        assert_eq!(env.get_binding("big"), Ok(Val::BigInt(BigInt::from(2).pow(70))));
+        assert_eq!(
+            crate::parse("fib 1.5").unwrap().eval(&mut env).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Type, "parameter ‘n’ of ‘fib’ must be Int, found float".to_string())),
+        );
+    }
+
+    #[test]
+    fn encode_decoded_program_the_same() {
+        let bytes = encoded();
+        assert_eq!(Program::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn round_trip_values() {
+        let vals = vec![
+            Val::Number(-3),
+            Val::BigInt(-BigInt::from(2).pow(80)),
+            Val::Float(0.5),
+            Val::Bool(true),
+            Val::Unit,
+        ];
+        for val in vals {
+            let mut writer = Writer(Vec::new());
+            writer.val(&val);
+            assert_eq!(Reader(&writer.0).val(), Ok(val));
+        }
+    }
+
+    #[test]
+    fn keep_kinds_of_rejected_code() {
+        let run = |source| {
+            let bytes = crate::compile(source, &Env::default()).unwrap().to_bytes();
+            let error = Program::from_bytes(&bytes).unwrap().run(&mut Env::default()).unwrap_err();
+            (error.kind(), error.message().to_string())
+        };
+        assert_eq!(run("y"), (ErrorKind::Undefined, "binding with name ‘y’ does not exist".to_string()));
+        assert_eq!(run("fun f x => x\nf 1 2"), (ErrorKind::Arity, "expected 1 parameters, got 2".to_string()));
+    }
+
+    #[test]
+    fn reject_values_that_are_never_constants() {
+        assert_eq!(Reader(&[4, 0, 0, 0, 0]).val(), Err(corrupt("unknown value 4")));
+        assert_eq!(Reader(&[5, 0, 0, 0, 0]).val(), Err(corrupt("unknown value 5")));
+        assert_eq!(Reader(&[6, 0, 0, 0, 0]).val(), Err(corrupt("unknown value 6")));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert_eq!(Program::from_bytes(b"fib 10").unwrap_err(), "not a compiled moye program");
+    }
+
+    #[test]
+    fn reject_other_versions() {
+        let mut bytes = encoded();
+        bytes[MAGIC.len()] = 1;
+        assert_eq!(
+            Program::from_bytes(&bytes).unwrap_err(),
+            "compiled program has version 1, but only version 5 is supported",
+        );
+    }
+
+    #[test]
+    fn reject_corrupt_programs() {
+        let bytes = encoded();
+        for idx in MAGIC.len() + 4..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[idx] ^= 0x10;
+            assert_eq!(
+                Program::from_bytes(&corrupted).unwrap_err(),
+                "compiled program is corrupt: wrong checksum",
+            );
+        }
+        assert_eq!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            "compiled program is corrupt: wrong checksum",
+        );
+    }
+
+    // Programs with a valid checksum that the compiler would never write.
+    fn crafted(ops: Vec<Op>) -> Vec<u8> {
+        let code = Rc::new(Code { ops, ..Code::default() });
+        Program { env: Vec::new(), num_globals: 1, stmts: vec![CompiledStmt { code, defines: None }] }.to_bytes()
+    }
+
+    #[test]
+    fn reject_popping_an_empty_stack() {
+        assert_eq!(
+            Program::from_bytes(&crafted(vec![Op::Pop, Op::Return])).unwrap_err(),
+            "compiled program is corrupt: stack underflow",
+        );
+    }
+
+    #[test]
+    fn reject_popping_a_frame_never_pushed() {
+        assert_eq!(
+            Program::from_bytes(&crafted(vec![Op::PopFrame, Op::Return])).unwrap_err(),
+            "compiled program is corrupt: frame popped without being pushed",
+        );
+    }
+
+    #[test]
+    fn reject_slots_out_of_range() {
+        assert_eq!(
+            Program::from_bytes(&crafted(vec![Op::Unit, Op::Store { slot: 7 }, Op::Return])).unwrap_err(),
+            "compiled program is corrupt: slot out of range",
+        );
+        assert!(Program::from_bytes(&crafted(vec![Op::Unit, Op::Store { slot: 0 }, Op::Unit, Op::Return])).is_ok());
+    }
+
+    // Patches the bytes of an instruction, keeping the checksum valid.
+    fn tampered(source: &str, op: Op, replacement: Op) -> Vec<u8> {
+        let [from, to] = [op, replacement].map(|op| {
+            let mut writer = Writer(Vec::new());
+            writer.op(&op);
+            writer.0
+        });
+        let mut bytes = crate::compile(source, &Env::default()).unwrap().to_bytes();
+        bytes.truncate(bytes.len() - 4);
+        let idx = bytes.windows(from.len()).position(|window| window == from).expect("the program has the instruction");
+        bytes.splice(idx..idx + from.len(), to);
+        let checksum = crc32(&bytes);
+        bytes.extend(checksum.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn reject_builtin_calls_with_wrong_number_of_parameters() {
+        let bytes = tampered("int 1", Op::CallBuiltin { builtin: 0, argc: 1 }, Op::CallBuiltin { builtin: 0, argc: 0 });
+        assert_eq!(
+            Program::from_bytes(&bytes).unwrap_err(),
+            "compiled program is corrupt: builtin called with the wrong number of parameters",
+        );
+    }
+
+    // Each function defines the next one, and the innermost returns unit.
+    fn nested_functions(count: usize) -> Vec<u8> {
+        let mut code = Rc::new(Code { ops: vec![Op::Unit, Op::Return], ..Code::default() });
+        for _ in 0..count {
+            let function = Function {
+                name: "f".to_string(),
+                arity: 0,
+                param_types: Vec::new(),
+                return_type: None,
+                num_slots: 1,
+                body: None,
+                code: OnceCell::from(code),
+            };
+            code = Rc::new(Code {
+                ops: vec![Op::DefineFunc { slot: 0, function: 0 }, Op::Unit, Op::Return],
+                functions: vec![Rc::new(function)],
+                ..Code::default()
+            });
+        }
+        Program { env: Vec::new(), num_globals: 1, stmts: vec![CompiledStmt { code, defines: None }] }.to_bytes()
+    }
+
+    #[test]
+    fn reject_deeply_nested_functions() {
+        assert!(Program::from_bytes(&nested_functions(MAX_NESTED_FUNCTIONS)).is_ok());
+        assert_eq!(
+            Program::from_bytes(&nested_functions(MAX_NESTED_FUNCTIONS + 1)).unwrap_err(),
+            "compiled program is corrupt: functions nested too deeply",
+        );
+    }
+
+    #[test]
+    fn reject_unbalanced_paths() {
+        // Only one branch leaves a value for the return.
+        let ops = vec![Op::Unit, Op::JumpUnless(3), Op::Unit, Op::Return];
+        assert_eq!(
+            Program::from_bytes(&crafted(ops)).unwrap_err(),
+            "compiled program is corrupt: paths with different stacks or frames",
+        );
+    }
+}
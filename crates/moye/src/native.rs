@@ -0,0 +1,151 @@
+/*
+ * Native functions are implemented in Rust by the host and can be called from
+ * moye code like any other function. Hosts report failures as messages;
+ * builtins also say what kind of error they are
+ */
+
+use crate::convert::{FromVal, IntoVal};
+use crate::error::{Error, ErrorKind};
+use crate::val::Val;
+use std::fmt;
+use std::rc::Rc;
+
+type NativeFn = dyn Fn(Vec<Val>) -> Result<Val, Error>;
+
+#[derive(Clone)]
+pub struct NativeFunc {
+    arity: usize,
+    func: Rc<NativeFn>,
+}
+
+impl NativeFunc {
+    /// Creates a function taking `arity` arguments; the closure is only ever
+    /// called with exactly that many values. It needn't be `Send`, since an
+    /// `Env` never leaves the thread that created it.
+    pub fn new(arity: usize, func: impl Fn(Vec<Val>) -> Result<Val, String> + 'static) -> Self {
+        Self::builtin(arity, move |args| func(args).map_err(|message| Error::new(ErrorKind::Host, message)))
+    }
+
+    /// Like `new`, for functions whose errors have a kind of their own.
+    pub(crate) fn builtin(arity: usize, func: impl Fn(Vec<Val>) -> Result<Val, Error> + 'static) -> Self {
+        Self {
+            arity,
+            func: Rc::new(func),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub(crate) fn call(&self, args: Vec<Val>) -> Result<Val, Error> {
+        (self.func)(args)
+    }
+}
+
+impl fmt::Debug for NativeFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunc").field("arity", &self.arity).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for NativeFunc {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+/*
+ * Implemented for closures whose parameters implement `FromVal` and whose
+ * return type implements `IntoVal`; `Args` only exists to tell the impls for
+ * different arities apart
+ */
+pub trait IntoNativeFunc<Args> {
+    fn into_native_func(self) -> NativeFunc;
+}
+
+impl IntoNativeFunc<NativeFunc> for NativeFunc {
+    fn into_native_func(self) -> NativeFunc {
+        self
+    }
+}
+
+fn convert_arg<T: FromVal>(position: usize, val: Val) -> Result<T, String> {
+    T::from_val(val).map_err(|msg| format!("invalid parameter {}: {}", position, msg))
+}
+
+macro_rules! impl_into_native_func {
+    ($len:literal => $($arg:ident),*) => {
+        impl<Func, Ret, $($arg),*> IntoNativeFunc<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Ret + 'static,
+            Ret: IntoVal,
+            $($arg: FromVal,)*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn into_native_func(self) -> NativeFunc {
+                NativeFunc::new($len, move |args| {
+                    let mut args = args.into_iter();
+                    let mut position = 0;
+                    $(
+                        position += 1;
+                        let $arg = convert_arg::<$arg>(position, args.next().unwrap())?;
+                    )*
+                    self($($arg),*).into_val()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native_func!(0 =>);
+impl_into_native_func!(1 => A);
+impl_into_native_func!(2 => A, B);
+impl_into_native_func!(3 => A, B, C);
+impl_into_native_func!(4 => A, B, C, D);
+impl_into_native_func!(5 => A, B, C, D, E);
+impl_into_native_func!(6 => A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_closure_with_converted_args() {
+        let func = (|times: i32, s: String| s.repeat(times as usize)).into_native_func();
+        assert_eq!(func.arity(), 2);
+        assert_eq!(
+            func.call(vec![Val::Number(3), Val::Str("ab".to_string())]),
+            Ok(Val::Str("ababab".to_string())),
+        );
+    }
+
+    #[test]
+    fn call_closure_with_no_args() {
+        let func = (|| true).into_native_func();
+        assert_eq!(func.arity(), 0);
+        assert_eq!(func.call(Vec::new()), Ok(Val::Bool(true)));
+    }
+
+    #[test]
+    fn reject_arg_of_wrong_type() {
+        let func = (|a: i32, b: i32| a + b).into_native_func();
+        assert_eq!(
+            func.call(vec![Val::Number(1), Val::Bool(false)]),
+            Err(Error::new(ErrorKind::Host, "invalid parameter 2: expected int, found bool")),
+        );
+    }
+
+    #[test]
+    fn propagate_error_returned_by_closure() {
+        let func = (|n: i32| if n < 0 { Err("negative".to_string()) } else { Ok(n) }).into_native_func();
+        assert_eq!(func.call(vec![Val::Number(-1)]), Err(Error::new(ErrorKind::Host, "negative")));
+    }
+
+    #[test]
+    fn clones_are_equal() {
+        let func = (|| 1).into_native_func();
+        assert_eq!(func.clone(), func);
+        assert_ne!((|| 1).into_native_func(), func);
+    }
+}
@@ -0,0 +1,484 @@
+/*
+ * Rewrites the syntax tree of a program into one that computes the same
+ * values with less work. Code that fails at runtime keeps failing the same
+ * way, but optimized code takes fewer evaluation steps, so it runs out of
+ * fuel later. The passes run in turn until none of them changes anything,
+ * since each can open up opportunities for the others
+ */
+
+use crate::arith;
+use crate::ast::{self, BigNumber, Block, Bool, Expression, Float, NestingChecker, Number, Span, Statement, Visitor, VisitorMut};
+use crate::val::Val;
+use std::collections::HashMap;
+
+/// Which optimizations `Parse::optimize` applies; all of them by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Passes {
+    /// Operations on literals become their result, so `2 * 3` becomes `6`,
+    /// and `if` with a literal condition becomes the selected branch.
+    /// Operations that fail, like `1 / 0`, are kept.
+    pub fold_constants: bool,
+    /// Bindings defined in blocks with a literal value are replaced by the
+    /// literal wherever they are used, and the definition is dropped.
+    /// Top-level bindings are kept, since the host can change them.
+    pub inline_constants: bool,
+    /// Removes `{}` where its value isn't used.
+    pub remove_empty_blocks: bool,
+    /// Merges blocks that define nothing into the statements around them,
+    /// and replaces `{ x }` by `x`.
+    pub flatten_blocks: bool,
+}
+
+impl Passes {
+    pub fn none() -> Self {
+        Self {
+            fold_constants: false,
+            inline_constants: false,
+            remove_empty_blocks: false,
+            flatten_blocks: false,
+        }
+    }
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self {
+            fold_constants: true,
+            inline_constants: true,
+            remove_empty_blocks: true,
+            flatten_blocks: true,
+        }
+    }
+}
+
+pub(crate) fn optimize(stmts: &mut Vec<Statement>, passes: Passes) {
+    // The passes recurse into every node.
+    let mut nesting = NestingChecker::new(0);
+    for stmt in stmts.iter() {
+        nesting.visit_statement(stmt);
+    }
+    if nesting.too_deep().is_some() {
+        return;
+    }
+
+    loop {
+        let before = stmts.clone();
+        if passes.inline_constants {
+            Inliner::default().statements(stmts, true);
+        }
+        if passes.fold_constants {
+            for stmt in stmts.iter_mut() {
+                ConstantFolder.visit_statement_mut(stmt);
+            }
+        }
+        if passes.remove_empty_blocks {
+            EmptyBlockRemover.statements(stmts);
+        }
+        if passes.flatten_blocks {
+            BlockFlattener.statements(stmts);
+        }
+        if *stmts == before {
+            return;
+        }
+    }
+}
+
+fn literal_value(expr: &Expression) -> Option<Val> {
+    match expr {
+        Expression::Number(number) => Some(Val::Number(number.value)),
+        Expression::BigNumber(number) => Some(Val::BigInt(number.value.clone())),
+        Expression::Float(float) => Some(Val::Float(float.value)),
+        Expression::Bool(boolean) => Some(Val::Bool(boolean.value)),
+        _ => None,
+    }
+}
+
+// Values without a literal, like lists, stay operations.
+fn literal(val: Val, span: Span) -> Option<Expression> {
+    match val {
+        Val::Number(value) => Some(Expression::Number(Number { value, span })),
+        Val::BigInt(value) => Some(Expression::BigNumber(BigNumber { value, span })),
+        Val::Float(value) => Some(Expression::Float(Float { value, span })),
+        Val::Bool(value) => Some(Expression::Bool(Bool { value, span })),
+        _ => None,
+    }
+}
+
+fn with_span(mut expr: Expression, new_span: Span) -> Expression {
+    match &mut expr {
+        Expression::Number(Number { span, .. })
+        | Expression::BigNumber(BigNumber { span, .. })
+        | Expression::Float(Float { span, .. })
+        | Expression::Bool(Bool { span, .. }) => *span = new_span,
+        _ => {},
+    }
+    expr
+}
+
+fn defines_nothing(block: &Block) -> bool {
+    block.stmts.iter().all(|stmt| matches!(stmt, Statement::Expression(_)))
+}
+
+struct ConstantFolder;
+
+impl VisitorMut for ConstantFolder {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        ast::walk_expression_mut(self, expression);
+
+        let folded = match expression {
+            Expression::Operation { lhs, rhs, op, span } => match (literal_value(lhs), literal_value(rhs)) {
+                (Some(lhs), Some(rhs)) => arith::binary(op, lhs, rhs).ok().and_then(|val| literal(val, *span)),
+                _ => None,
+            },
+            Expression::Unary { op, operand, span } => {
+                literal_value(operand).and_then(|val| arith::unary(op, val).ok()).and_then(|val| literal(val, *span))
+            },
+            Expression::IfElse(if_else) => match *if_else.condition {
+                Expression::Bool(Bool { value: true, .. }) => Some((*if_else.then_branch).clone()),
+                Expression::Bool(Bool { value: false, .. }) => Some((*if_else.else_branch).clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            *expression = folded;
+        }
+    }
+}
+
+/*
+ * Follows the scopes of the resolver: a definition hides the ones before it
+ * from the statements after it. Names that aren't constants are recorded as
+ * `None`, so that they hide constants of the same name further out
+ */
+#[derive(Default)]
+struct Inliner {
+    scopes: Vec<HashMap<String, Option<Expression>>>,
+}
+
+impl Inliner {
+    fn lookup(&self, name: &str) -> Option<&Expression> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).and_then(Option::as_ref)
+    }
+
+    fn define(&mut self, name: &str, constant: Option<Expression>) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), constant);
+    }
+
+    fn statements(&mut self, stmts: &mut Vec<Statement>, top_level: bool) {
+        self.scopes.push(HashMap::new());
+        let mut inlined = Vec::new();
+        for (idx, stmt) in stmts.iter_mut().enumerate() {
+            match stmt {
+                Statement::BindingDef(binding_def) => {
+                    self.expression(&mut binding_def.value);
+                    // Constants that don't fit the annotation have to fail
+                    // where they're defined.
+                    let constant = literal_value(&binding_def.value)
+                        .filter(|val| binding_def.ty.as_ref().is_none_or(|annotation| annotation.ty.admits(val)))
+                        .map(|_| binding_def.value.clone());
+                    if constant.is_some() && !top_level {
+                        inlined.push(idx);
+                        self.define(&binding_def.name, constant);
+                    } else {
+                        self.define(&binding_def.name, None);
+                    }
+                },
+                // Functions can call themselves, so their name is defined
+                // before their body.
+                Statement::FuncDef(func_def) => {
+                    self.define(&func_def.name, None);
+                    self.scopes.push(func_def.params.iter().map(|param| (param.clone(), None)).collect());
+                    self.statement(&mut func_def.body);
+                    self.scopes.pop();
+                },
+                Statement::Expression(expr) => self.expression(expr),
+            }
+        }
+        self.scopes.pop();
+
+        // A definition that is the last statement is the value of its block,
+        // and calling a binding is an error that has to stay one.
+        for idx in inlined.into_iter().rev() {
+            let Statement::BindingDef(binding_def) = &stmts[idx] else { unreachable!() };
+            if idx + 1 < stmts.len() && !mentions(&stmts[idx + 1..], &binding_def.name) {
+                stmts.remove(idx);
+            }
+        }
+    }
+
+    // Function bodies are the only statements outside of a list.
+    fn statement(&mut self, stmt: &mut Statement) {
+        let mut stmts = vec![stmt.clone()];
+        self.statements(&mut stmts, false);
+        if let [optimized] = &mut stmts[..] {
+            *stmt = optimized.clone();
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::BindingUsage(binding_usage) => {
+                if let Some(constant) = self.lookup(&binding_usage.name) {
+                    *expr = with_span(constant.clone(), binding_usage.span);
+                }
+            },
+            Expression::Block(block) => self.statements(&mut block.stmts, false),
+            Expression::Operation { lhs, rhs, .. } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            },
+            Expression::Unary { operand, .. } => self.expression(operand),
+            Expression::FuncCall(func_call) => {
+                for param in &mut func_call.params {
+                    self.expression(param);
+                }
+            },
+            Expression::IfElse(if_else) => {
+                self.expression(&mut if_else.condition);
+                self.expression(&mut if_else.then_branch);
+                self.expression(&mut if_else.else_branch);
+            },
+            Expression::Number(_) | Expression::BigNumber(_) | Expression::Float(_) | Expression::Bool(_) => {},
+        }
+    }
+}
+
+// Whether `name` is used anywhere in `stmts`, in any scope.
+fn mentions(stmts: &[Statement], name: &str) -> bool {
+    struct Mentions<'n> {
+        name: &'n str,
+        found: bool,
+    }
+
+    impl Visitor for Mentions<'_> {
+        fn visit_binding_usage(&mut self, binding_usage: &ast::BindingUsage) {
+            self.found |= binding_usage.name == self.name;
+        }
+
+        fn visit_func_call(&mut self, func_call: &ast::FuncCall) {
+            self.found |= func_call.callee == self.name;
+            ast::walk_func_call(self, func_call);
+        }
+    }
+
+    let mut visitor = Mentions { name, found: false };
+    for stmt in stmts {
+        visitor.visit_statement(stmt);
+    }
+    visitor.found
+}
+
+struct EmptyBlockRemover;
+
+impl EmptyBlockRemover {
+    // The last statement is the value of the list.
+    fn statements(&mut self, stmts: &mut Vec<Statement>) {
+        for stmt in stmts.iter_mut() {
+            self.visit_statement_mut(stmt);
+        }
+        let last = stmts.len().saturating_sub(1);
+        let mut idx = 0;
+        stmts.retain(|stmt| {
+            idx += 1;
+            idx - 1 == last || !matches!(stmt, Statement::Expression(Expression::Block(block)) if block.stmts.is_empty())
+        });
+    }
+}
+
+impl VisitorMut for EmptyBlockRemover {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        self.statements(&mut block.stmts);
+    }
+}
+
+struct BlockFlattener;
+
+impl BlockFlattener {
+    fn statements(&mut self, stmts: &mut Vec<Statement>) {
+        for stmt in stmts.iter_mut() {
+            self.visit_statement_mut(stmt);
+        }
+        *stmts = std::mem::take(stmts)
+            .into_iter()
+            .flat_map(|stmt| match stmt {
+                Statement::Expression(Expression::Block(block)) if defines_nothing(&block) && !block.stmts.is_empty() => {
+                    block.stmts
+                },
+                stmt => vec![stmt],
+            })
+            .collect();
+    }
+}
+
+impl VisitorMut for BlockFlattener {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        self.statements(&mut block.stmts);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        ast::walk_expression_mut(self, expression);
+        if let Expression::Block(block) = expression
+            && let [Statement::Expression(inner)] = &mut block.stmts[..]
+        {
+            *expression = inner.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::EraseSpans;
+    use crate::env;
+    use crate::error::ErrorKind;
+    use proptest::prelude::*;
+
+    fn optimized(source: &str, passes: Passes) -> Vec<Statement> {
+        let mut parse = crate::parse(source).unwrap();
+        parse.optimize(passes);
+        parse.into_statements().into_iter().map(EraseSpans::erase_spans).collect()
+    }
+
+    fn statements(source: &str) -> Vec<Statement> {
+        crate::parse(source).unwrap().into_statements().into_iter().map(EraseSpans::erase_spans).collect()
+    }
+
+    fn only(configure: impl FnOnce(&mut Passes)) -> Passes {
+        let mut passes = Passes::none();
+        configure(&mut passes);
+        passes
+    }
+
+    #[test]
+    fn fold_constant_operations() {
+        let passes = only(|passes| passes.fold_constants = true);
+        assert_eq!(optimized("2 * 3 + x", passes), statements("6 + x\n"));
+        assert_eq!(optimized("- 2 ** 3 < 1.5 * 2", passes), statements("true\n"));
+        assert_eq!(optimized("if 1 < 2 then a else b", passes), statements("a\n"));
+    }
+
+    #[test]
+    fn keep_operations_that_fail_or_have_no_literal() {
+        let passes = only(|passes| passes.fold_constants = true);
+        assert_eq!(optimized("1 / 0 + 2 * 2", passes), statements("1 / 0 + 4\n"));
+        assert_eq!(optimized("1.0 / 0", passes), statements("1.0 / 0\n"));
+        assert_eq!(optimized("if 1 then 2 else 3", passes), statements("if 1 then 2 else 3\n"));
+    }
+
+    #[test]
+    fn inline_constants_defined_in_blocks() {
+        let passes = only(|passes| passes.inline_constants = true);
+        assert_eq!(
+            optimized("let top = 1\n{ let a = 2\nlet b = a\nfun f x => a + x + top\nf b }", passes),
+            statements("let top = 1\n{\n    fun f x => 2 + x + top\n    f 2\n}\n"),
+        );
+    }
+
+    #[test]
+    fn inline_only_where_binding_is_visible() {
+        let passes = only(|passes| passes.inline_constants = true);
+        assert_eq!(
+            optimized("{ let a = 1\nfun f a => a\nlet b = { let a = x\na }\na + b }", passes),
+            statements("{ let a = 1\nfun f a => a\nlet b = { let a = x\na }\n1 + b }"),
+        );
+    }
+
+    #[test]
+    fn keep_inlined_definitions_still_needed() {
+        let passes = only(|passes| passes.inline_constants = true);
+        assert_eq!(optimized("{ let a = 1\na 2 }", passes), statements("{\n    let a = 1\n    a 2\n}\n"));
+        assert_eq!(optimized("{ let a = 1 }", passes), statements("{\n    let a = 1\n}\n"));
+    }
+
+    #[test]
+    fn keep_constants_that_contradict_annotations() {
+        let passes = only(|passes| passes.inline_constants = true);
+        assert_eq!(
+            optimized("{ let a: Int = 1.5\nlet b: Int = 2\na + b }", passes),
+            statements("{ let a: Int = 1.5\na + 2 }"),
+        );
+    }
+
+    #[test]
+    fn remove_empty_blocks_whose_value_is_unused() {
+        let passes = only(|passes| passes.remove_empty_blocks = true);
+        assert_eq!(optimized("{}\n{ {}\n1 }\n{}", passes), statements("{\n    1\n}\n{}\n"));
+    }
+
+    #[test]
+    fn flatten_blocks_defining_nothing() {
+        let passes = only(|passes| passes.flatten_blocks = true);
+        assert_eq!(
+            optimized("{ a\n{ b\nc } }\n{ let d = 1\nd }", passes),
+            statements("a\nb\nc\n{\n    let d = 1\n    d\n}\n"),
+        );
+        assert_eq!(optimized("let x = { 1 }", passes), statements("let x = 1\n"));
+    }
+
+    #[test]
+    fn optimize_until_nothing_changes() {
+        assert_eq!(
+            optimized("fun f x => { let a = 2 * 3\n{}\n{ a + x } }", Passes::default()),
+            statements("fun f x => 6 + x\n"),
+        );
+    }
+
+    #[test]
+    fn keep_results_and_errors() {
+        let sources = [
+            "fun fib n => { let two = 2\nif n < two then n else { fib n - 1 } + { fib n - 2 } }\nfib 15",
+            "{ let a = 1 / 0\n{}\na }",
+            "{ let a = 1\nlet b = { true }\nif b then a 2 else 3 }",
+            "let a = { let b = 2 ** 3\nb * b }\na - 1",
+        ];
+        for source in sources {
+            let mut parse = crate::parse(source).unwrap();
+            let expected = outcome(&parse);
+            parse.optimize(Passes::default());
+            assert_eq!(outcome(&parse), expected, "{}", source);
+        }
+    }
+
+    // Optimizing can merge or drop statements, so errors may blame another
+    // one; only their kind and message must stay.
+    fn outcome(parse: &crate::Parse) -> Result<Val, (ErrorKind, String)> {
+        env::on_engines(|env| parse.eval(env)).map_err(|error| (error.kind(), error.message().to_string()))
+    }
+
+    // Sources of expressions where constants abound; `c` is never defined.
+    fn expression() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            (0..20i64).prop_map(|n| n.to_string()),
+            Just("1.5".to_string()),
+            Just("true".to_string()),
+            Just("a".to_string()),
+            Just("c".to_string()),
+            Just("{}".to_string()),
+        ];
+        let ops: &[&str] = &["+", "-", "*", "/", "%", "&", "<<", "==", "<", ">="];
+        leaf.prop_recursive(4, 32, 3, move |inner| {
+            prop_oneof![
+                (inner.clone(), proptest::sample::select(ops), inner.clone())
+                    .prop_map(|(lhs, op, rhs)| format!("{{ {} }} {} {{ {} }}", lhs, op, rhs)),
+                inner.clone().prop_map(|operand| format!("-{{ {} }}", operand)),
+                (inner.clone(), inner.clone(), inner.clone()).prop_map(|(condition, then_branch, else_branch)| {
+                    format!("if {{ {} }} then {{ {} }} else {{ {} }}", condition, then_branch, else_branch)
+                }),
+                (inner.clone(), inner.clone()).prop_map(|(value, body)| format!("{{ let a = {}\n{} }}", value, body)),
+                (inner.clone(), inner.clone()).prop_map(|(first, second)| format!("{{ {}\n{} }}", first, second)),
+                (inner.clone(), inner).prop_map(|(body, arg)| format!("{{ fun f a => {}\nf {{ {} }} }}", body, arg)),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn optimizing_preserves_the_result(source in expression()) {
+            let mut parse = crate::parse(&source).unwrap();
+            let expected = outcome(&parse);
+            parse.optimize(Passes::default());
+            prop_assert_eq!(outcome(&parse), expected);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Programs compiled ahead of time. They run on the VM without being parsed
+ * or resolved again, and can be saved in the `.moyec` format to ship them to
+ * wherever parsing would be too slow
+ */
+
+use crate::bytecode::Code;
+use crate::cancel::CancelToken;
+use crate::compiler;
+use crate::diagnostic::ParseError;
+use crate::env::Env;
+use crate::error::{Error, ErrorKind};
+use crate::moyec;
+use crate::resolved::Stmt;
+use crate::resolver;
+use crate::val::Val;
+use std::rc::Rc;
+
+/// A compiled program. Names are resolved against the environment it was
+/// compiled for, so it can only run in an environment with the same
+/// definitions, made in the same order.
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    /// The names of the definitions of the environment, by slot.
+    pub(crate) env: Vec<Option<String>>,
+    pub(crate) num_globals: usize,
+    pub(crate) stmts: Vec<CompiledStmt>,
+}
+
+/// A top-level statement, and the name and slot it defines if any.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CompiledStmt {
+    pub(crate) code: Rc<Code>,
+    pub(crate) defines: Option<(String, usize)>,
+}
+
+/// Parses and compiles `source` for running in `env`, or in an environment
+/// set up the same way.
+pub fn compile(source: &str, env: &Env) -> Result<Program, ParseError> {
+    let parse = crate::parse(source)?;
+    let resolution = resolver::resolve(parse.statements(), env);
+    let stmts = resolution
+        .stmts
+        .iter()
+        .map(|stmt| CompiledStmt {
+            code: Rc::new(compiler::compile(stmt)),
+            defines: match stmt {
+                Stmt::BindingDef { name, slot, .. } | Stmt::FuncDef { name, slot, .. } => Some((name.clone(), *slot)),
+                Stmt::Expr(..) => None,
+            },
+        })
+        .collect();
+
+    Ok(Program {
+        env: env.slot_names(),
+        num_globals: resolution.num_globals,
+        stmts,
+    })
+}
+
+impl Program {
+    /// Runs the program on the VM, whatever the engine of `env`, returning
+    /// the value of the last statement. Like `Parse::eval`, top-level
+    /// definitions made before an error stay in `env`.
+    pub fn run(&self, env: &mut Env) -> Result<Val, Error> {
+        self.run_cancellable(env, &CancelToken::default())
+    }
+
+    /// Like `run`, but stops with an error once `cancel_token` is cancelled,
+    /// as `Parse::eval_cancellable` does.
+    pub fn run_cancellable(&self, env: &mut Env, cancel_token: &CancelToken) -> Result<Val, Error> {
+        if env.slot_names() != self.env {
+            return Err(Error::new(
+                ErrorKind::Incompatible,
+                "the program was compiled for an environment with different definitions",
+            ));
+        }
+        env.start_evaluation(cancel_token);
+        env.run_compiled(self)
+    }
+
+    /// The program in the `.moyec` format: a version header, the bytecode
+    /// and a checksum.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        moyec::encode(self)
+    }
+
+    /// Reads a program written by `to_bytes`, failing if it is corrupt or
+    /// was written by an incompatible version of moye. The checksum catches
+    /// accidental corruption; the bytecode is also verified, so a tampered
+    /// program fails here instead of crashing the VM.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        moyec::decode(bytes)
+    }
+
+    /// Lists the instructions of every statement and function, under the
+    /// lines of `source` they were compiled from. Programs read with
+    /// `from_bytes` have no line numbers.
+    pub fn disassemble(&self, source: Option<&str>) -> String {
+        let mut out = String::new();
+        for (idx, stmt) in self.stmts.iter().enumerate() {
+            if idx > 0 {
+                out.push('\n');
+            }
+            out.push_str(&format!("statement {}:\n", idx + 1));
+            stmt.code.disassemble(source, &mut out);
+            disassemble_functions(&stmt.code, source, &mut out);
+        }
+        out
+    }
+}
+
+// Functions come after the code that defines them.
+fn disassemble_functions(code: &Code, source: Option<&str>, out: &mut String) {
+    for function in &code.functions {
+        let function_code = function.code();
+        out.push_str(&format!("\nfun {}:\n", function.name));
+        function_code.disassemble(source, out);
+        disassemble_functions(&function_code, source, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn run_compiled_program() {
+        let program = compile("fun double x => x * 2\nlet a = double 21", &Env::default()).unwrap();
+        let mut env = Env::default();
+        assert_eq!(program.run(&mut env), Ok(Val::Unit));
+        assert_eq!(env.get_binding("a"), Ok(Val::Number(42)));
+        assert_eq!(crate::parse("double a").unwrap().eval(&mut env), Ok(Val::Number(84)));
+    }
+
+    #[test]
+    fn run_only_in_environment_compiled_for() {
+        let mut env = Env::default();
+        env.register_fn("double", |n: i64| n * 2);
+        let program = compile("double 2", &env).unwrap();
+        assert_eq!(program.run(&mut env), Ok(Val::Number(4)));
+        assert_eq!(
+            program.run(&mut Env::default()).map_err(|error| (error.kind(), error.message().to_string())),
+            Err((ErrorKind::Incompatible, "the program was compiled for an environment with different definitions".to_string())),
+        );
+    }
+
+    #[test]
+    fn disassemble_with_source_lines() {
+        let source = "fun fib n =>\n    if n < 2 then n else { fib n - 1 } + { fib n - 2 }\nfib 10";
+        let program = compile(source, &Env::default()).unwrap();
+        assert_eq!(
+            program.disassemble(Some(source)),
+            "\
+statement 1:
+   1 | fun fib n =>
+0000 define       fib 0:0
+0001 unit
+0002 return
+
+fun fib:
+   2 |     if n < 2 then n else { fib n - 1 } + { fib n - 2 }
+0000 load         n 0:0
+0001 const        2
+0002 binary       <
+0003 jump-unless  0006
+0004 load         n 0:0
+0005 jump         0017
+0006 step
+0007 load         n 0:0
+0008 const        1
+0009 binary       -
+0010 call         fib 1:0 argc=1 nesting=5
+0011 step
+0012 load         n 0:0
+0013 const        2
+0014 binary       -
+0015 call         fib 1:0 argc=1 nesting=5
+0016 binary       +
+0017 return
+
+statement 2:
+   3 | fib 10
+0000 const        10
+0001 call         fib 0:0 argc=1 nesting=2
+0002 return
+",
+        );
+    }
+}
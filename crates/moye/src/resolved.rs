@@ -0,0 +1,400 @@
+/*
+ * Programs as they run, after the resolver has replaced every name by the
+ * frame and slot it lives in, so that evaluation never looks names up.
+ *
+ * A frame holds the slots of a block or function call and points to the
+ * frame its code was defined in; the depth of a name is how many frames up
+ * from the current one its definition lives. Functions can only be called,
+ * never passed around, so the frame a function was defined in is always an
+ * ancestor of the frames that call it: a call finds the function and the
+ * parent of the callee's frame in the same place
+ */
+
+use crate::annotation::Type;
+use crate::arith;
+use crate::bytecode::Code;
+use crate::compiler;
+use crate::error::{Error, ErrorKind};
+use crate::expr::{Operations, UnaryOperations};
+use crate::lexer::Span;
+use crate::limits::Budget;
+use crate::native::NativeFunc;
+use crate::val::Val;
+use crate::vm;
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Frame {
+    slots: RefCell<Vec<Slot>>,
+    parent: Option<Rc<Frame>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum Slot {
+    /// Not defined yet, or removed.
+    #[default]
+    Empty,
+    Val(Val),
+    Func(Func),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Func {
+    User(Rc<Function>),
+    Native(NativeFunc),
+}
+
+/// A function defined in moye code. Its parameters are the first slots of
+/// its frame.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) arity: usize,
+    /// The name and annotated type of every parameter, checked when the
+    /// function is called.
+    pub(crate) param_types: Vec<(String, Option<Type>)>,
+    /// Checked when the function returns. Calls in tail position of a
+    /// function with a return type aren't tail calls, since the value they
+    /// return has to be checked.
+    pub(crate) return_type: Option<Type>,
+    pub(crate) num_slots: usize,
+    /// Functions loaded from compiled programs have no body to walk, and
+    /// always run on the VM.
+    pub(crate) body: Option<Stmt>,
+    /// The body compiled to bytecode, the first time the VM calls it.
+    pub(crate) code: OnceCell<Rc<Code>>,
+}
+
+/// Statements keep their spans for the line numbers in disassembled code.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Stmt {
+    BindingDef { name: String, slot: usize, ty: Option<Type>, value: Expr, span: Span },
+    FuncDef { name: String, slot: usize, func: Rc<Function>, span: Span },
+    Expr(Expr, Span),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Expr {
+    Const(Val),
+    Binding { name: String, depth: usize, slot: usize },
+    /// `nesting` is how many levels of the stack of the host the call takes
+    /// on the tree walker: one for every expression it is nested in within
+    /// the function that makes it, itself included, and one for entering
+    /// the callee.
+    Call { callee: Callee, args: Vec<Expr>, nesting: usize },
+    Operation { lhs: Box<Expr>, rhs: Box<Expr>, op: Operations },
+    Unary { op: UnaryOperations, operand: Box<Expr> },
+    IfElse { condition: Box<Expr>, then_branch: Box<Expr>, else_branch: Box<Expr> },
+    /// Blocks without slots define nothing and run in the frame around them.
+    Block { num_slots: usize, stmts: Vec<Stmt> },
+    /// Code the resolver rejected, which fails with this error if it runs.
+    Fail(Error),
+}
+
+/// A value, or the call that will produce it, made by the caller in place
+/// of its own. Until the body of the callee starts, errors are blamed on the
+/// statement that made the call.
+enum Tail {
+    Val(Val),
+    Call { function: Rc<Function>, parent: Rc<Frame>, args: Vec<Val>, nesting: usize, span: Option<Span> },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Callee {
+    Slot { name: String, depth: usize, slot: usize },
+    Builtin { name: String, native: NativeFunc },
+}
+
+impl Frame {
+    pub(crate) fn new(num_slots: usize, parent: Option<Rc<Frame>>) -> Rc<Self> {
+        Rc::new(Self {
+            slots: RefCell::new(vec![Slot::Empty; num_slots]),
+            parent,
+        })
+    }
+
+    pub(crate) fn with_slots(slots: Vec<Slot>, parent: Rc<Frame>) -> Rc<Self> {
+        Rc::new(Self {
+            slots: RefCell::new(slots),
+            parent: Some(parent),
+        })
+    }
+
+    pub(crate) fn parent(&self) -> Option<&Rc<Frame>> {
+        self.parent.as_ref()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.slots.borrow().len()
+    }
+
+    /// Adds empty slots until there are `num_slots`.
+    pub(crate) fn grow(&self, num_slots: usize) {
+        let mut slots = self.slots.borrow_mut();
+        if slots.len() < num_slots {
+            slots.resize(num_slots, Slot::Empty);
+        }
+    }
+
+    pub(crate) fn push(&self, value: Slot) -> usize {
+        let mut slots = self.slots.borrow_mut();
+        slots.push(value);
+        slots.len() - 1
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, slot: usize) -> Slot {
+        self.slots.borrow()[slot].clone()
+    }
+
+    #[inline]
+    pub(crate) fn set(&self, slot: usize, value: Slot) -> Slot {
+        std::mem::replace(&mut self.slots.borrow_mut()[slot], value)
+    }
+
+    #[inline]
+    pub(crate) fn ancestor(self: &Rc<Self>, depth: usize) -> &Rc<Self> {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame.parent.as_ref().expect("the resolver only refers to enclosing frames");
+        }
+        frame
+    }
+}
+
+impl Func {
+    pub(crate) fn arity(&self) -> usize {
+        match self {
+            Self::User(function) => function.arity,
+            Self::Native(native) => native.arity(),
+        }
+    }
+
+    /// `parent` is the frame the function was defined in. Calls made this
+    /// way, by the host or through a binding, only take a level for entering
+    /// the callee.
+    pub(crate) fn call(&self, parent: &Rc<Frame>, args: Vec<Val>, budget: &Budget) -> Result<Val, Error> {
+        if self.arity() != args.len() {
+            return Err(arity_mismatch(self.arity(), args.len()));
+        }
+
+        match self {
+            Self::User(function) => function.call(parent, args, 1, budget),
+            Self::Native(native) => native.call(args),
+        }
+    }
+}
+
+impl Function {
+    // `args` are the first slots of the new frame. Calls in tail position
+    // replace this one instead of nesting in it, so loops written as
+    // recursion run in constant space, and as deeply nested as this one.
+    fn call(&self, parent: &Rc<Frame>, args: Vec<Val>, nesting: usize, budget: &Budget) -> Result<Val, Error> {
+        let _guard = budget.enter_call(nesting)?;
+        let mut tail = self.enter(parent, args, budget)?;
+        loop {
+            match tail {
+                Tail::Val(val) => return Ok(val),
+                Tail::Call { function, parent, args, span, .. } => {
+                    tail = budget
+                        .tail_call()
+                        .and_then(|()| function.enter(&parent, args, budget))
+                        .map_err(|error| error.within(span))?;
+                },
+            }
+        }
+    }
+
+    // Runs the body in a frame of its own, up to its call in tail position.
+    fn enter(&self, parent: &Rc<Frame>, args: Vec<Val>, budget: &Budget) -> Result<Tail, Error> {
+        let frame = self.frame(args, parent)?;
+        match (&self.body, self.return_type) {
+            (Some(body), None) => body.eval_tail(&frame, budget),
+            (Some(body), Some(return_type)) => {
+                let val = body.eval(&frame, budget)?;
+                return_type
+                    .enforce(&val, || format!("the value of ‘{}’", self.name))
+                    .map_err(|error| error.within(Some(body.span())))?;
+                Ok(Tail::Val(val))
+            },
+            // Compiled code checks the value itself.
+            (None, _) => vm::run(self.code(), &frame, budget).map(Tail::Val),
+        }
+    }
+
+    /// The frame of a call with `args`, which have to fit the annotations of
+    /// the parameters. Their slots take over the memory of `args`, so room
+    /// for the other slots saves growing it.
+    pub(crate) fn frame(&self, args: Vec<Val>, parent: &Rc<Frame>) -> Result<Rc<Frame>, Error> {
+        for (arg, (param, ty)) in args.iter().zip(&self.param_types) {
+            if let Some(ty) = ty {
+                ty.enforce(arg, || format!("parameter ‘{}’ of ‘{}’", param, self.name))?;
+            }
+        }
+        let mut slots: Vec<Slot> = args.into_iter().map(Slot::Val).collect();
+        slots.resize(self.num_slots, Slot::Empty);
+        Ok(Frame::with_slots(slots, Rc::clone(parent)))
+    }
+
+    pub(crate) fn code(&self) -> Rc<Code> {
+        Rc::clone(self.code.get_or_init(|| Rc::new(compiler::compile_function(self))))
+    }
+}
+
+impl Stmt {
+    pub(crate) fn eval(&self, frame: &Rc<Frame>, budget: &Budget) -> Result<Val, Error> {
+        self.eval_tail(frame, budget)?.finish(budget)
+    }
+
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            Self::BindingDef { span, .. } | Self::FuncDef { span, .. } | Self::Expr(_, span) => *span,
+        }
+    }
+
+    // Blames errors, and the call in tail position, on this statement unless
+    // a statement inside it is to blame.
+    fn eval_tail(&self, frame: &Rc<Frame>, budget: &Budget) -> Result<Tail, Error> {
+        let tail = match self {
+            Self::BindingDef { name, slot, ty, value, .. } => value.eval(frame, budget).and_then(|val| {
+                if let Some(ty) = ty {
+                    ty.enforce(&val, || format!("binding ‘{}’", name))?;
+                }
+                frame.set(*slot, Slot::Val(val));
+                Ok(Tail::Val(Val::Unit))
+            }),
+            Self::FuncDef { slot, func, .. } => {
+                frame.set(*slot, Slot::Func(Func::User(Rc::clone(func))));
+                Ok(Tail::Val(Val::Unit))
+            },
+            Self::Expr(expr, _) => expr.eval_tail(frame, budget),
+        };
+        match tail {
+            Ok(Tail::Call { function, parent, args, nesting, span: None }) => {
+                Ok(Tail::Call { function, parent, args, nesting, span: Some(self.span()) })
+            },
+            Ok(tail) => Ok(tail),
+            Err(error) => Err(error.within(Some(self.span()))),
+        }
+    }
+}
+
+impl Expr {
+    pub(crate) fn eval(&self, frame: &Rc<Frame>, budget: &Budget) -> Result<Val, Error> {
+        match self {
+            Self::Call { callee: Callee::Slot { .. }, .. } | Self::IfElse { .. } | Self::Block { .. } => {
+                self.eval_tail(frame, budget)?.finish(budget)
+            },
+            _ => {
+                budget.step()?;
+                self.eval_simple(frame, budget)
+            },
+        }
+    }
+
+    // Leaves the call of a moye function in tail position to the caller:
+    // the last statement of a block, and the branches of an `if`.
+    fn eval_tail(&self, frame: &Rc<Frame>, budget: &Budget) -> Result<Tail, Error> {
+        budget.step()?;
+
+        match self {
+            Self::Call { callee: Callee::Slot { name, depth, slot }, args, nesting } => {
+                let defined_in = frame.ancestor(*depth);
+                let Slot::Func(func) = defined_in.get(*slot) else {
+                    return Err(undefined("function", name));
+                };
+                match func {
+                    Func::User(function) if function.arity == args.len() => Ok(Tail::Call {
+                        function,
+                        parent: Rc::clone(defined_in),
+                        args: eval_all(args, frame, budget)?,
+                        nesting: *nesting,
+                        span: None,
+                    }),
+                    _ => func.call(defined_in, eval_all(args, frame, budget)?, budget).map(Tail::Val),
+                }
+            },
+            Self::IfElse { condition, then_branch, else_branch } => match condition.eval(frame, budget)? {
+                Val::Bool(true) => then_branch.eval_tail(frame, budget),
+                Val::Bool(false) => else_branch.eval_tail(frame, budget),
+                other => Err(not_a_condition(&other)),
+            },
+            Self::Block { num_slots, stmts } => {
+                let Some((last, stmts)) = stmts.split_last() else {
+                    return Ok(Tail::Val(Val::Unit));
+                };
+                let own_frame;
+                let frame = if *num_slots == 0 {
+                    frame
+                } else {
+                    own_frame = Frame::new(*num_slots, Some(Rc::clone(frame)));
+                    &own_frame
+                };
+                for stmt in stmts {
+                    stmt.eval(frame, budget)?;
+                }
+                last.eval_tail(frame, budget)
+            },
+            _ => self.eval_simple(frame, budget).map(Tail::Val),
+        }
+    }
+
+    // Evaluates the expressions that can't end in a call in tail position,
+    // without counting their step.
+    fn eval_simple(&self, frame: &Rc<Frame>, budget: &Budget) -> Result<Val, Error> {
+        match self {
+            Self::Const(val) => Ok(val.clone()),
+            // The host may have replaced a binding by a function since the
+            // code was resolved, or removed it.
+            Self::Binding { name, depth, slot } => {
+                let defined_in = frame.ancestor(*depth);
+                match defined_in.get(*slot) {
+                    Slot::Val(val) => Ok(val),
+                    Slot::Func(func) => func.call(defined_in, Vec::new(), budget),
+                    Slot::Empty => Err(undefined("binding", name)),
+                }
+            },
+            Self::Call { callee: Callee::Builtin { native, .. }, args, .. } => native.call(eval_all(args, frame, budget)?),
+            Self::Operation { lhs, rhs, op } => {
+                let lhs = lhs.eval(frame, budget)?;
+                let rhs = rhs.eval(frame, budget)?;
+                arith::binary(op, lhs, rhs)
+            },
+            Self::Unary { op, operand } => arith::unary(op, operand.eval(frame, budget)?),
+            Self::Fail(error) => Err(error.clone()),
+            Self::Call { callee: Callee::Slot { .. }, .. } | Self::IfElse { .. } | Self::Block { .. } => {
+                unreachable!("expressions that can end in a call are evaluated by eval_tail")
+            },
+        }
+    }
+}
+
+impl Tail {
+    // Makes the call in tail position, if there is one.
+    fn finish(self, budget: &Budget) -> Result<Val, Error> {
+        match self {
+            Self::Val(val) => Ok(val),
+            Self::Call { function, parent, args, nesting, span } => {
+                function.call(&parent, args, nesting, budget).map_err(|error| error.within(span))
+            },
+        }
+    }
+}
+
+fn eval_all(exprs: &[Expr], frame: &Rc<Frame>, budget: &Budget) -> Result<Vec<Val>, Error> {
+    exprs.iter().map(|expr| expr.eval(frame, budget)).collect()
+}
+
+// `what` is a binding or a function.
+pub(crate) fn undefined(what: &str, name: &str) -> Error {
+    Error::new(ErrorKind::Undefined, format!("{} with name ‘{}’ does not exist", what, name))
+}
+
+pub(crate) fn arity_mismatch(arity: usize, argc: usize) -> Error {
+    Error::new(ErrorKind::Arity, format!("expected {} parameters, got {}", arity, argc))
+}
+
+pub(crate) fn not_a_condition(val: &Val) -> Error {
+    Error::new(ErrorKind::Type, format!("condition of ‘if’ must be a bool, found {}", val.type_name()))
+}
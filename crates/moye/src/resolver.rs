@@ -0,0 +1,612 @@
+/*
+ * Finds mistakes in the names a program uses before it runs, and replaces
+ * every name by the frame and slot it lives in at runtime. Scopes are
+ * lexical: statements see the names defined before them in their block and
+ * the blocks around it, and a function body sees its parameters, the
+ * function itself and whatever its definition sees. Function bodies also see
+ * the names defined later at the top level, so that functions can call each
+ * other and use bindings defined after them. Names from the `Env` the
+ * program will run in surround the whole program.
+ *
+ * Every scope is a frame at runtime, and every definition gets a slot of its
+ * own, so redefining a name doesn't change what earlier code refers to. Only
+ * a top-level definition may take over the slot of an earlier one with the
+ * same name, when no function body refers to it, since top-level code runs
+ * in order and nothing else could see the old value again
+ */
+
+use crate::ast::{self, BindingUsage, Block, FuncCall, Span};
+use crate::builtins;
+use crate::diagnostic::{Diagnostic, ParseError, Severity};
+use crate::env::Env;
+use crate::error::{Error, ErrorKind};
+use crate::limits::{self, MAX_NESTING};
+use crate::native::NativeFunc;
+use crate::resolved::{self, Callee, Expr, Function, Slot, Stmt};
+use crate::val::Val;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::rc::Rc;
+
+/// Parses `source` and reports undefined bindings and functions, calls with
+/// the wrong number of parameters and definitions that shadow others, in
+/// source order, without running any code. Names defined in `env` count as
+/// defined.
+pub fn check(source: &str, env: &Env) -> Result<Vec<Diagnostic>, ParseError> {
+    let parse = crate::parse(source)?;
+    Ok(resolve(parse.statements(), env)
+        .problems
+        .into_iter()
+        .map(|problem| Diagnostic::at(source, problem.span, problem.severity, problem.message))
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Problem {
+    pub(crate) span: Span,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+/// Code with problems still resolves, to code that fails with the usual
+/// runtime error only if it is reached.
+#[derive(Debug)]
+pub(crate) struct Resolution {
+    pub(crate) stmts: Vec<Stmt>,
+    /// How many slots the frame of `env` needs, including the ones for the
+    /// top-level definitions of the program.
+    pub(crate) num_globals: usize,
+    pub(crate) problems: Vec<Problem>,
+    /// The slots of the frame of `env` that function bodies refer to.
+    pub(crate) captured: HashSet<usize>,
+}
+
+pub(crate) fn resolve(stmts: &[ast::Statement], env: &Env) -> Resolution {
+    let mut resolver = Resolver::new(env);
+    let in_bodies = names_in_bodies(stmts);
+    let mut slots = HashMap::new();
+    for stmt in stmts {
+        let (name, def) = match stmt {
+            ast::Statement::FuncDef(func_def) => (&func_def.name, Def::Func { arity: func_def.params.len() }),
+            ast::Statement::BindingDef(binding_def) => (&binding_def.name, Def::Binding),
+            ast::Statement::Expression(_) => continue,
+        };
+        let earlier = slots.get(name).copied().or_else(|| env.reusable_slot(name));
+        let slot = match earlier {
+            Some(slot) if !in_bodies.contains(name.as_str()) => slot,
+            _ => {
+                let globals = &mut resolver.scopes[0];
+                globals.num_slots += 1;
+                globals.num_slots - 1
+            },
+        };
+        slots.insert(name, slot);
+        resolver.ahead.push((name.clone(), def, slot));
+    }
+    let stmts = stmts.iter().map(|stmt| resolver.statement(stmt)).collect();
+    resolver.problems.sort_by_key(|problem| problem.span.start);
+    Resolution {
+        stmts,
+        num_globals: resolver.scopes[0].num_slots,
+        problems: resolver.problems,
+        captured: resolver.captured,
+    }
+}
+
+// Every name used in a function body anywhere in `stmts`, in any scope.
+// Code nested too deeply fails to resolve and isn't looked into.
+fn names_in_bodies(stmts: &[ast::Statement]) -> HashSet<&str> {
+    #[derive(Default)]
+    struct Names<'a> {
+        num_bodies: usize,
+        depth: usize,
+        names: HashSet<&'a str>,
+    }
+
+    impl<'a> Names<'a> {
+        fn statement(&mut self, stmt: &'a ast::Statement) {
+            match stmt {
+                ast::Statement::BindingDef(binding_def) => self.expression(&binding_def.value),
+                ast::Statement::FuncDef(func_def) => {
+                    self.num_bodies += 1;
+                    self.statement(&func_def.body);
+                    self.num_bodies -= 1;
+                },
+                ast::Statement::Expression(expr) => self.expression(expr),
+            }
+        }
+
+        fn expression(&mut self, expr: &'a ast::Expression) {
+            if self.depth == MAX_NESTING {
+                return;
+            }
+            self.depth += 1;
+            match expr {
+                ast::Expression::BindingUsage(binding_usage) => self.name(&binding_usage.name),
+                ast::Expression::FuncCall(func_call) => {
+                    self.name(&func_call.callee);
+                    func_call.params.iter().for_each(|param| self.expression(param));
+                },
+                ast::Expression::Operation { lhs, rhs, .. } => {
+                    self.expression(lhs);
+                    self.expression(rhs);
+                },
+                ast::Expression::Unary { operand, .. } => self.expression(operand),
+                ast::Expression::Block(block) => block.stmts.iter().for_each(|stmt| self.statement(stmt)),
+                ast::Expression::IfElse(if_else) => {
+                    self.expression(&if_else.condition);
+                    self.expression(&if_else.then_branch);
+                    self.expression(&if_else.else_branch);
+                },
+                ast::Expression::Number(_)
+                | ast::Expression::BigNumber(_)
+                | ast::Expression::Float(_)
+                | ast::Expression::Bool(_) => {},
+            }
+            self.depth -= 1;
+        }
+
+        fn name(&mut self, name: &'a str) {
+            if self.num_bodies > 0 {
+                self.names.insert(name);
+            }
+        }
+    }
+
+    let mut visitor = Names::default();
+    stmts.iter().for_each(|stmt| visitor.statement(stmt));
+    visitor.names
+}
+
+#[cfg(test)]
+pub(crate) fn resolve_expression(expr: &ast::Expression, env: &Env) -> Expr {
+    Resolver::new(env).expression(expr)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Def {
+    Binding,
+    Func { arity: usize },
+}
+
+// Where the definition of a name lives at runtime.
+enum Place {
+    Slot { depth: usize, slot: usize },
+    Builtin(NativeFunc),
+}
+
+#[derive(Default)]
+struct Scope {
+    names: HashMap<String, (Def, usize)>,
+    num_slots: usize,
+}
+
+struct Resolver<'env> {
+    env: &'env Env,
+    /// Innermost last; the first is the frame of `env`.
+    scopes: Vec<Scope>,
+    /// Every top-level definition of the program, in order, with its slot.
+    ahead: Vec<(String, Def, usize)>,
+    /// How many of `ahead` have been defined so far.
+    num_defined: usize,
+    /// How many function bodies the resolver is in.
+    num_bodies: usize,
+    /// How many expressions and function definitions the resolver is in.
+    depth: usize,
+    /// The depth at which the innermost function body starts.
+    body_depth: usize,
+    problems: Vec<Problem>,
+    captured: HashSet<usize>,
+}
+
+impl<'env> Resolver<'env> {
+    fn new(env: &'env Env) -> Self {
+        Self {
+            env,
+            scopes: vec![Scope { names: HashMap::new(), num_slots: env.num_globals() }],
+            ahead: Vec::new(),
+            num_defined: 0,
+            num_bodies: 0,
+            depth: 0,
+            body_depth: 0,
+            problems: Vec::new(),
+            captured: HashSet::new(),
+        }
+    }
+
+    // Like `Env`, the innermost definition of a name hides all others, even
+    // if it is a binding and a function is needed or the other way round.
+    fn lookup(&self, name: &str) -> Option<(Def, Place)> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&(def, slot)) = scope.names.get(name) {
+                return Some((def, Place::Slot { depth, slot }));
+            }
+        }
+
+        let depth = self.scopes.len() - 1;
+        let global = self.env.global(name).and_then(|(slot, value)| match value {
+            Slot::Val(_) => Some((Def::Binding, Place::Slot { depth, slot })),
+            Slot::Func(func) => Some((Def::Func { arity: func.arity() }, Place::Slot { depth, slot })),
+            Slot::Empty => None,
+        });
+        global.or_else(|| {
+            builtins::get(name).map(|native| (Def::Func { arity: native.arity() }, Place::Builtin(native)))
+        })
+    }
+
+    // A name defined later at the top level, which usually exists by the
+    // time a function body using it runs. Its slot is empty until then.
+    fn lookup_later(&self, name: &str) -> Option<(Def, Place)> {
+        if self.num_bodies == 0 {
+            return None;
+        }
+        let later = self.ahead.get(self.num_defined..)?;
+        let (_, def, slot) = later.iter().find(|(later, ..)| later == name)?;
+        Some((*def, Place::Slot { depth: self.scopes.len() - 1, slot: *slot }))
+    }
+
+    // Looks `name` up for a use of it, noting which slots of the frame of
+    // `env` function bodies refer to.
+    fn lookup_use(&mut self, name: &str) -> Option<(Def, Place)> {
+        let found = self.lookup(name).or_else(|| self.lookup_later(name));
+        if let Some((_, Place::Slot { depth, slot })) = &found
+            && self.num_bodies > 0
+            && *depth == self.scopes.len() - 1
+        {
+            self.captured.insert(*slot);
+        }
+        found
+    }
+
+    fn define(&mut self, name: &str, def: Def, span: Span) -> usize {
+        if self.lookup(name).is_some() {
+            self.warn(span, format!("‘{}’ shadows an earlier definition", name));
+        }
+        let slot = if self.scopes.len() == 1 {
+            self.num_defined += 1;
+            self.ahead[self.num_defined - 1].2
+        } else {
+            let scope = self.scopes.last_mut().unwrap();
+            scope.num_slots += 1;
+            scope.num_slots - 1
+        };
+        let scope = self.scopes.last_mut().unwrap();
+        scope.names.insert(name.to_string(), (def, slot));
+        slot
+    }
+
+    fn statement(&mut self, stmt: &ast::Statement) -> Stmt {
+        match stmt {
+            // A binding is only visible after its definition, so `let x = x`
+            // refers to an earlier `x`.
+            ast::Statement::BindingDef(binding_def) => {
+                let value = self.expression(&binding_def.value);
+                let slot = self.define(&binding_def.name, Def::Binding, binding_def.span);
+                Stmt::BindingDef {
+                    name: binding_def.name.clone(),
+                    slot,
+                    ty: binding_def.ty.as_ref().map(|annotation| annotation.ty),
+                    value,
+                    span: binding_def.span,
+                }
+            },
+            ast::Statement::FuncDef(func_def) => {
+                let arity = func_def.params.len();
+                let slot = self.define(&func_def.name, Def::Func { arity }, func_def.span);
+
+                self.scopes.push(Scope::default());
+                for param in &func_def.params {
+                    self.define(param, Def::Binding, func_def.span);
+                }
+                let body = match self.too_deep(func_def.span) {
+                    Some(fail) => Stmt::Expr(fail, func_def.span),
+                    None => {
+                        self.num_bodies += 1;
+                        self.depth += 1;
+                        let body_depth = mem::replace(&mut self.body_depth, self.depth);
+                        let body = self.statement(&func_def.body);
+                        self.body_depth = body_depth;
+                        self.depth -= 1;
+                        self.num_bodies -= 1;
+                        body
+                    },
+                };
+                let scope = self.scopes.pop().unwrap();
+
+                let function = Function {
+                    name: func_def.name.clone(),
+                    arity,
+                    param_types: func_def
+                        .params
+                        .iter()
+                        .zip(&func_def.param_types)
+                        .map(|(param, annotation)| (param.clone(), annotation.as_ref().map(|annotation| annotation.ty)))
+                        .collect(),
+                    return_type: func_def.return_type.as_ref().map(|annotation| annotation.ty),
+                    num_slots: scope.num_slots,
+                    body: Some(body),
+                    code: OnceCell::new(),
+                };
+                Stmt::FuncDef { name: func_def.name.clone(), slot, func: Rc::new(function), span: func_def.span }
+            },
+            ast::Statement::Expression(expr) => Stmt::Expr(self.expression(expr), expr.span()),
+        }
+    }
+
+    fn expression(&mut self, expr: &ast::Expression) -> Expr {
+        if let Some(fail) = self.too_deep(expr.span()) {
+            return fail;
+        }
+
+        self.depth += 1;
+        let resolved = match expr {
+            ast::Expression::Number(number) => Expr::Const(Val::Number(number.value)),
+            ast::Expression::BigNumber(number) => Expr::Const(Val::BigInt(number.value.clone())),
+            ast::Expression::Float(float) => Expr::Const(Val::Float(float.value)),
+            ast::Expression::Bool(boolean) => Expr::Const(Val::Bool(boolean.value)),
+            ast::Expression::Operation { lhs, rhs, op, .. } => Expr::Operation {
+                lhs: Box::new(self.expression(lhs)),
+                rhs: Box::new(self.expression(rhs)),
+                op: *op,
+            },
+            ast::Expression::Unary { op, operand, .. } => Expr::Unary {
+                op: *op,
+                operand: Box::new(self.expression(operand)),
+            },
+            ast::Expression::FuncCall(func_call) => self.func_call(func_call),
+            ast::Expression::BindingUsage(binding_usage) => self.binding_usage(binding_usage),
+            ast::Expression::Block(block) => self.block(block),
+            ast::Expression::IfElse(if_else) => Expr::IfElse {
+                condition: Box::new(self.expression(&if_else.condition)),
+                then_branch: Box::new(self.expression(&if_else.then_branch)),
+                else_branch: Box::new(self.expression(&if_else.else_branch)),
+            },
+        };
+        self.depth -= 1;
+        resolved
+    }
+
+    // Code nested deeper than the parser allows can only have been built by
+    // hand, and isn't resolved any further.
+    fn too_deep(&mut self, span: Span) -> Option<Expr> {
+        (self.depth == MAX_NESTING).then(|| self.fail(span, Error::new(ErrorKind::Limit, limits::nested_too_deeply())))
+    }
+
+    // Blocks that define nothing get no scope, and so no frame.
+    fn block(&mut self, block: &Block) -> Expr {
+        if block.stmts.iter().all(|stmt| matches!(stmt, ast::Statement::Expression(_))) {
+            let stmts = block.stmts.iter().map(|stmt| self.statement(stmt)).collect();
+            return Expr::Block { num_slots: 0, stmts };
+        }
+
+        self.scopes.push(Scope::default());
+        let stmts = block.stmts.iter().map(|stmt| self.statement(stmt)).collect();
+        let scope = self.scopes.pop().unwrap();
+        Expr::Block { num_slots: scope.num_slots, stmts }
+    }
+
+    fn func_call(&mut self, func_call: &FuncCall) -> Expr {
+        let args = func_call.params.iter().map(|param| self.expression(param)).collect();
+        let found = self.lookup_use(&func_call.callee);
+        match found {
+            Some((Def::Func { arity }, place)) => self.call(&func_call.callee, place, arity, args, func_call.span),
+            _ => self.fail(func_call.span, resolved::undefined("function", &func_call.callee)),
+        }
+    }
+
+    // Names of functions call them without parameters.
+    fn binding_usage(&mut self, binding_usage: &BindingUsage) -> Expr {
+        let name = &binding_usage.name;
+        let found = self.lookup_use(name);
+        match found {
+            Some((Def::Binding, Place::Slot { depth, slot })) => Expr::Binding { name: name.clone(), depth, slot },
+            Some((Def::Func { arity }, place)) => self.call(name, place, arity, Vec::new(), binding_usage.span),
+            _ => self.fail(binding_usage.span, resolved::undefined("binding", name)),
+        }
+    }
+
+    fn call(&mut self, name: &str, place: Place, arity: usize, args: Vec<Expr>, span: Span) -> Expr {
+        if arity != args.len() {
+            self.error(span, format!("function ‘{}’ expects {} parameters, got {}", name, arity, args.len()));
+            return Expr::Fail(resolved::arity_mismatch(arity, args.len()));
+        }
+
+        let callee = match place {
+            Place::Slot { depth, slot } => Callee::Slot { name: name.to_string(), depth, slot },
+            Place::Builtin(native) => Callee::Builtin { name: name.to_string(), native },
+        };
+        Expr::Call { callee, args, nesting: self.depth - self.body_depth + 1 }
+    }
+
+    fn fail(&mut self, span: Span, error: Error) -> Expr {
+        self.error(span, error.message().to_string());
+        Expr::Fail(error)
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.problems.push(Problem { span, severity: Severity::Error, message });
+    }
+
+    fn warn(&mut self, span: Span, message: String) {
+        self.problems.push(Problem { span, severity: Severity::Warning, message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One line per problem.
+    fn check_str(source: &str) -> Vec<String> {
+        check(source, &Env::default())
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn accept_defined_names() {
+        assert_eq!(
+            check_str("let a = 1\nfun add x y => x + y\nfun one => 1\nadd { one } a\nint 2.5"),
+            Vec::<String>::new(),
+        );
+    }
+
+    #[test]
+    fn report_undefined_bindings_and_functions() {
+        assert_eq!(
+            check_str("let a = b\nfun f x => { y + x }\nf { g 1 }"),
+            vec![
+                "1:9: binding with name ‘b’ does not exist",
+                "2:14: binding with name ‘y’ does not exist",
+                "3:5: function with name ‘g’ does not exist",
+            ],
+        );
+    }
+
+    #[test]
+    fn report_use_before_definition() {
+        assert_eq!(
+            check_str("let x = x\nlater\nlet later = 1"),
+            vec![
+                "1:9: binding with name ‘x’ does not exist",
+                "2:1: binding with name ‘later’ does not exist",
+            ],
+        );
+    }
+
+    #[test]
+    fn allow_use_of_bindings_defined_later_in_functions() {
+        assert_eq!(check_str("fun f => later\nlet later = 1\nf"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn do_not_leak_names_out_of_blocks_and_functions() {
+        assert_eq!(
+            check_str("{ let inner = 1 }\nfun f x => x\ninner + x"),
+            vec![
+                "3:1: binding with name ‘inner’ does not exist",
+                "3:9: binding with name ‘x’ does not exist",
+            ],
+        );
+    }
+
+    #[test]
+    fn allow_recursion() {
+        assert_eq!(check_str("fun loop n => loop n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn allow_calls_of_functions_defined_later() {
+        assert_eq!(
+            check_str(
+                "fun even n => if n == 0 then true else odd { n - 1 }\n\
+                 fun odd n => if n == 0 then false else even { n - 1 }\n\
+                 even 10",
+            ),
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            check_str("fun g x => h x\nfun h x y => x * y\nlater\nfun later => 1"),
+            vec![
+                "1:12: function ‘h’ expects 2 parameters, got 1",
+                "3:1: binding with name ‘later’ does not exist",
+            ],
+        );
+    }
+
+    #[test]
+    fn report_wrong_number_of_parameters() {
+        assert_eq!(
+            check_str("fun add x y => x + y\nadd 1\nadd\nint 1 2"),
+            vec![
+                "2:1: function ‘add’ expects 2 parameters, got 1",
+                "3:1: function ‘add’ expects 2 parameters, got 0",
+                "4:1: function ‘int’ expects 1 parameters, got 2",
+            ],
+        );
+    }
+
+    #[test]
+    fn report_calls_of_bindings() {
+        assert_eq!(
+            check_str("let f = 1\nf 2"),
+            vec!["2:1: function with name ‘f’ does not exist"],
+        );
+    }
+
+    #[test]
+    fn warn_about_shadowing() {
+        assert_eq!(
+            check_str("let a = 1\n{ let a = 2 }\nfun f a => a\nlet int = 3"),
+            vec![
+                "2:3: warning: ‘a’ shadows an earlier definition",
+                "3:1: warning: ‘a’ shadows an earlier definition",
+                "4:1: warning: ‘int’ shadows an earlier definition",
+            ],
+        );
+    }
+
+    #[test]
+    fn know_names_defined_in_env() {
+        let mut env = Env::default();
+        env.store_binding("limit", crate::Val::Number(10));
+        env.register_fn("double", |n: i64| n * 2);
+        assert_eq!(
+            check("double limit\ndouble", &env).unwrap().iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["2:1: function ‘double’ expects 1 parameters, got 0"],
+        );
+    }
+
+    #[test]
+    fn report_syntax_errors_instead() {
+        assert!(check("let = 1", &Env::default()).is_err());
+    }
+
+    #[test]
+    fn resolve_names_to_frames_and_slots() {
+        let mut env = Env::default();
+        env.store_binding("limit", crate::Val::Number(10));
+        let parse = crate::parse("let a = 1\nfun f x => { let y = x\na + y + limit }").unwrap();
+        let resolution = resolve(parse.statements(), &env);
+        assert_eq!(resolution.num_globals, 3);
+
+        let binding = |name: &str, depth, slot| Box::new(Expr::Binding { name: name.to_string(), depth, slot });
+        let [_, Stmt::FuncDef { slot: 2, func, .. }] = &resolution.stmts[..] else {
+            panic!("unexpected statements: {:?}", resolution.stmts);
+        };
+        assert_eq!(func.num_slots, 1);
+        assert_eq!(
+            func.body,
+            Some(Stmt::Expr(
+                Expr::Block {
+                    num_slots: 1,
+                    stmts: vec![
+                        Stmt::BindingDef {
+                            name: "y".to_string(),
+                            slot: 0,
+                            ty: None,
+                            value: *binding("x", 1, 0),
+                            span: Span { start: 23, end: 32 },
+                        },
+                        Stmt::Expr(
+                            Expr::Operation {
+                                lhs: Box::new(Expr::Operation {
+                                    lhs: binding("a", 2, 1),
+                                    rhs: binding("y", 0, 0),
+                                    op: ast::Operations::Add,
+                                }),
+                                rhs: binding("limit", 2, 0),
+                                op: ast::Operations::Add,
+                            },
+                            Span { start: 33, end: 46 },
+                        ),
+                    ],
+                },
+                Span { start: 21, end: 48 },
+            )),
+        );
+    }
+}
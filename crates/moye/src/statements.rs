@@ -1,53 +1,114 @@
-use crate::expr::Expression;
+use crate::expr::{Expression, Operations};
 use crate::binding_def::BindingDef;
+#[cfg(test)]
 use crate::val::Val;
+#[cfg(test)]
 use crate::env::Env;
 use crate::func_def::FuncDef;
+use crate::diagnostic::{self, SyntaxError};
+use crate::lexer::{Span, Token, TokenKind};
 
+/// A definition or an expression. A program is a sequence of statements.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) enum Statement {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Statement {
     BindingDef(BindingDef),
     Expression(Expression),
     FuncDef(FuncDef),
 }
 
 impl Statement {
-    pub(crate) fn new(s:&str) -> Result<(&str, Self), String> {
-        BindingDef::new(s)
-            .map(|(s, binding_def)| (s, Self::BindingDef(binding_def)))
-            .or_else(|_| FuncDef::new(s).map(|(s, func_def)| (s, Self::FuncDef(func_def))))
-            .or_else(|_| Expression::new(s).map(|(s, expr)| (s, Self::Expression(expr))))
+    pub fn span(&self) -> Span {
+        match self {
+            Self::BindingDef(binding_def) => binding_def.span,
+            Self::Expression(expr) => expr.span(),
+            Self::FuncDef(func_def) => func_def.span,
+        }
     }
 
-    pub(crate) fn eval(&self, env: &mut Env) -> Result<Val, String> {
-        match self {
-            Self::BindingDef(binding_def) => {
-                binding_def.eval(env)?;
-                Ok(Val::Unit)
-            },
-            Self::FuncDef(func_def) => {
-                func_def.eval(env)?;
-                Ok(Val::Unit)
-            },
-            Self::Expression(expr) => expr.eval(env),
+    /// `depth` is how many expressions and function definitions the
+    /// statement is nested in.
+    pub(crate) fn new(tokens: &[Token], depth: usize) -> Result<(&[Token], Self), SyntaxError> {
+        match tokens[0].kind {
+            TokenKind::Let => BindingDef::new(tokens, depth)
+                .map(|(tokens, binding_def)| (tokens, Self::BindingDef(binding_def))),
+            TokenKind::Fun => FuncDef::new(tokens, depth)
+                .map(|(tokens, func_def)| (tokens, Self::FuncDef(func_def))),
+            _ => Expression::new(tokens, depth)
+                .map(|(tokens, expr)| (tokens, Self::Expression(expr)))
+                .map_err(|error| error.merge(Self::expected_keyword(tokens))),
+        }
+    }
+
+    /// Parses statements up to the first token that cannot start one; the
+    /// error explains why no further statement could be parsed. Every
+    /// statement ends in an expression, so operators continuing it would
+    /// have been accepted as well.
+    pub(crate) fn new_sequence(mut tokens: &[Token], depth: usize) -> (&[Token], Vec<Self>, SyntaxError) {
+        let mut stmts = Vec::new();
+        loop {
+            match Self::new(tokens, depth) {
+                Ok((remainder, stmt)) => {
+                    tokens = remainder;
+                    stmts.push(stmt);
+                },
+                Err(error) if stmts.is_empty() => return (tokens, stmts, error),
+                Err(error) => {
+                    let error = error.merge(SyntaxError::expected(Operations::expected(), &tokens[0]));
+                    return (tokens, stmts, error);
+                },
+            }
         }
     }
+
+    /// Skips the statement at the start of `tokens`, which failed to parse
+    /// with `error`: resumes at the first line from the error on that does
+    /// not continue a block opened by the statement.
+    pub(crate) fn recover<'t>(tokens: &'t [Token], error: &SyntaxError) -> &'t [Token] {
+        let mut depth = 0;
+        for (idx, token) in tokens.iter().enumerate() {
+            let starts_line = idx > 0 && token.newline_before && token.span.start >= error.span.start;
+            if token.kind == TokenKind::Eof || (starts_line && depth <= 0) {
+                return &tokens[idx..];
+            }
+            match token.kind {
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace => depth -= 1,
+                _ => {},
+            }
+        }
+        unreachable!("tokens always end with `Eof`")
+    }
+
+    fn expected_keyword(tokens: &[Token]) -> SyntaxError {
+        let keywords = [TokenKind::Let, TokenKind::Fun];
+        SyntaxError::expected(keywords.iter().map(diagnostic::token), &tokens[0])
+    }
+
+    #[cfg(test)]
+    pub(crate) fn eval(&self, env: &mut Env) -> Result<Val, crate::error::Error> {
+        env.eval(std::slice::from_ref(self))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::parse_str;
     use crate::expr::{BindingUsage, Number, Operations};
 
     #[test]
     fn parse_binding_def() {
         assert_eq!(
-            Statement::new("let a = 10"), 
+            parse_str(|tokens| Statement::new(tokens, 0), "let a = 10"), 
             Ok((
                 "",
                 Statement::BindingDef(BindingDef {
                     name: "a".to_string(),
-                    value: Expression::Number(Number(10)),
+                    ty: None,
+                    value: Expression::Number(Number { value: 10, span: Span::default() }),
+                    span: Span::default(),
                 }),
             )),
         );
@@ -56,13 +117,14 @@ mod tests {
     #[test]
     fn parse_expression() {
         assert_eq!(
-            Statement::new("1+1"),
+            parse_str(|tokens| Statement::new(tokens, 0), "1+1"),
             Ok((
                 "",
                 Statement::Expression(Expression::Operation {
-                    lhs: Box::new(Expression::Number(Number(1))),
-                    rhs: Box::new(Expression::Number(Number(1))),
+                    lhs: Box::new(Expression::Number(Number { value: 1, span: Span::default() })),
+                    rhs: Box::new(Expression::Number(Number { value: 1, span: Span::default() })),
                     op: Operations::Add,
+                    span: Span::default(),
                 }),
             ))
         );
@@ -73,7 +135,9 @@ mod tests {
         assert_eq!(
             Statement::BindingDef(BindingDef {
                 name: "whatever".to_string(),
-                value: Expression::Number(Number(-10)),
+                ty: None,
+                value: Expression::Number(Number { value: -10, span: Span::default() }),
+                span: Span::default(),
             })
             .eval(&mut Env::default()),
             Ok(Val::Unit),
@@ -83,7 +147,7 @@ mod tests {
     #[test]
     fn eval_expr() {
         assert_eq!(
-            Statement::Expression(Expression::Number(Number(5))).eval(&mut Env::default()),
+            Statement::Expression(Expression::Number(Number { value: 5, span: Span::default() })).eval(&mut Env::default()),
             Ok(Val::Number(5)),
         );
     }
@@ -91,15 +155,19 @@ mod tests {
     #[test]
     fn parse_function_def() {
         assert_eq!(
-            Statement::new("fun name x => x"),
+            parse_str(|tokens| Statement::new(tokens, 0), "fun name x => x"),
             Ok((
                 "",
                 Statement::FuncDef(FuncDef {
                     name: "name".to_string(),
                     params: vec!["x".to_string()],
+                    param_types: vec![None],
+                    return_type: None,
                     body: Box::new(Statement::Expression(Expression::BindingUsage(BindingUsage {
                         name: "x".to_string(),
+                        span: Span::default(),
                     }))),
+                    span: Span::default(),
                 }),
             )),
         );
@@ -111,7 +179,10 @@ mod tests {
             Statement::FuncDef(FuncDef {
                 name: "always_return_one".to_string(),
                 params: Vec::new(),
-                body: Box::new(Statement::Expression(Expression::Number(Number(1)))),
+                param_types: Vec::new(),
+                return_type: None,
+                body: Box::new(Statement::Expression(Expression::Number(Number { value: 1, span: Span::default() }))),
+                span: Span::default(),
             })
             .eval(&mut Env::default()),
             Ok(Val::Unit),
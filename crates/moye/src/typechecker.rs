@@ -0,0 +1,728 @@
+/*
+ * Infers the type of every expression before the program runs, in the style
+ * of Hindley–Milner, and reports the ones that would fail at runtime.
+ * Bindings have the type of their value; functions get the most general
+ * types their bodies allow, so that `fun id x => x` works on any type, and
+ * annotations pin types down. Names are looked up like the resolver does;
+ * names it reports as undefined, and names defined later, can have any type
+ * here.
+ *
+ * Ints and floats mix like at runtime: `x + 1` has the type of `x`, and
+ * `x + 1.5` is a float. Where neither type is known, as in `x + y`, the
+ * result joins them: it is a float once either operand is, and an int once
+ * both are. Joins of a function's generic types are solved anew for every
+ * call. The branches of an `if` don't mix, though: both must have the same
+ * type, since the type of its value would otherwise depend on the branch
+ * taken
+ */
+
+use crate::annotation;
+use crate::ast::{self, Expression, Operations, Span, Statement, UnaryOperations};
+use crate::diagnostic::{Diagnostic, ParseError, Severity};
+use crate::env::Env;
+use crate::resolved::Slot;
+use crate::resolver::Problem;
+use crate::val::Val;
+use std::collections::{HashMap, HashSet};
+
+/// Parses `source` and reports operations, conditions, calls and annotated
+/// definitions whose types don't fit, in source order, without running any
+/// code. Bindings defined in `env` have the type of their value; functions
+/// defined there can take and return anything.
+pub fn check_types(source: &str, env: &Env) -> Result<Vec<Diagnostic>, ParseError> {
+    let parse = crate::parse(source)?;
+    Ok(infer(parse.statements(), env)
+        .problems
+        .into_iter()
+        .map(|problem| Diagnostic::at(source, problem.span, problem.severity, problem.message))
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    Unit,
+    List,
+    Map,
+    Var(usize),
+}
+
+// The types a type variable may still stand for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Any,
+    Number,
+    /// Ints and bools, which the bitwise operators work on.
+    Bits,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    Unbound(Kind),
+    Bound(Type),
+}
+
+/// The type of a function, for all types of its `generics`.
+#[derive(Debug, Clone)]
+struct Scheme {
+    generics: Vec<usize>,
+    params: Vec<Type>,
+    ret: Type,
+    /// The joins of the body that involve generic types.
+    joins: Vec<Join>,
+}
+
+/// The type of an arithmetic operation whose operands are numbers of types
+/// not known yet, reported at `span` if the types turn out not to fit.
+#[derive(Debug, Clone, Copy)]
+struct Join {
+    result: Type,
+    lhs: Type,
+    rhs: Type,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+enum Def {
+    Binding(Type),
+    Func(Scheme),
+}
+
+// Leaves the types of top-level definitions in the outermost scope.
+fn infer<'env>(stmts: &[Statement], env: &'env Env) -> TypeChecker<'env> {
+    let mut checker =
+        TypeChecker { env, vars: Vec::new(), joins: Vec::new(), scopes: vec![HashMap::new()], problems: Vec::new() };
+    for stmt in stmts {
+        checker.statement(stmt);
+    }
+    checker.problems.sort_by_key(|problem| problem.span.start);
+    checker
+}
+
+impl From<annotation::Type> for Type {
+    fn from(ty: annotation::Type) -> Self {
+        match ty {
+            annotation::Type::Int => Self::Int,
+            annotation::Type::Float => Self::Float,
+            annotation::Type::Bool => Self::Bool,
+            annotation::Type::String => Self::String,
+            annotation::Type::Unit => Self::Unit,
+            annotation::Type::List => Self::List,
+            annotation::Type::Map => Self::Map,
+        }
+    }
+}
+
+impl Type {
+    fn of(val: &Val) -> Self {
+        match val {
+            Val::Number(_) | Val::BigInt(_) => Self::Int,
+            Val::Float(_) => Self::Float,
+            Val::Bool(_) => Self::Bool,
+            Val::Str(_) => Self::String,
+            Val::List(_) => Self::List,
+            Val::Map(_) => Self::Map,
+            Val::Unit => Self::Unit,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Self::Int | Self::Float)
+    }
+}
+
+impl Kind {
+    fn allows(self, ty: Type) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Number => ty.is_number(),
+            Self::Bits => matches!(ty, Type::Int | Type::Bool),
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::Any => "a value",
+            Self::Number => "a number",
+            Self::Bits => "an int or bool",
+        }
+    }
+}
+
+struct TypeChecker<'env> {
+    env: &'env Env,
+    vars: Vec<Var>,
+    /// Joins that aren't solved yet.
+    joins: Vec<Join>,
+    /// Innermost last.
+    scopes: Vec<HashMap<String, Def>>,
+    problems: Vec<Problem>,
+}
+
+impl TypeChecker<'_> {
+    fn fresh(&mut self, kind: Kind) -> Type {
+        self.vars.push(Var::Unbound(kind));
+        Type::Var(self.vars.len() - 1)
+    }
+
+    // Follows bound variables to the type they stand for.
+    fn resolve(&self, ty: Type) -> Type {
+        match ty {
+            Type::Var(var) => match self.vars[var] {
+                Var::Bound(ty) => self.resolve(ty),
+                Var::Unbound(_) => ty,
+            },
+            ty => ty,
+        }
+    }
+
+    fn kind(&self, var: usize) -> Kind {
+        match self.vars[var] {
+            Var::Unbound(kind) => kind,
+            Var::Bound(_) => unreachable!("only unbound variables have a kind"),
+        }
+    }
+
+    // Makes two types the same, reporting at `span` if they can't be.
+    fn unify(&mut self, expected: Type, found: Type, span: Span) {
+        if self.try_unify(expected, found) {
+            self.solve_joins();
+        } else {
+            let message = format!("expected {}, found {}", self.describe(expected), self.describe(found));
+            self.error(span, message);
+        }
+    }
+
+    fn join(&mut self, lhs: Type, rhs: Type, span: Span) -> Type {
+        let result = self.fresh(Kind::Number);
+        self.joins.push(Join { result, lhs, rhs, span });
+        self.solve_joins();
+        result
+    }
+
+    // Solves the joins whose types are known well enough, until no more
+    // are.
+    fn solve_joins(&mut self) {
+        let mut solved_any = true;
+        while solved_any {
+            solved_any = false;
+            for join in std::mem::take(&mut self.joins) {
+                let Join { result, lhs, rhs, span } = join;
+                let pairs = match (self.resolve(lhs), self.resolve(rhs), self.resolve(result)) {
+                    (Type::Float, _, _) | (_, Type::Float, _) => vec![(result, Type::Float)],
+                    (Type::Int, other, _) | (other, Type::Int, _) => vec![(result, other)],
+                    (lhs, rhs, _) if lhs == rhs => vec![(result, lhs)],
+                    // Either operand being a float would make a float.
+                    (_, _, Type::Int) => vec![(lhs, Type::Int), (rhs, Type::Int)],
+                    _ => {
+                        self.joins.push(join);
+                        continue;
+                    },
+                };
+                for (expected, found) in pairs {
+                    if !self.try_unify(expected, found) {
+                        let message = format!("expected {}, found {}", self.describe(expected), self.describe(found));
+                        self.error(span, message);
+                    }
+                }
+                solved_any = true;
+            }
+        }
+    }
+
+    fn try_unify(&mut self, lhs: Type, rhs: Type) -> bool {
+        match (self.resolve(lhs), self.resolve(rhs)) {
+            (lhs, rhs) if lhs == rhs => true,
+            (Type::Var(lhs), Type::Var(rhs)) => {
+                let kind = match (self.kind(lhs), self.kind(rhs)) {
+                    (Kind::Any, kind) | (kind, Kind::Any) => kind,
+                    (lhs_kind, rhs_kind) if lhs_kind == rhs_kind => lhs_kind,
+                    // Only ints are both numbers and bits.
+                    _ => {
+                        self.vars[rhs] = Var::Bound(Type::Int);
+                        self.vars[lhs] = Var::Bound(Type::Int);
+                        return true;
+                    },
+                };
+                self.vars[rhs] = Var::Unbound(kind);
+                self.vars[lhs] = Var::Bound(Type::Var(rhs));
+                true
+            },
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                let allowed = self.kind(var).allows(ty);
+                if allowed {
+                    self.vars[var] = Var::Bound(ty);
+                }
+                allowed
+            },
+            _ => false,
+        }
+    }
+
+    // Restricts the operand of an operator to `kind`, returning its type.
+    fn operand(&mut self, expr: &Expression, kind: Kind) -> Type {
+        let ty = self.expression(expr);
+        match self.resolve(ty) {
+            Type::Var(var) => {
+                let constraint = self.fresh(kind);
+                self.try_unify(Type::Var(var), constraint);
+                self.solve_joins();
+            },
+            ty if !kind.allows(ty) => {
+                let message = format!("expected {}, found {}", kind.description(), self.describe(ty));
+                self.error(expr.span(), message);
+            },
+            _ => {},
+        }
+        ty
+    }
+
+    fn describe(&self, ty: Type) -> String {
+        match self.resolve(ty) {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::String => "string",
+            Type::Unit => "unit",
+            Type::List => "list",
+            Type::Map => "map",
+            Type::Var(var) => self.kind(var).description(),
+        }
+        .to_string()
+    }
+
+    fn define(&mut self, name: &str, def: Def) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), def);
+    }
+
+    // The innermost definition of a name hides all others, like in the
+    // resolver.
+    fn lookup(&mut self, name: &str) -> Option<Def> {
+        if let Some(def) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Some(def.clone());
+        }
+
+        match self.env.global(name) {
+            Some((_, Slot::Val(val))) => return Some(Def::Binding(Type::of(&val))),
+            Some((_, Slot::Func(func))) => {
+                let params: Vec<_> = (0..func.arity()).map(|_| self.fresh(Kind::Any)).collect();
+                let ret = self.fresh(Kind::Any);
+                return Some(Def::Func(Scheme { generics: Vec::new(), params, ret, joins: Vec::new() }));
+            },
+            Some((_, Slot::Empty)) | None => {},
+        }
+
+        let ret = match name {
+            "int" | "round" | "floor" => Type::Int,
+            "float" => Type::Float,
+            _ => return None,
+        };
+        let param = self.fresh(Kind::Number);
+        Some(Def::Func(Scheme { generics: Vec::new(), params: vec![param], ret, joins: Vec::new() }))
+    }
+
+    // Gives the generic variables of `scheme` fresh variables of the same
+    // kind, joined like the generic ones are.
+    fn instantiate(&mut self, scheme: &Scheme) -> (Vec<Type>, Type) {
+        let fresh: HashMap<_, _> =
+            scheme.generics.iter().map(|&var| (var, self.fresh(self.kind(var)))).collect();
+        let substitute = |ty: Type| match self.resolve(ty) {
+            Type::Var(var) => fresh.get(&var).copied().unwrap_or(Type::Var(var)),
+            ty => ty,
+        };
+        let params = scheme.params.iter().map(|&param| substitute(param)).collect();
+        let ret = substitute(scheme.ret);
+        let joins: Vec<_> = scheme
+            .joins
+            .iter()
+            .map(|join| Join {
+                result: substitute(join.result),
+                lhs: substitute(join.lhs),
+                rhs: substitute(join.rhs),
+                span: join.span,
+            })
+            .collect();
+        self.joins.extend(joins);
+        (params, ret)
+    }
+
+    // The variables of a function's type that nothing outside of it can
+    // still pin down. Joins of only those variables can't be solved any more
+    // but for a call, so they move to the scheme.
+    fn generalize(&mut self, params: Vec<Type>, ret: Type) -> Scheme {
+        let mut outside = HashSet::new();
+        for def in self.scopes.iter().flat_map(HashMap::values) {
+            let (types, generics): (Vec<Type>, &[usize]) = match def {
+                Def::Binding(ty) => (vec![*ty], &[]),
+                Def::Func(scheme) => ([&scheme.params[..], &[scheme.ret]].concat(), &scheme.generics),
+            };
+            outside.extend(self.unbound(&types).into_iter().filter(|var| !generics.contains(var)));
+        }
+
+        let params: Vec<_> = params.into_iter().map(|param| self.resolve(param)).collect();
+        let ret = self.resolve(ret);
+        let generics: Vec<_> =
+            self.unbound(&[&params[..], &[ret]].concat()).into_iter().filter(|var| !outside.contains(var)).collect();
+
+        let mut joins = Vec::new();
+        for join in std::mem::take(&mut self.joins) {
+            let vars = self.unbound(&[join.result, join.lhs, join.rhs]);
+            if vars.iter().any(|var| generics.contains(var)) {
+                joins.push(join);
+            }
+            if !vars.iter().all(|var| generics.contains(var)) {
+                self.joins.push(join);
+            }
+        }
+        Scheme { generics, params, ret, joins }
+    }
+
+    fn unbound(&self, types: &[Type]) -> Vec<usize> {
+        let mut vars = Vec::new();
+        for &ty in types {
+            if let Type::Var(var) = self.resolve(ty)
+                && !vars.contains(&var)
+            {
+                vars.push(var);
+            }
+        }
+        vars
+    }
+
+    // Returns the type of the value of the statement.
+    fn statement(&mut self, stmt: &Statement) -> Type {
+        match stmt {
+            Statement::BindingDef(binding_def) => {
+                let ty = self.expression(&binding_def.value);
+                let ty = match &binding_def.ty {
+                    Some(annotation) => {
+                        self.unify(annotation.ty.into(), ty, binding_def.value.span());
+                        annotation.ty.into()
+                    },
+                    None => ty,
+                };
+                self.define(&binding_def.name, Def::Binding(ty));
+                Type::Unit
+            },
+            Statement::FuncDef(func_def) => {
+                let params: Vec<_> = func_def
+                    .param_types
+                    .iter()
+                    .map(|annotation| match annotation {
+                        Some(annotation) => annotation.ty.into(),
+                        None => self.fresh(Kind::Any),
+                    })
+                    .collect();
+                let ret = match &func_def.return_type {
+                    Some(annotation) => annotation.ty.into(),
+                    None => self.fresh(Kind::Any),
+                };
+
+                // Recursive calls have the same types as the function.
+                let scheme = Scheme { generics: Vec::new(), params: params.clone(), ret, joins: Vec::new() };
+                self.define(&func_def.name, Def::Func(scheme));
+                self.scopes.push(
+                    func_def.params.iter().zip(&params).map(|(param, ty)| (param.clone(), Def::Binding(*ty))).collect(),
+                );
+                let body = self.statement(&func_def.body);
+                self.unify(ret, body, func_def.body.span());
+                self.scopes.pop();
+
+                self.scopes.last_mut().unwrap().remove(&func_def.name);
+                let scheme = self.generalize(params, ret);
+                self.define(&func_def.name, Def::Func(scheme));
+                Type::Unit
+            },
+            Statement::Expression(expr) => self.expression(expr),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Type {
+        match expr {
+            Expression::Number(_) | Expression::BigNumber(_) => Type::Int,
+            Expression::Float(_) => Type::Float,
+            Expression::Bool(_) => Type::Bool,
+            Expression::Operation { lhs, rhs, op, span } => self.operation(lhs, rhs, *op, *span),
+            Expression::Unary { op, operand, .. } => match op {
+                UnaryOperations::Not => self.operand(operand, Kind::Bits),
+                _ => self.operand(operand, Kind::Number),
+            },
+            Expression::FuncCall(func_call) => {
+                let args: Vec<_> = func_call.params.iter().map(|param| (self.expression(param), param.span())).collect();
+                self.call(&func_call.callee, &args)
+            },
+            // Names of functions call them without parameters.
+            Expression::BindingUsage(binding_usage) => match self.lookup(&binding_usage.name) {
+                Some(Def::Binding(ty)) => ty,
+                Some(Def::Func(_)) => self.call(&binding_usage.name, &[]),
+                None => self.fresh(Kind::Any),
+            },
+            Expression::Block(block) => {
+                self.scopes.push(HashMap::new());
+                let ty = block.stmts.iter().map(|stmt| self.statement(stmt)).last().unwrap_or(Type::Unit);
+                self.scopes.pop();
+                ty
+            },
+            Expression::IfElse(if_else) => {
+                let condition = self.expression(&if_else.condition);
+                self.unify(Type::Bool, condition, if_else.condition.span());
+                let then_branch = self.expression(&if_else.then_branch);
+                let else_branch = self.expression(&if_else.else_branch);
+                self.unify(then_branch, else_branch, if_else.else_branch.span());
+                then_branch
+            },
+        }
+    }
+
+    // Calls with the wrong number of parameters are reported by the resolver,
+    // and their value can have any type.
+    fn call(&mut self, name: &str, args: &[(Type, Span)]) -> Type {
+        let Some(Def::Func(scheme)) = self.lookup(name) else {
+            return self.fresh(Kind::Any);
+        };
+        if scheme.params.len() != args.len() {
+            return self.fresh(Kind::Any);
+        }
+
+        let (params, ret) = self.instantiate(&scheme);
+        for (param, &(arg, span)) in params.into_iter().zip(args) {
+            self.unify(param, arg, span);
+        }
+        self.solve_joins();
+        ret
+    }
+
+    fn operation(&mut self, lhs: &ast::Expression, rhs: &ast::Expression, op: Operations, span: Span) -> Type {
+        let kind = match op {
+            Operations::BitAnd | Operations::BitOr | Operations::BitXor => Kind::Bits,
+            Operations::Shl | Operations::Shr => {
+                for operand in [lhs, rhs] {
+                    let ty = self.expression(operand);
+                    self.unify(Type::Int, ty, operand.span());
+                }
+                return Type::Int;
+            },
+            Operations::Eq | Operations::Ne => Kind::Any,
+            _ => Kind::Number,
+        };
+        let lhs_type = self.operand(lhs, kind);
+        let rhs_type = self.operand(rhs, kind);
+
+        // Ints and floats mix into floats, and `==` compares anything, so
+        // only the operands of bitwise operators must have the same type.
+        // Operands of the wrong type have been reported already.
+        let ty = match (kind, self.resolve(lhs_type), self.resolve(rhs_type)) {
+            (_, lhs, rhs) if lhs.is_number() && rhs.is_number() => {
+                if lhs == Type::Float { lhs } else { rhs }
+            },
+            (Kind::Number, Type::Var(lhs), Type::Var(rhs)) if lhs != rhs && !op.is_comparison() => {
+                self.join(lhs_type, rhs_type, span)
+            },
+            (Kind::Number, Type::Var(_), Type::Float) | (Kind::Number, Type::Float, Type::Var(_)) => Type::Float,
+            (Kind::Number, Type::Int, var @ Type::Var(_)) => var,
+            (Kind::Any, Type::Var(_), _) | (Kind::Any, _, Type::Var(_)) => lhs_type,
+            (Kind::Any | Kind::Bits, lhs_resolved, rhs_resolved) => {
+                let fits = |ty| matches!(ty, Type::Var(_)) || kind.allows(ty);
+                if fits(lhs_resolved) && fits(rhs_resolved) {
+                    self.unify(lhs_type, rhs_type, rhs.span());
+                }
+                lhs_type
+            },
+            _ => lhs_type,
+        };
+
+        if op.is_comparison() { Type::Bool } else { ty }
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.problems.push(Problem { span, severity: Severity::Error, message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One line per problem.
+    fn check_str(source: &str) -> Vec<String> {
+        check_types(source, &Env::default()).unwrap().iter().map(ToString::to_string).collect()
+    }
+
+    fn def_types(source: &str) -> Vec<String> {
+        let parse = crate::parse(source).unwrap();
+        let env = Env::default();
+        let checker = infer(parse.statements(), &env);
+        assert_eq!(checker.problems, Vec::new());
+        let mut defs: Vec<_> =
+            checker.scopes[0].iter().map(|(name, def)| format!("{}: {}", name, checker.describe_def(def))).collect();
+        defs.sort();
+        defs
+    }
+
+    impl TypeChecker<'_> {
+        // Like annotations, with type variables named `a`, `b` and so on.
+        fn describe_def(&self, def: &Def) -> String {
+            let mut names = HashMap::new();
+            let mut name = |checker: &Self, ty: Type| match checker.resolve(ty) {
+                Type::Var(var) => {
+                    let next = names.len();
+                    let letter = *names.entry(var).or_insert_with(|| char::from(b'a' + next as u8));
+                    letter.to_string()
+                },
+                ty => annotation_name(ty).to_string(),
+            };
+            match def {
+                Def::Binding(ty) => name(self, *ty),
+                Def::Func(scheme) => {
+                    let params: Vec<_> = scheme.params.iter().map(|param| name(self, *param)).collect();
+                    format!("({}) -> {}", params.join(", "), name(self, scheme.ret))
+                },
+            }
+        }
+    }
+
+    fn annotation_name(ty: Type) -> &'static str {
+        match ty {
+            Type::Int => "Int",
+            Type::Float => "Float",
+            Type::Bool => "Bool",
+            Type::String => "String",
+            Type::Unit => "Unit",
+            Type::List => "List",
+            Type::Map => "Map",
+            Type::Var(_) => unreachable!("type variables have no annotation"),
+        }
+    }
+
+    #[test]
+    fn infer_types_of_definitions() {
+        assert_eq!(
+            def_types(
+                "let a = 1 + 2.5\nlet b = a < 2\nfun fib n => if n < 2 then n else { fib n - 1 } + { fib n - 2 }\n\
+                 fun id x => x\nfun add x y => x + y\nfun inc x => x + 1\nlet c = { inc 1.5 } * { add 1 2 }\n\
+                 let d = id true",
+            ),
+            vec![
+                "a: Float",
+                "add: (a, b) -> c",
+                "b: Bool",
+                "c: Float",
+                "d: Bool",
+                "fib: (a) -> a",
+                "id: (a) -> a",
+                "inc: (a) -> a",
+            ],
+        );
+    }
+
+    #[test]
+    fn infer_types_from_annotations() {
+        assert_eq!(
+            def_types("fun half (x: Float) -> Float => x / 2\nfun unit -> Unit => {}\nlet n: Int = int { half 3.0 }"),
+            vec!["half: (Float) -> Float", "n: Int", "unit: () -> Unit"],
+        );
+    }
+
+    #[test]
+    fn report_mismatched_types() {
+        assert_eq!(
+            check_str("let a = 1 + true\nif 1 then 2 else 3\nif true then 1 else false\n1 << 2.5\n!1.5"),
+            vec![
+                "1:13: expected a number, found bool",
+                "2:4: expected bool, found int",
+                "3:21: expected int, found bool",
+                "4:6: expected int, found float",
+                "5:2: expected an int or bool, found float",
+            ],
+        );
+    }
+
+    #[test]
+    fn report_calls_with_mismatched_parameters() {
+        assert_eq!(
+            check_str("fun inc x => x + 1\ninc true\nfun both x y => x & y\nboth 1 { true }\nint false"),
+            vec![
+                "2:5: expected a number, found bool",
+                "4:8: expected int, found bool",
+                "5:5: expected a number, found bool",
+            ],
+        );
+    }
+
+    #[test]
+    fn report_definitions_that_contradict_annotations() {
+        assert_eq!(
+            check_str("let a: Bool = 1\nfun f (x: Int) -> Bool => x\nf 1.5\nlet b: Int = f 1"),
+            vec![
+                "1:15: expected bool, found int",
+                "2:27: expected bool, found int",
+                "3:3: expected int, found float",
+                "4:14: expected int, found bool",
+            ],
+        );
+    }
+
+    #[test]
+    fn mix_ints_and_floats_like_at_runtime() {
+        assert_eq!(
+            check_str(
+                "fun add x y => x + y\nadd 1 2.5\nfun same x y => x == y\nsame 1 2.5\n\
+                 fun inc x => x + 1\nlet n: Int = inc 1.5",
+            ),
+            vec!["6:14: expected int, found float"],
+        );
+    }
+
+    #[test]
+    fn join_types_of_operands_at_every_call() {
+        assert_eq!(
+            check_str(
+                "fun f x y => x + y\nlet a: Int = f 1.5 2\nlet b = f 1.5 2\nb & 1\nlet c: Int = f 1 2\n\
+                 fun g x y => f x y * 2\nlet d: Float = g 1 2.5",
+            ),
+            vec!["2:14: expected int, found float", "4:1: expected an int or bool, found float"],
+        );
+        assert_eq!(
+            check_str("fun h (n: Int) -> Int => n\nfun f x y => h { x + y }\nf 1 2\nf 1 2.5"),
+            vec!["4:5: expected int, found float"],
+        );
+        assert_eq!(
+            def_types("fun f x y => x - y\nlet a = f 1 2\nlet b = f 2 0.5"),
+            vec!["a: Int", "b: Float", "f: (a, b) -> c"],
+        );
+    }
+
+    // The value of the `if` would be an int or a float depending on the
+    // condition.
+    #[test]
+    fn require_branches_of_the_same_type() {
+        assert_eq!(check_str("fun f x => if x then 1 else 2.5"), vec!["1:29: expected int, found float"]);
+        assert_eq!(check_str("fun f x => if x then 1 else 2.5 + 0"), vec!["1:29: expected int, found float"]);
+        assert_eq!(check_str("fun f x y => if x then y else 2.5"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn use_generic_functions_at_different_types() {
+        assert_eq!(check_str("fun id x => x\nlet a = id 1\nlet b = id true\nif b then a else 2"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn use_types_of_host_definitions() {
+        let mut env = Env::default();
+        env.store_binding("name", Val::Str("moye".to_string()));
+        env.register_fn("shout", |s: String| s.to_uppercase());
+        let diagnostics = check_types("shout 1\nname + 1", &env).unwrap();
+        assert_eq!(
+            diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["2:1: expected a number, found string"],
+        );
+    }
+
+    #[test]
+    fn leave_undefined_names_to_the_resolver() {
+        assert_eq!(check_str("undefined + 1\nf 1 2\nlet a = b"), Vec::<String>::new());
+    }
+}
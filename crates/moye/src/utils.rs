@@ -1,8 +1,12 @@
+use crate::diagnostic::{self, SyntaxError};
+use crate::lexer::{Span, Token, TokenKind};
+use crate::limits;
+
 pub(crate) fn take_while(accept: impl Fn(char) -> bool, s:&str) -> (&str, &str) {
     let extracted_end = s
         .char_indices()
         .find_map(|(idx, ch)| if accept(ch) { None } else { Some(idx) })
-        .unwrap_or_else(|| s.len());
+        .unwrap_or(s.len());
     let extracted = &s[..extracted_end];
     let remainder = &s[extracted_end..];
     (remainder, extracted)
@@ -17,72 +21,152 @@ fn take_while_with_error(accept: impl Fn(char) -> bool, s:&str, error_msg: Strin
     }
 }
 
-const WHITESPACE: &[char] = &[' ', '\n'];
+pub(crate) fn extract_digits(s:&str) -> Result<(&str, &str), String> {
+    take_while_with_error(|c| c.is_ascii_digit(), s, "expected digits".to_string())
+}
 
-pub(crate) fn extract_whitespaces(s:&str) -> (&str, &str) {
-    take_while(|c| WHITESPACE.contains(&c), s)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NumberLiteral<'a> {
+    /// `digits` excludes the radix prefix and may contain `_` separators.
+    Integer { digits: &'a str, radix: u32 },
+    /// May contain `_` separators.
+    Float(&'a str),
 }
 
-pub(crate) fn extract_whitespaces_with_error(s:&str) -> Result<(&str, &str), String> {
-    take_while_with_error(|c| WHITESPACE.contains(&c), s, "expected a space".to_string())
+const RADIX_PREFIXES: &[(&str, u32, &str)] = &[
+    ("0x", 16, "hexadecimal"),
+    ("0o", 8, "octal"),
+    ("0b", 2, "binary"),
+];
+
+// Separators may not come first, so `_1` is still an identifier.
+fn extract_decimal_digits(s: &str) -> Result<(&str, &str), String> {
+    extract_digits(s)?;
+    Ok(take_while(|c| c.is_ascii_digit() || c == '_', s))
 }
 
-pub(crate) fn extract_digits(s:&str) -> Result<(&str, &str), String> {
-    take_while_with_error(|c| c.is_ascii_digit(), s, "expected digits".to_string())
+// Scans `0x`, `0o` or `0b` followed by digits in that base, or
+// `digits [. digits] [(e|E) [+|-] digits]` where only a fractional part or an
+// exponent makes the literal a float. Any digits may be separated by `_`.
+pub(crate) fn extract_number(s: &str) -> Result<(&str, NumberLiteral<'_>), String> {
+    for (prefix, radix, base_name) in RADIX_PREFIXES {
+        if let Some(after_prefix) = s.strip_prefix(prefix) {
+            return extract_prefixed_integer(after_prefix, *radix, prefix, base_name);
+        }
+    }
+
+    let (mut remainder, _) = extract_decimal_digits(s)?;
+
+    if let Some(after_dot) = remainder.strip_prefix('.')
+        && let Ok((after_fraction, _)) = extract_decimal_digits(after_dot)
+    {
+        remainder = after_fraction;
+    }
+
+    if let Some(after_e) = remainder.strip_prefix(['e', 'E'])
+        && let Ok((after_exponent, _)) = extract_decimal_digits(after_e.strip_prefix(['+', '-']).unwrap_or(after_e))
+    {
+        remainder = after_exponent;
+    }
+
+    let literal = &s[..s.len() - remainder.len()];
+    if literal.contains(['.', 'e', 'E']) {
+        Ok((remainder, NumberLiteral::Float(literal)))
+    } else {
+        Ok((remainder, NumberLiteral::Integer { digits: literal, radix: 10 }))
+    }
 }
 
+fn extract_prefixed_integer<'a>(
+    s: &'a str,
+    radix: u32,
+    prefix: &str,
+    base_name: &str,
+) -> Result<(&'a str, NumberLiteral<'a>), String> {
+    // Take letters too, so that `0b12` is reported instead of parsed as `0b1`
+    // followed by `2`.
+    let (remainder, digits) = take_while(|c| c.is_ascii_alphanumeric() || c == '_', s);
+
+    if let Some(invalid) = digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+        return Err(format!("invalid digit ‘{}’ in {} literal", invalid, base_name));
+    }
+    if !digits.chars().any(|c| c != '_') {
+        return Err(format!("expected {} digits after {}", base_name, prefix));
+    }
+
+    Ok((remainder, NumberLiteral::Integer { digits, radix }))
+}
+
+// Identifiers follow Unicode's XID rules, and may also start with `_`.
 pub(crate) fn extract_ident(s:&str) -> Result<(&str, &str), String> {
-    let input_starts_with_alphabet = s
+    let input_starts_with_ident_start = s
         .chars()
         .next()
-        .map(|ch| ch.is_ascii_alphabetic())
+        .map(|ch| ch == '_' || unicode_ident::is_xid_start(ch))
         .unwrap_or(false);
-    if input_starts_with_alphabet {
-        Ok(take_while(|ch| ch.is_ascii_alphanumeric(), s))
+    if input_starts_with_ident_start {
+        Ok(take_while(unicode_ident::is_xid_continue, s))
     } else {
         Err("expected identifier".to_string())
     }
 }
 
-pub(crate) fn tag<'a, 'b>(starting_text: &'a str, s: &'b str) -> Result<&'b str, String> {
-    if s.starts_with(starting_text) {
-        Ok(&s[starting_text.len()..])
+// Token slices always end with `Eof`, so they are never empty.
+pub(crate) fn tag<'t>(kind: &TokenKind, tokens: &'t [Token]) -> Result<&'t [Token], SyntaxError> {
+    if tokens[0].kind == *kind {
+        Ok(&tokens[1..])
     } else {
-        Err(format!("expected {}", starting_text))
+        Err(SyntaxError::expected([diagnostic::token(kind)], &tokens[0]))
+    }
+}
+
+pub(crate) fn extract_ident_token(tokens: &[Token]) -> Result<(&[Token], String), SyntaxError> {
+    match &tokens[0].kind {
+        TokenKind::Ident(name) => Ok((&tokens[1..], name.clone())),
+        kind if kind.is_keyword() => Err(SyntaxError::new(
+            tokens[0].span,
+            format!("‘{}’ is a reserved word and cannot be used as an identifier", kind),
+        )),
+        _ => Err(SyntaxError::expected(["identifier"], &tokens[0])),
     }
 }
 
-pub(crate) fn sequence<T>(
-    parser: impl Fn(&str) -> Result<(&str, T), String>,
-    separator_parser: impl Fn(&str) -> (&str, &str),
-    mut s: &str,
-) -> Result<(&str, Vec<T>), String> {
-    let mut items = Vec::new();
-    while let Ok((new_s, item)) = parser(s) {
-        s = new_s;
-        items.push(item);
-        let (new_s, _) = separator_parser(s);
-        s = new_s;
-    }
-    Ok((s, items))
+// A `-` directly in front of a number literal makes the literal negative
+// (rather than negating it), which is the only way to write the smallest
+// `i64`.
+pub(crate) fn extract_minus(tokens: &[Token]) -> (&[Token], &'static str) {
+    match tokens {
+        [minus, number, ..]
+            if minus.kind == TokenKind::Minus
+                && matches!(number.kind, TokenKind::Int { .. } | TokenKind::Float(_))
+                && !number.is_separated_from(minus) =>
+        {
+            (&tokens[1..], "-")
+        },
+        _ => (tokens, ""),
+    }
 }
 
-pub(crate) fn non_empty_sequence<T>(
-    parser: impl Fn(&str) -> Result<(&str, T), String>,
-    separator_parser: impl Fn(&str) -> (&str, &str),
-    s: &str,
-) -> Result<(&str, Vec<T>), String> {
-    let (s, sequence) = sequence(parser, separator_parser, s)?;
-    if sequence.is_empty() {
-        Err("expected a sequence with more than one item".to_string())
+// Rejects an expression or function definition starting at `token` that
+// would be nested in `depth` others, if that is too deep.
+pub(crate) fn check_nesting(depth: usize, token: &Token) -> Result<(), SyntaxError> {
+    if depth < limits::MAX_NESTING {
+        Ok(())
     } else {
-        Ok((s, sequence))
+        Err(SyntaxError::new(token.span, limits::nested_too_deeply()))
     }
 }
 
+// The span of the tokens a parser consumed, given the tokens it started with
+// and the ones it left over.
+pub(crate) fn consumed(tokens: &[Token], remainder: &[Token]) -> Span {
+    tokens[0].span.to(tokens[tokens.len() - remainder.len() - 1].span)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer;
 
     #[test]
     fn extract_one_digit() {
@@ -100,23 +184,77 @@ mod tests {
     }
 
     #[test]
-    fn extract_newlines_or_spaces() {
-        assert_eq!(extract_whitespaces(" \n     \n\nabc"), ("abc", " \n     \n\n"));
+    fn extract_integer_literal() {
+        assert_eq!(
+            extract_number("42 + 1"),
+            Ok((" + 1", NumberLiteral::Integer { digits: "42", radix: 10 })),
+        );
     }
 
     #[test]
-    fn do_not_extract_spaces_when_input_does_not_start_with_them() {
-        assert_eq!(extract_whitespaces_with_error("blah"), Err("expected a space".to_string()));
+    fn extract_integer_literal_with_separators() {
+        assert_eq!(
+            extract_number("1_000_000"),
+            Ok(("", NumberLiteral::Integer { digits: "1_000_000", radix: 10 })),
+        );
     }
 
     #[test]
-    fn extract_digits_with_no_remainder() {
-        assert_eq!(extract_digits("100"), Ok(("", "100")))
+    fn extract_prefixed_integer_literals() {
+        assert_eq!(
+            extract_number("0xFF_ff)"),
+            Ok((")", NumberLiteral::Integer { digits: "FF_ff", radix: 16 })),
+        );
+        assert_eq!(
+            extract_number("0o17"),
+            Ok(("", NumberLiteral::Integer { digits: "17", radix: 8 })),
+        );
+        assert_eq!(
+            extract_number("0b1010 "),
+            Ok((" ", NumberLiteral::Integer { digits: "1010", radix: 2 })),
+        );
+    }
+
+    #[test]
+    fn reject_invalid_digit_for_base() {
+        assert_eq!(extract_number("0b102"), Err("invalid digit ‘2’ in binary literal".to_string()));
+        assert_eq!(extract_number("0o8"), Err("invalid digit ‘8’ in octal literal".to_string()));
+        assert_eq!(extract_number("0xFG"), Err("invalid digit ‘G’ in hexadecimal literal".to_string()));
     }
 
     #[test]
-    fn extract_spaces() {
-        assert_eq!(extract_whitespaces("  4"), ("4", "  "))
+    fn reject_prefix_without_digits() {
+        assert_eq!(extract_number("0x"), Err("expected hexadecimal digits after 0x".to_string()));
+        assert_eq!(extract_number("0b_ "), Err("expected binary digits after 0b".to_string()));
+    }
+
+    #[test]
+    fn extract_float_literal_with_separators() {
+        assert_eq!(extract_number("1_000.000_1"), Ok(("", NumberLiteral::Float("1_000.000_1"))));
+    }
+
+    #[test]
+    fn extract_float_literal_with_fraction() {
+        assert_eq!(extract_number("1.5*2"), Ok(("*2", NumberLiteral::Float("1.5"))));
+    }
+
+    #[test]
+    fn extract_float_literal_with_exponent() {
+        assert_eq!(extract_number("1e-3"), Ok(("", NumberLiteral::Float("1e-3"))));
+        assert_eq!(extract_number("2.5E+10x"), Ok(("x", NumberLiteral::Float("2.5E+10"))));
+    }
+
+    #[test]
+    fn do_not_extract_incomplete_fraction_or_exponent() {
+        assert_eq!(extract_number("1."), Ok((".", NumberLiteral::Integer { digits: "1", radix: 10 })));
+        assert_eq!(extract_number("3e"), Ok(("e", NumberLiteral::Integer { digits: "3", radix: 10 })));
+        assert_eq!(extract_number("3e+"), Ok(("e+", NumberLiteral::Integer { digits: "3", radix: 10 })));
+        assert_eq!(extract_number("1._5"), Ok(("._5", NumberLiteral::Integer { digits: "1", radix: 10 })));
+    }
+
+    #[test]
+    fn extract_digits_with_no_remainder() {
+        assert_eq!(extract_digits("100"), Ok(("", "100")))
     }
 
     #[test]
@@ -129,14 +267,54 @@ mod tests {
         assert_eq!(extract_ident("var1()"), Ok(("()", "var1")))
     }
 
+    #[test]
+    fn extract_ident_with_underscores() {
+        assert_eq!(extract_ident("_private_1 x"), Ok((" x", "_private_1")));
+        assert_eq!(extract_ident("_"), Ok(("", "_")));
+    }
+
+    #[test]
+    fn extract_unicode_ident() {
+        assert_eq!(extract_ident("größe+1"), Ok(("+1", "größe")));
+        assert_eq!(extract_ident("π"), Ok(("", "π")));
+    }
+
+    #[test]
+    fn cannot_extract_ident_token_from_keyword() {
+        let tokens = lexer::lex("fun").unwrap();
+        assert_eq!(
+            extract_ident_token(&tokens).map_err(|error| error.to_string()),
+            Err("‘fun’ is a reserved word and cannot be used as an identifier".to_string()),
+        );
+    }
+
     #[test]
     fn cannot_extract_ident_beginning_with_number() {
         assert_eq!(extract_ident("123abc"), Err("expected identifier".to_string()));
     }
 
     #[test]
-    fn tag_word() {
-        assert_eq!(tag("let", "let a"), Ok(" a"));
+    fn tag_keyword() {
+        let tokens = lexer::lex("let a").unwrap();
+        assert_eq!(tag(&TokenKind::Let, &tokens), Ok(&tokens[1..]));
+        assert_eq!(
+            tag(&TokenKind::Fun, &tokens).map_err(|error| error.to_string()),
+            Err("expected ‘fun’, found ‘let’".to_string()),
+        );
+    }
+
+    #[test]
+    fn extract_minus_only_directly_before_number() {
+        let tokens = lexer::lex("-1 - 2 -x").unwrap();
+        assert_eq!(extract_minus(&tokens), (&tokens[1..], "-"));
+        assert_eq!(extract_minus(&tokens[2..]), (&tokens[2..], ""));
+        assert_eq!(extract_minus(&tokens[4..]), (&tokens[4..], ""));
+    }
+
+    #[test]
+    fn span_of_consumed_tokens() {
+        let tokens = lexer::lex("let a = 1").unwrap();
+        assert_eq!(consumed(&tokens, &tokens[2..]), Span { start: 0, end: 5 });
     }
 }
 
@@ -1,16 +1,130 @@
+use num_bigint::BigInt;
+use std::collections::BTreeMap;
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Val {
-    Number(i32),
+    Number(i64),
+    /// Integers outside the range of `i64`; arithmetic only ever produces
+    /// this variant for such values, smaller results are always `Number`.
+    BigInt(BigInt),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<Val>),
+    Map(BTreeMap<String, Val>),
     Unit,
 }
 
+impl Val {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Number(_) | Self::BigInt(_) => "int",
+            Self::Float(_) => "float",
+            Self::Bool(_) => "bool",
+            Self::Str(_) => "string",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Unit => "unit",
+        }
+    }
+}
+
 impl fmt::Display for Val {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Number(n) => write!(f, "{}", n),
+            Self::BigInt(n) => write!(f, "{}", n),
+            // Debug formatting is the shortest representation that parses
+            // back to the same float, and always has a `.` or an exponent.
+            Self::Float(x) => write!(f, "{:?}", x),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Str(s) => write!(f, "{:?}", s),
+            Self::List(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Self::Map(entries) => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in entries.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            },
             Self::Unit => write!(f, "Unit"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_number() {
+        assert_eq!(Val::Number(-3).to_string(), "-3");
+    }
+
+    #[test]
+    fn display_big_int() {
+        let n: BigInt = "-123456789012345678901234567890".parse().unwrap();
+        assert_eq!(Val::BigInt(n).to_string(), "-123456789012345678901234567890");
+    }
+
+    #[test]
+    fn display_float_distinct_from_int() {
+        assert_eq!(Val::Float(3.0).to_string(), "3.0");
+        assert_eq!(Val::Float(0.1).to_string(), "0.1");
+        assert_eq!(Val::Float(1e-7).to_string(), "1e-7");
+        assert_eq!(Val::Float(1e21).to_string(), "1e21");
+    }
+
+    #[test]
+    fn display_string_is_quoted() {
+        assert_eq!(Val::Str("hi \"there\"".to_string()).to_string(), r#""hi \"there\"""#);
+    }
+
+    #[test]
+    fn display_nested_list() {
+        assert_eq!(
+            Val::List(vec![Val::Number(1), Val::List(vec![Val::Bool(true), Val::Unit])]).to_string(),
+            "[1, [true, Unit]]",
+        );
+    }
+
+    #[test]
+    fn display_map_in_key_order() {
+        let map = BTreeMap::from([
+            ("b".to_string(), Val::Number(2)),
+            ("a".to_string(), Val::Number(1)),
+        ]);
+        assert_eq!(Val::Map(map).to_string(), r#"{"a": 1, "b": 2}"#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_to_json() {
+        let val = Val::List(vec![Val::Number(1), Val::Str("a".to_string()), Val::Unit]);
+        let json = serde_json::to_string(&val).unwrap();
+        assert_eq!(json, r#"{"List":[{"Number":1},{"Str":"a"},"Unit"]}"#);
+        assert_eq!(serde_json::from_str::<Val>(&json).unwrap(), val);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trip_big_int_through_json() {
+        let val = Val::BigInt(BigInt::from(i64::MAX) * 4);
+        let json = serde_json::to_string(&val).unwrap();
+        assert_eq!(serde_json::from_str::<Val>(&json).unwrap(), val);
+    }
+}
@@ -0,0 +1,346 @@
+/*
+ * Runs bytecode in a loop, keeping the calls of moye functions on a stack of
+ * its own rather than on the Rust stack. Frames are the same as for the tree
+ * walker, so both engines can run in the same environment and call the
+ * functions the other one defined
+ */
+
+use crate::arith;
+use crate::bytecode::{Code, Op};
+use crate::error::Error;
+use crate::limits::{Budget, CallGuard};
+use crate::resolved::{self, Frame, Func, Function, Slot};
+use crate::val::Val;
+use std::mem;
+use std::rc::Rc;
+
+/// How an environment runs code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Walks the resolved syntax tree. Slower, but simple enough to serve as
+    /// the reference for the VM.
+    TreeWalker,
+    /// Compiles to bytecode and runs it in a virtual machine.
+    #[default]
+    Bytecode,
+}
+
+/// Runs `code` in `frame`.
+pub(crate) fn run(code: Rc<Code>, frame: &Rc<Frame>, budget: &Budget) -> Result<Val, Error> {
+    Vm::new(budget).run(code, Rc::clone(frame))
+}
+
+/// Calls `function`, which was defined in `parent`.
+pub(crate) fn call(function: &Function, parent: &Rc<Frame>, args: Vec<Val>, budget: &Budget) -> Result<Val, Error> {
+    if function.arity != args.len() {
+        return Err(resolved::arity_mismatch(function.arity, args.len()));
+    }
+
+    let _guard = budget.enter_call(1)?;
+    Vm::new(budget).run(function.code(), function.frame(args, parent)?)
+}
+
+// Where to continue once a function returns.
+struct Caller<'b> {
+    code: Rc<Code>,
+    ip: usize,
+    frame: Rc<Frame>,
+    _guard: CallGuard<'b>,
+}
+
+struct Vm<'b> {
+    stack: Vec<Val>,
+    callers: Vec<Caller<'b>>,
+    budget: &'b Budget,
+}
+
+impl<'b> Vm<'b> {
+    fn new(budget: &'b Budget) -> Self {
+        Self { stack: Vec::new(), callers: Vec::new(), budget }
+    }
+
+    // Errors are blamed on the statement of the instruction that failed.
+    fn run(mut self, mut code: Rc<Code>, mut frame: Rc<Frame>) -> Result<Val, Error> {
+        let mut ip = 0;
+        loop {
+            let op = code.ops[ip];
+            ip += 1;
+            match self.execute(op, &mut code, &mut ip, &mut frame) {
+                Ok(None) => {},
+                Ok(Some(val)) => return Ok(val),
+                Err(error) => return Err(error.within(code.position(ip - 1))),
+            }
+        }
+    }
+
+    // Returns the value of the code run first, once it returns.
+    #[inline(always)]
+    fn execute(
+        &mut self,
+        op: Op,
+        code: &mut Rc<Code>,
+        ip: &mut usize,
+        frame: &mut Rc<Frame>,
+    ) -> Result<Option<Val>, Error> {
+        match op {
+            Op::Const(constant) => {
+                self.budget.step()?;
+                self.stack.push(code.consts[constant as usize].clone());
+            },
+            Op::Unit => self.stack.push(Val::Unit),
+            Op::Pop => {
+                self.pop();
+            },
+            Op::Step => self.budget.step()?,
+            Op::Load { depth, slot, name } => {
+                self.budget.step()?;
+                let defined_in = frame.ancestor(depth as usize);
+                match defined_in.get(slot as usize) {
+                    Slot::Val(val) => self.stack.push(val),
+                    Slot::Func(func) => {
+                        let defined_in = Rc::clone(defined_in);
+                        self.call(func, defined_in, 0, 1, code, ip, frame)?;
+                    },
+                    Slot::Empty => {
+                        return Err(resolved::undefined("binding", &code.strings[name as usize]));
+                    },
+                }
+            },
+            Op::Store { slot } => {
+                let val = self.pop();
+                frame.set(slot as usize, Slot::Val(val));
+            },
+            Op::DefineFunc { slot, function } => {
+                let function = Rc::clone(&code.functions[function as usize]);
+                frame.set(slot as usize, Slot::Func(Func::User(function)));
+            },
+            Op::Call { depth, slot, argc, name, nesting } => {
+                self.budget.step()?;
+                let defined_in = frame.ancestor(depth as usize);
+                let Slot::Func(func) = defined_in.get(slot as usize) else {
+                    return Err(resolved::undefined("function", &code.strings[name as usize]));
+                };
+                let defined_in = Rc::clone(defined_in);
+                self.call(func, defined_in, argc as usize, nesting as usize, code, ip, frame)?;
+            },
+            Op::TailCall { depth, slot, argc, name } => {
+                self.budget.step()?;
+                let defined_in = frame.ancestor(depth as usize);
+                let Slot::Func(func) = defined_in.get(slot as usize) else {
+                    return Err(resolved::undefined("function", &code.strings[name as usize]));
+                };
+                let defined_in = Rc::clone(defined_in);
+                match func {
+                    // The callee takes over the caller of the current
+                    // function, and its place in the call depth.
+                    Func::User(function) if function.arity == argc as usize => {
+                        self.budget.tail_call()?;
+                        let args = self.pop_args_for(&function, argc as usize);
+                        *frame = function.frame(args, &defined_in)?;
+                        *code = function.code();
+                        *ip = 0;
+                    },
+                    func => self.call(func, defined_in, argc as usize, 1, code, ip, frame)?,
+                }
+            },
+            Op::CallBuiltin { builtin, argc } => {
+                self.budget.step()?;
+                let args = self.pop_args(argc as usize);
+                let val = code.builtins[builtin as usize].1.call(args)?;
+                self.stack.push(val);
+            },
+            Op::Binary(op) => {
+                self.budget.step()?;
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.stack.push(arith::binary(&op, lhs, rhs)?);
+            },
+            Op::Unary(op) => {
+                self.budget.step()?;
+                let operand = self.pop();
+                self.stack.push(arith::unary(&op, operand)?);
+            },
+            Op::Jump(target) => *ip = target as usize,
+            Op::JumpUnless(target) => {
+                self.budget.step()?;
+                match self.pop() {
+                    Val::Bool(true) => {},
+                    Val::Bool(false) => *ip = target as usize,
+                    other => {
+                        return Err(resolved::not_a_condition(&other));
+                    },
+                }
+            },
+            Op::PushFrame { num_slots } => {
+                self.budget.step()?;
+                *frame = Frame::new(num_slots as usize, Some(Rc::clone(frame)));
+            },
+            Op::PopFrame => {
+                let parent = frame.parent().expect("frames are pushed before they are popped");
+                *frame = Rc::clone(parent);
+            },
+            Op::Fail { kind, message } => {
+                self.budget.step()?;
+                return Err(Error::new(kind, code.strings[message as usize].clone()));
+            },
+            Op::Check { ty, what } => {
+                let val = self.stack.last().expect("every expression leaves a value on the stack");
+                ty.enforce(val, || code.strings[what as usize].clone())?;
+            },
+            Op::Return => match self.callers.pop() {
+                Some(caller) => {
+                    *code = caller.code;
+                    *ip = caller.ip;
+                    *frame = caller.frame;
+                },
+                None => return Ok(Some(self.pop())),
+            },
+        }
+        Ok(None)
+    }
+
+    // Calls of moye functions continue in the callee; the others complete
+    // right away. `nesting` is as for the tree walker.
+    #[allow(clippy::too_many_arguments)]
+    fn call(
+        &mut self,
+        func: Func,
+        defined_in: Rc<Frame>,
+        argc: usize,
+        nesting: usize,
+        code: &mut Rc<Code>,
+        ip: &mut usize,
+        frame: &mut Rc<Frame>,
+    ) -> Result<(), Error> {
+        match func {
+            Func::User(function) if function.arity == argc => {
+                let guard = self.budget.enter_call(nesting)?;
+                let args = self.pop_args_for(&function, argc);
+                let callee_frame = function.frame(args, &defined_in)?;
+                self.callers.push(Caller {
+                    code: mem::replace(code, function.code()),
+                    ip: mem::replace(ip, 0),
+                    frame: mem::replace(frame, callee_frame),
+                    _guard: guard,
+                });
+            },
+            func => {
+                let args = self.pop_args(argc);
+                let val = func.call(&defined_in, args, self.budget)?;
+                self.stack.push(val);
+            },
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Val {
+        self.stack.pop().expect("every expression leaves a value on the stack")
+    }
+
+    fn pop_args(&mut self, argc: usize) -> Vec<Val> {
+        self.stack.split_off(self.stack.len() - argc)
+    }
+
+    // With room for the other slots of the frame of `function`.
+    fn pop_args_for(&mut self, function: &Function, argc: usize) -> Vec<Val> {
+        let mut args = Vec::with_capacity(function.num_slots);
+        args.extend(self.stack.drain(self.stack.len() - argc..));
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::env::{self, Env};
+    use crate::limits::Limits;
+    use crate::resolver;
+    use proptest::prelude::*;
+
+    // Runs `source` on the VM alone, in a frame of its own.
+    fn run_str(source: &str, limits: Limits) -> Result<Val, String> {
+        let parse = crate::parse(source).unwrap();
+        let resolution = resolver::resolve(parse.statements(), &Env::default());
+        let frame = Frame::new(resolution.num_globals, None);
+        let budget = Budget::default();
+        budget.set_limits(limits);
+        budget.start();
+        resolution
+            .stmts
+            .iter()
+            .try_fold(Val::Unit, |_, stmt| run(Rc::new(compiler::compile(stmt)), &frame, &budget))
+            .map_err(|error| error.message().to_string())
+    }
+
+    #[test]
+    fn recurse_deeper_than_the_rust_stack_allows() {
+        assert_eq!(
+            run_str(
+                "fun count n => if n == 0 then 0 else 1 + { count n - 1 }\ncount 1_000_000",
+                Limits::unlimited(),
+            ),
+            Ok(Val::Number(1_000_000)),
+        );
+    }
+
+    #[test]
+    fn limit_call_depth() {
+        assert_eq!(
+            run_str("fun f x => { f x } + 1\nf 1", Limits::default()),
+            Err("maximum call depth of 100 exceeded".to_string()),
+        );
+    }
+
+    #[test]
+    fn restore_frames_after_blocks_and_calls() {
+        assert_eq!(
+            run_str("let a = 1\nfun f x => { let a = x\na }\n{ let a = 2\n{ f 3 } + a } + a", Limits::default()),
+            Ok(Val::Number(6)),
+        );
+    }
+
+    // Sources of expressions over the definitions in `PRELUDE`; `c` is
+    // never defined.
+    fn expression() -> impl Strategy<Value = String> {
+        let leaf = prop_oneof![
+            (0..20i64).prop_map(|n| n.to_string()),
+            Just("1.5".to_string()),
+            Just("true".to_string()),
+            Just("false".to_string()),
+            Just("a".to_string()),
+            Just("b".to_string()),
+            Just("c".to_string()),
+            Just("{}".to_string()),
+        ];
+        let ops: &[&str] = &["+", "-", "*", "/", "%", "**", "&", "|", "^", "<<", "==", "!=", "<", ">="];
+        leaf.prop_recursive(4, 32, 3, move |inner| {
+            prop_oneof![
+                (inner.clone(), proptest::sample::select(ops), inner.clone())
+                    .prop_map(|(lhs, op, rhs)| format!("{{ {} }} {} {{ {} }}", lhs, op, rhs)),
+                inner.clone().prop_map(|operand| format!("-{{ {} }}", operand)),
+                inner.clone().prop_map(|operand| format!("!{{ {} }}", operand)),
+                (inner.clone(), inner.clone(), inner.clone()).prop_map(|(condition, then_branch, else_branch)| {
+                    format!("if {{ {} }} then {{ {} }} else {{ {} }}", condition, then_branch, else_branch)
+                }),
+                (inner.clone(), inner.clone()).prop_map(|(value, body)| format!("{{ let a = {}\n{} }}", value, body)),
+                inner.clone().prop_map(|arg| format!("double {{ {} }}", arg)),
+                (inner.clone(), inner.clone()).prop_map(|(x, y)| format!("min {{ {} }} {{ {} }}", x, y)),
+                inner.prop_map(|arg| format!("int {{ {} }}", arg)),
+            ]
+        })
+    }
+
+    const PRELUDE: &str = "let a = 3\nlet b = 2.5\nfun double x => x * 2\nfun min x y => if x < y then x else y";
+
+    proptest! {
+        #[test]
+        fn engines_agree(source in expression()) {
+            let _ = env::on_engines(|env| {
+                env.set_limits(Limits { fuel: Some(200), ..Limits::default() });
+                crate::parse(PRELUDE).unwrap().eval(env)?;
+                crate::parse(&source).unwrap().eval(env)
+            });
+        }
+    }
+}